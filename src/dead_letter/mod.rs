@@ -0,0 +1,216 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// How many dead letters are kept in memory for the admin endpoint
+const RECENT_CAPACITY: usize = 100;
+
+/// A chat request that no provider could serve, with enough detail to replay it
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub timestamp: u64,
+    pub request_id: Option<String>,
+    /// Masked, like everywhere else keys are logged
+    pub api_key: String,
+    pub status: u16,
+    pub error: String,
+    /// The request body as the client sent it
+    pub request: Value,
+}
+
+/// Appends failed requests to a JSONL file from a background thread, and keeps the
+/// most recent ones (since startup) in memory for inspection.
+#[derive(Debug)]
+pub struct DeadLetterLog {
+    tx: Sender<DeadLetter>,
+    redact_content: bool,
+    count: AtomicU64,
+    recent: Mutex<VecDeque<DeadLetter>>,
+}
+
+impl DeadLetterLog {
+    pub fn spawn(path: &str, redact_content: bool) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (tx, rx) = mpsc::channel::<DeadLetter>();
+
+        std::thread::spawn(move || {
+            let mut writer = BufWriter::new(file);
+            for letter in rx {
+                let result = serde_json::to_writer(&mut writer, &letter)
+                    .map_err(std::io::Error::from)
+                    .and_then(|_| writer.write_all(b"\n"))
+                    .and_then(|_| writer.flush());
+                if let Err(e) = result {
+                    error!("Failed to write dead letter: {}", e);
+                }
+            }
+        });
+
+        Ok(Self {
+            tx,
+            redact_content,
+            count: AtomicU64::new(0),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)),
+        })
+    }
+
+    pub fn record(
+        &self,
+        request_id: Option<String>,
+        api_key: String,
+        status: u16,
+        error: String,
+        mut request: Value,
+    ) {
+        if self.redact_content {
+            redact_messages(&mut request);
+        }
+
+        let letter = DeadLetter {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            request_id,
+            api_key,
+            status,
+            error,
+            request,
+        };
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut recent) = self.recent.lock() {
+            if recent.len() == RECENT_CAPACITY {
+                recent.pop_front();
+            }
+            recent.push_back(letter.clone());
+        }
+        let _ = self.tx.send(letter);
+    }
+
+    /// Total recorded since startup
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Up to `limit` of the latest dead letters, newest first
+    pub fn recent(&self, limit: usize) -> Vec<DeadLetter> {
+        self.recent
+            .lock()
+            .map(|recent| recent.iter().rev().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Replace message content so prompts never reach the file; roles and the rest of
+/// the request are kept.
+fn redact_messages(request: &mut Value) {
+    let Some(Value::Array(messages)) = request.get_mut("messages") else {
+        return;
+    };
+    for message in messages {
+        if let Some(content) = message.get_mut("content") {
+            *content = Value::String(String::from("[redacted]"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn log_file() -> String {
+        let path =
+            std::env::temp_dir().join(format!("dead-letters-{}.jsonl", uuid::Uuid::new_v4()));
+        path.to_str().unwrap().to_string()
+    }
+
+    /// The file's lines, once the writer thread has produced `count`.
+    fn read_lines(path: &str, count: usize) -> Vec<Value> {
+        for _ in 0..100 {
+            let raw = std::fs::read_to_string(path).unwrap_or_default();
+            let lines: Vec<Value> = raw
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect();
+            if lines.len() >= count {
+                return lines;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("dead-letter file never got {} lines", count);
+    }
+
+    fn body(content: &str) -> Value {
+        serde_json::json!({"model": "llama3.2", "messages": [{"role": "user", "content": content}]})
+    }
+
+    #[test]
+    fn failed_request_is_written_with_its_body() {
+        let path = log_file();
+        let log = DeadLetterLog::spawn(&path, false).unwrap();
+        log.record(
+            Some(String::from("req-1")),
+            String::from("sk-t***1234"),
+            503,
+            String::from("upstream down"),
+            body("Hi"),
+        );
+
+        let lines = read_lines(&path, 1);
+        let _ = std::fs::remove_file(&path);
+        let line = &lines[0];
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["api_key"], "sk-t***1234");
+        assert_eq!(line["status"], 503);
+        assert_eq!(line["error"], "upstream down");
+        assert_eq!(line["request"], body("Hi"));
+        assert!(line["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn redaction_keeps_roles_but_drops_content() {
+        let path = log_file();
+        let log = DeadLetterLog::spawn(&path, true).unwrap();
+        log.record(
+            None,
+            String::new(),
+            502,
+            String::from("down"),
+            body("secret"),
+        );
+
+        let lines = read_lines(&path, 1);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            lines[0]["request"]["messages"][0],
+            serde_json::json!({"role": "user", "content": "[redacted]"})
+        );
+        assert_eq!(
+            log.recent(1)[0].request["messages"][0]["content"],
+            "[redacted]"
+        );
+    }
+
+    #[test]
+    fn recent_letters_come_newest_first() {
+        let path = log_file();
+        let log = DeadLetterLog::spawn(&path, false).unwrap();
+        for status in [500, 502, 503] {
+            log.record(None, String::new(), status, String::new(), body("Hi"));
+        }
+        assert_eq!(log.count(), 3);
+        let statuses: Vec<u16> = log.recent(2).iter().map(|l| l.status).collect();
+        assert_eq!(statuses, [503, 502]);
+        read_lines(&path, 3);
+        let _ = std::fs::remove_file(&path);
+    }
+}
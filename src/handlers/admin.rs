@@ -0,0 +1,264 @@
+use actix_web::{web, HttpRequest, HttpResponse, HttpMessage};
+use crate::dead_letter::{DeadLetter, DeadLetterLog};
+use crate::middleware::auth::{mask_key, ApiKeyRole, ValidatedApiKey};
+use crate::models::ApiError;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::warn;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_SHA: &str = env!("GATEWAY_GIT_SHA");
+pub const BUILD_TIMESTAMP: &str = env!("GATEWAY_BUILD_TIMESTAMP");
+
+/// Settings shared by admin-only handlers.
+#[derive(Debug, Clone, Default)]
+pub struct AdminConfig {
+    /// Require `X-Admin-Intent: true` on mutating admin endpoints (`REQUIRE_ADMIN_INTENT`)
+    pub require_intent: bool,
+    /// Wakes the shutdown task; `None` unless `ENABLE_ADMIN_SHUTDOWN` is set
+    pub shutdown: Option<Arc<Notify>>,
+    /// Where stats are saved (`STATS_FILE`), so a reset persists right away
+    pub stats_file: String,
+}
+
+/// Rejects a mutating admin call that doesn't carry `X-Admin-Intent: true`, when
+/// that's required. Guards against destructive calls from misrouted traffic.
+pub(super) fn check_admin_intent(req: &HttpRequest, config: &AdminConfig) -> Option<HttpResponse> {
+    if !config.require_intent {
+        return None;
+    }
+
+    let has_intent = req
+        .headers()
+        .get("X-Admin-Intent")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("true"));
+
+    if has_intent {
+        None
+    } else {
+        Some(HttpResponse::BadRequest().json(ApiError::admin_intent_required()))
+    }
+}
+
+#[derive(Serialize)]
+pub struct BuildInfoResponse {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub build_timestamp: &'static str,
+}
+
+pub async fn admin_info(req: HttpRequest) -> HttpResponse {
+    let validated_key = req.extensions().get::<ValidatedApiKey>().cloned();
+
+    let Some(validated) = validated_key else {
+        return HttpResponse::Unauthorized().body("Missing API key context");
+    };
+
+    if !matches!(validated.role, ApiKeyRole::Admin) {
+        return HttpResponse::Forbidden().body("Admin API key required");
+    }
+
+    HttpResponse::Ok().json(BuildInfoResponse {
+        version: VERSION,
+        git_sha: GIT_SHA,
+        build_timestamp: BUILD_TIMESTAMP,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct DeadLettersQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct DeadLettersResponse {
+    pub count: u64,
+    pub recent: Vec<DeadLetter>,
+}
+
+/// Recent requests that no provider could serve, newest first (`?limit=`, default 20).
+pub async fn dead_letters(
+    req: HttpRequest,
+    log: web::Data<Option<Arc<DeadLetterLog>>>,
+    query: web::Query<DeadLettersQuery>,
+) -> HttpResponse {
+    let validated_key = req.extensions().get::<ValidatedApiKey>().cloned();
+
+    let Some(validated) = validated_key else {
+        return HttpResponse::Unauthorized().body("Missing API key context");
+    };
+
+    if !matches!(validated.role, ApiKeyRole::Admin) {
+        return HttpResponse::Forbidden().body("Admin API key required");
+    }
+
+    let Some(log) = log.get_ref() else {
+        return HttpResponse::NotFound().body("Dead-letter log is not enabled (set DEAD_LETTER_FILE)");
+    };
+
+    HttpResponse::Ok().json(DeadLettersResponse {
+        count: log.count(),
+        recent: log.recent(query.limit.unwrap_or(20)),
+    })
+}
+
+/// Stops the server the way SIGTERM does: in-flight requests drain, then stats are saved.
+pub async fn shutdown(req: HttpRequest, config: web::Data<AdminConfig>) -> HttpResponse {
+    let validated_key = req.extensions().get::<ValidatedApiKey>().cloned();
+
+    let Some(validated) = validated_key else {
+        return HttpResponse::Unauthorized().body("Missing API key context");
+    };
+
+    if !matches!(validated.role, ApiKeyRole::Admin) {
+        return HttpResponse::Forbidden().body("Admin API key required");
+    }
+
+    let Some(trigger) = &config.shutdown else {
+        return HttpResponse::NotFound().body("Admin shutdown is not enabled (set ENABLE_ADMIN_SHUTDOWN)");
+    };
+
+    if let Some(response) = check_admin_intent(&req, &config) {
+        return response;
+    }
+
+    warn!("Shutdown requested through the admin API by {}", mask_key(&validated.key));
+    // Stored if the shutdown task isn't waiting yet, so the request is never lost
+    trigger.notify_one();
+    HttpResponse::Accepted().json(serde_json::json!({ "status": "shutting_down" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+    use crate::handlers::keys::AddKeyRequest;
+    use crate::handlers::stats::StatsQuery;
+    use crate::handlers::{add_key, reset_stats};
+    use crate::middleware::auth::KeySet;
+    use crate::tracking::RequestTracker;
+    use std::sync::RwLock;
+
+    fn request_as(role: Option<ApiKeyRole>, req: TestRequest) -> HttpRequest {
+        let req = req.to_http_request();
+        if let Some(role) = role {
+            req.extensions_mut().insert(ValidatedApiKey { key: String::from("test-key"), role, allowed_models: None });
+        }
+        req
+    }
+
+    async fn json_body(res: HttpResponse) -> serde_json::Value {
+        serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap()
+    }
+
+    fn intent_config() -> AdminConfig {
+        AdminConfig { require_intent: true, ..AdminConfig::default() }
+    }
+
+    #[actix_web::test]
+    async fn missing_intent_is_a_json_400() {
+        let req = TestRequest::post().to_http_request();
+        let res = check_admin_intent(&req, &intent_config()).expect("rejected");
+        assert_eq!(res.status(), 400);
+        let body = json_body(res).await;
+        assert_eq!(body["error"]["code"], "admin_intent_required");
+        assert_eq!(body["error"]["param"], "X-Admin-Intent");
+    }
+
+    #[test]
+    fn intent_header_is_accepted_in_any_case() {
+        for value in ["true", "TRUE", " True "] {
+            let req = TestRequest::post().insert_header(("X-Admin-Intent", value)).to_http_request();
+            assert!(check_admin_intent(&req, &intent_config()).is_none(), "{:?}", value);
+        }
+        let req = TestRequest::post().insert_header(("X-Admin-Intent", "yes")).to_http_request();
+        assert!(check_admin_intent(&req, &intent_config()).is_some());
+    }
+
+    #[test]
+    fn intent_is_only_checked_when_required() {
+        let req = TestRequest::post().to_http_request();
+        assert!(check_admin_intent(&req, &AdminConfig::default()).is_none());
+    }
+
+    fn with_intent(req: TestRequest) -> TestRequest {
+        req.insert_header(("X-Admin-Intent", "true"))
+    }
+
+    #[actix_web::test]
+    async fn reset_stats_needs_intent_when_required() {
+        let stats_file = std::env::temp_dir().join(format!("gateway-stats-{}.json", uuid::Uuid::new_v4()));
+        let config = web::Data::new(AdminConfig { stats_file: stats_file.to_string_lossy().into_owned(), ..intent_config() });
+        let tracker = web::Data::new(RwLock::new(RequestTracker::new()));
+        tracker.write().unwrap().record_rate_limited("user-key");
+        let query = || web::Query(StatsQuery { key: None, unmask: false });
+
+        let req = request_as(Some(ApiKeyRole::Admin), TestRequest::delete());
+        let res = reset_stats(req, query(), tracker.clone(), config.clone()).await;
+        assert_eq!(res.status(), 400);
+        assert_eq!(json_body(res).await["error"]["code"], "admin_intent_required");
+        assert!(tracker.read().unwrap().get_stats("user-key").is_some());
+
+        let req = request_as(Some(ApiKeyRole::Admin), with_intent(TestRequest::delete()));
+        assert_eq!(reset_stats(req, query(), tracker.clone(), config).await.status(), 204);
+        assert!(tracker.read().unwrap().get_stats("user-key").is_none());
+        let _ = std::fs::remove_file(stats_file);
+    }
+
+    #[actix_web::test]
+    async fn add_key_needs_intent_when_required() {
+        let config = web::Data::new(intent_config());
+        let keys = web::Data::new(RwLock::new(KeySet::default()));
+        let body = || web::Json(AddKeyRequest { key: Some(String::from("new-key")), role: None });
+
+        let req = request_as(Some(ApiKeyRole::Admin), TestRequest::post());
+        let res = add_key(req, keys.clone(), config.clone(), body()).await;
+        assert_eq!(res.status(), 400);
+        assert_eq!(json_body(res).await["error"]["param"], "X-Admin-Intent");
+        assert!(keys.read().unwrap().role_of("new-key").is_none());
+
+        let req = request_as(Some(ApiKeyRole::Admin), with_intent(TestRequest::post()));
+        assert_eq!(add_key(req, keys.clone(), config, body()).await.status(), 201);
+        assert_eq!(keys.read().unwrap().role_of("new-key"), Some(ApiKeyRole::User));
+    }
+
+    #[actix_web::test]
+    async fn shutdown_needs_intent_when_required() {
+        let trigger = Arc::new(Notify::new());
+        let config = web::Data::new(AdminConfig { shutdown: Some(trigger.clone()), ..intent_config() });
+
+        let res = shutdown(request_as(Some(ApiKeyRole::Admin), TestRequest::post()), config.clone()).await;
+        assert_eq!(res.status(), 400);
+        let res = shutdown(request_as(Some(ApiKeyRole::Admin), with_intent(TestRequest::post())), config).await;
+        assert_eq!(res.status(), 202);
+        // The stored permit shows the shutdown task was woken
+        tokio::time::timeout(std::time::Duration::from_secs(1), trigger.notified()).await.expect("notified");
+    }
+
+    #[actix_web::test]
+    async fn info_needs_no_intent() {
+        // Read-only, so REQUIRE_ADMIN_INTENT doesn't apply
+        let res = admin_info(request_as(Some(ApiKeyRole::Admin), TestRequest::get())).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn info_reports_the_build_to_admins() {
+        let res = admin_info(request_as(Some(ApiKeyRole::Admin), TestRequest::get())).await;
+        assert_eq!(res.status(), 200);
+        let body = json_body(res).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["git_sha"], GIT_SHA);
+        assert!(!body["git_sha"].as_str().unwrap().is_empty());
+        assert!(!body["build_timestamp"].as_str().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn info_is_admin_only() {
+        assert_eq!(admin_info(request_as(Some(ApiKeyRole::User), TestRequest::get())).await.status(), 403);
+        assert_eq!(admin_info(request_as(None, TestRequest::get())).await.status(), 401);
+    }
+}
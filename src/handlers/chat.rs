@@ -1,21 +1,201 @@
 use actix_web::{web, HttpResponse, HttpRequest, HttpMessage};
-use crate::models::{ChatCompletionRequest};
-use crate::providers::{LLMProvider, ProviderError};
-use crate::tracking::RequestTracker;
-use crate::middleware::auth::ValidatedApiKey;
-use tracing::{info, error};
-use std::sync::RwLock;
+use actix_web::http::header::ContentEncoding;
+use crate::dead_letter::DeadLetterLog;
+use crate::models::{
+    ApiError, ApiErrorBody, ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, Choice, ChunkChoice, Delta,
+    Message, Usage,
+};
+use crate::providers::{sse_events, LLMProvider, ProviderError, StreamUsage};
+use crate::sessions::{SessionStore, SESSION_HEADER};
+use crate::tracking::{until_next_month, RequestTracker};
+use crate::middleware::auth::{mask_key, ApiKeyRole, Scope, ValidatedApiKey};
+use crate::middleware::request_id::RequestId;
+use crate::middleware::tracking::{RecordAsError, RequestModel, RequestUser};
+use crate::spans::{SpanContext, StreamSpan};
+use crate::transform::{RequestTransformer, ResponseTransformer};
+use super::health::HealthBackends;
+use tracing::{info, warn, error};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use futures::StreamExt;
+use bytes::Bytes;
+use uuid::Uuid;
+
+/// Handler-level settings for `/v1/chat/completions`.
+#[derive(Debug, Clone, Default)]
+pub struct ChatConfig {
+    /// When set, returned as a regular completion (with `finish_reason: "error"`)
+    /// instead of an HTTP error when every provider fails.
+    pub canned_fallback: Option<String>,
+    /// Upstream timeout overrides per model (e.g. longer for 70B models)
+    pub model_timeouts: HashMap<String, Duration>,
+    /// Applied in order to successful responses and stream chunks
+    pub transformers: Vec<Arc<dyn ResponseTransformer>>,
+    /// Applied in order to every request before it's sent upstream
+    pub request_transformers: Vec<Arc<dyn RequestTransformer>>,
+    /// Merge consecutive same-role messages for templates that require strict
+    /// user/assistant alternation (`REPAIR_ALTERNATION`)
+    pub repair_alternation: bool,
+    /// Limits on `tools`, to keep huge schemas away from upstreams
+    pub max_tools: usize,
+    pub max_tools_bytes: usize,
+    /// Upper bound on `n`, since each completion may be its own upstream call (`MAX_N`)
+    pub max_n: u32,
+    /// Where requests that no provider could serve are recorded (`DEAD_LETTER_FILE`)
+    pub dead_letters: Option<Arc<DeadLetterLog>>,
+    /// Conversation history kept for clients that send `X-Session-Id` (`SESSIONS_ENABLED`)
+    pub sessions: Option<Arc<SessionStore>>,
+    /// Friendly names for upstream models, e.g. `fast` -> `llama3.2` (`MODEL_ALIASES`)
+    pub model_aliases: HashMap<String, String>,
+    /// Used when a request has no model (`DEFAULT_MODEL`)
+    pub default_model: Option<String>,
+    /// Tokens each key may use per calendar month (`KEY_MONTHLY_TOKEN_QUOTAS`)
+    pub monthly_token_quotas: HashMap<String, u64>,
+}
+
+/// Tokens left in the key's monthly quota, on responses to keys that have one
+const QUOTA_REMAINING_HEADER: &str = "x-quota-remaining";
+
+/// Names one upstream (as listed by `/v1/health`) to send the request to directly
+const PROVIDER_HEADER: &str = "x-provider";
+
+impl ChatConfig {
+    /// The upstream model for what the client asked for: `DEFAULT_MODEL` when it's
+    /// blank, then the alias target if there is one. Anything else passes through.
+    pub(super) fn resolve_model(&self, model: &str) -> String {
+        let model = match (model.trim(), &self.default_model) {
+            ("", Some(default)) => default.as_str(),
+            (model, _) => model,
+        };
+        self.model_aliases
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| model.to_string())
+    }
+
+    /// Exact model match first, then the name without its `:tag`
+    /// (so `llama3.2` also covers `llama3.2:3b`).
+    fn timeout_for(&self, model: &str) -> Option<Duration> {
+        self.model_timeouts.get(model).copied().or_else(|| {
+            model
+                .split_once(':')
+                .and_then(|(base, _)| self.model_timeouts.get(base).copied())
+        })
+    }
+}
 
 pub async fn chat_completions(
+    req: HttpRequest,
+    provider: web::Data<dyn LLMProvider>,
+    backends: web::Data<HealthBackends>,
+    request_tracker: web::Data<RwLock<RequestTracker>>,
+    config: web::Data<ChatConfig>,
+    body: web::Json<ChatCompletionRequest>,
+) -> HttpResponse {
+    let role = req.extensions().get::<ValidatedApiKey>().map(|key| key.role);
+    if let Some(role) = role.filter(|role| !role.allows(Scope::Chat)) {
+        warn!("Rejected chat request from a '{}' key", role.name());
+        return HttpResponse::Forbidden().json(ApiError::insufficient_scope(role.name(), "call chat completions"));
+    }
+    let mut provider = provider;
+    if let Some(rejection) = pin_provider(&req, role, &mut provider, &backends) {
+        return rejection;
+    }
+
+    let quota = req
+        .extensions()
+        .get::<ValidatedApiKey>()
+        .and_then(|key| Some((key.key.clone(), *config.monthly_token_quotas.get(&key.key)?)));
+    let Some((api_key, quota)) = quota else {
+        return complete(req, provider, request_tracker, config, body).await;
+    };
+
+    // A request that starts within the quota may still take it past the limit
+    let used = request_tracker.read().unwrap().tokens_this_month(&api_key);
+    if used >= quota {
+        warn!(api_key = %mask_key(&api_key), "Rejected chat request, monthly quota of {} tokens used up", quota);
+        return HttpResponse::TooManyRequests()
+            .insert_header((QUOTA_REMAINING_HEADER, 0))
+            .insert_header((actix_web::http::header::RETRY_AFTER, until_next_month().as_secs()))
+            .json(ApiError::quota_exceeded(quota));
+    }
+
+    let mut response = complete(req, provider, request_tracker.clone(), config, body).await;
+    // Streams record their usage when they end, so theirs shows what was left beforehand
+    let remaining = quota.saturating_sub(request_tracker.read().unwrap().tokens_this_month(&api_key));
+    response.headers_mut().insert(
+        actix_web::http::header::HeaderName::from_static(QUOTA_REMAINING_HEADER),
+        remaining.into(),
+    );
+    response
+}
+
+async fn complete(
     req: HttpRequest,
     provider: web::Data<dyn LLMProvider>,
     request_tracker: web::Data<RwLock<RequestTracker>>,
+    config: web::Data<ChatConfig>,
     body: web::Json<ChatCompletionRequest>,
 ) -> HttpResponse {
-    let request = body.into_inner();
+    let mut request = body.into_inner();
+    // Resolved first so stats, routing and the response all use the real model name
+    request.model = config.resolve_model(&request.model);
+    // With a session the client sends only the new turn; the stored history goes first
+    let session = session_for(&req, &config);
+    let mut new_messages = Vec::new();
+    if let Some((store, api_key, id)) = &session {
+        new_messages = request.messages.clone();
+        let mut messages = store.history(api_key, id);
+        messages.append(&mut request.messages);
+        request.messages = messages;
+    }
+    // Validated with its history, which is what goes upstream
+    if let Err(e) = validate_request(&request, &config) {
+        warn!("Rejected chat request: {}", e.error.message);
+        return HttpResponse::BadRequest().json(e);
+    }
+    let allowed = req
+        .extensions()
+        .get::<ValidatedApiKey>()
+        .is_none_or(|key| key.may_use_model(&request.model));
+    if !allowed {
+        warn!("Rejected chat request for model '{}' outside the key's allowlist", request.model);
+        return HttpResponse::Forbidden().json(ApiError::model_not_allowed(&request.model));
+    }
+    req.extensions_mut().insert(RequestModel(request.model.clone()));
+    let user = request.user.clone().filter(|user| !user.is_empty());
+    if let Some(user) = &user {
+        req.extensions_mut().insert(RequestUser(user.clone()));
+        if let Some(span) = req.extensions().get::<SpanContext>() {
+            span.set_user(user);
+        }
+    }
+    // The resolved request, with the model alias and session history applied, so a replay
+    // needs no session; captured before the transformers and role repair
+    let dead_letter_body = config
+        .dead_letters
+        .as_ref()
+        .and_then(|_| serde_json::to_value(&request).ok());
+    for transformer in config.request_transformers.iter() {
+        transformer.transform(&mut request);
+    }
+    if config.repair_alternation {
+        request.messages = merge_consecutive_roles(std::mem::take(&mut request.messages));
+    }
+    request.timeout = config.timeout_for(&request.model);
+    request.request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    let requested_model = request.model.clone();
+    let span = req.extensions().get::<SpanContext>().cloned();
+    let provider_started = Instant::now();
 
     let is_streaming = request.stream.unwrap_or(false);
+    // Clients that prefer one JSON object per line over SSE framing
+    let ndjson = req
+        .headers()
+        .get(actix_web::http::header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/x-ndjson"));
 
     if is_streaming {
         info!("Streaming request received");
@@ -25,61 +205,92 @@ pub async fn chat_completions(
             .map(|k| k.key.clone())
             .unwrap_or_else(|| "unknown".to_string());
             
-        match provider.chat_stream(request).await {
+        let stream_usage = provider.stream_usage(&request.model);
+        let result = provider.chat_stream(request).await;
+        if let Some(span) = &span {
+            span.record("provider", provider_started);
+        }
+
+        match result {
             Ok(stream) => {
-                let tracker_for_closure = request_tracker.clone(); // Clone the Arc<RwLock<RequestTracker>>
-
-                let stream = stream.map(move |result| {
-                    if let Ok(bytes) = &result {
-                        
-                        let s = String::from_utf8_lossy(bytes);
-                        if s.starts_with("data: ") && !s.contains("[DONE]") {
-                             let json_str = s.trim_start_matches("data: ").trim();
-                             if let Ok(chunk) = serde_json::from_str::<crate::models::ChatCompletionChunk>(json_str) {
-                                 if let Some(usage) = chunk.usage {
-                                     let prompt_tokens = usage.prompt_tokens as u64;
-                                     let completion_tokens = usage.completion_tokens as u64;
-                                     let model = &chunk.model;
-                                     
-                                     if let Ok(mut t) = tracker_for_closure.write() {
-                                         t.record_tokens(&api_key, prompt_tokens, completion_tokens, model);
-                                          info!("Recorded streaming tokens: {}p + {}c for {}", prompt_tokens, completion_tokens, api_key);
-                                     } else {
-                                         error!("Failed to acquire write lock on RequestTracker for streaming usage");
-                                     }
-                                 }
-                             }
-                        }
-                    }
-                    result.map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
-                });
+                let tap = StreamTap {
+                    // Usage is recorded once, when the stream ends
+                    usage: StreamUsageRecorder {
+                        tracker: request_tracker.clone(),
+                        api_key,
+                        user,
+                        mode: stream_usage,
+                        totals: None,
+                    },
+                    session: session.map(|(store, api_key, id)| StreamSessionRecorder {
+                        store,
+                        api_key,
+                        id,
+                        turn: new_messages,
+                        reply: String::new(),
+                        finished: false,
+                    }),
+                    span: span.map(StreamSpan::new),
+                    transformers: config.transformers.clone(),
+                };
 
-                HttpResponse::Ok()
-                .content_type("text/event-stream")
-                .streaming(stream)
+                streaming_response(tap_stream(stream, tap), ndjson)
+            }
+            Err(e) => {
+                record_dead_letter(&req, &config, dead_letter_body, &e);
+                match &config.canned_fallback {
+                    Some(message) => {
+                        warn!("All providers failed ({}), returning canned stream", e);
+                        req.extensions_mut().insert(RecordAsError);
+                        canned_stream_response(&requested_model, message, ndjson)
+                    }
+                    None => error_to_response(e),
+                }
             }
-            Err(e) => error_to_response(e),
         }
     } else {
         info!("Non-streaming request received");
 
-        match provider.chat(request).await {
-            Ok(response) => {
+        let result = provider.chat(request).await;
+        if let Some(span) = &span {
+            span.record("provider", provider_started);
+        }
+
+        match result {
+            Ok(mut response) => {
+                // The reply as the model wrote it, before any transformer touches it
+                if let (Some((store, api_key, id)), Some(choice)) = (&session, response.choices.first()) {
+                    let mut turn = new_messages;
+                    turn.push(choice.message.clone());
+                    store.append(api_key, id, turn);
+                }
+
+                for transformer in config.transformers.iter() {
+                    transformer.transform(&mut response);
+                }
+
                 // Record token usage
                 if let Some(extensions) = req.extensions().get::<ValidatedApiKey>() {
                     let api_key = &extensions.key;
-                    let prompt_tokens = response.usage.prompt_tokens as u64;
-                    let completion_tokens = response.usage.completion_tokens as u64;
+                    // Responses without usage are recorded as zero tokens
+                    let usage = response.usage.clone().unwrap_or_default();
+                    let prompt_tokens = usage.prompt_tokens as u64;
+                    let completion_tokens = usage.completion_tokens as u64;
                     let model = response.model.clone();
 
                     // Acquire write lock and record
                     if let Ok(mut tracker) = request_tracker.write() {
-                        tracker.record_tokens(api_key, prompt_tokens, completion_tokens, &model);
+                        tracker.record_tokens(api_key, prompt_tokens, completion_tokens, &model, user.as_deref());
+                        if response.cached {
+                            tracker.record_cache_hit(api_key);
+                        }
                         info!(
-                            api_key = %api_key,
+                            api_key = %mask_key(api_key),
+                            user = user.as_deref(),
                             prompt_tokens = prompt_tokens,
                             completion_tokens = completion_tokens,
                             model = %model,
+                            cached = response.cached,
                             "Recorded tokens"
                         );
                     } else {
@@ -91,12 +302,366 @@ pub async fn chat_completions(
 
                 HttpResponse::Ok().json(response)
             },
-            Err(e) => error_to_response(e),
+            Err(e) => {
+                record_dead_letter(&req, &config, dead_letter_body, &e);
+                match &config.canned_fallback {
+                    Some(message) => {
+                        warn!("All providers failed ({}), returning canned response", e);
+                        req.extensions_mut().insert(RecordAsError);
+                        HttpResponse::Ok().json(canned_response(&requested_model, message))
+                    }
+                    None => error_to_response(e),
+                }
+            }
+        }
+    }
+}
+
+/// Checks a request against the configured limits before it goes upstream, reporting
+/// every violation at once.
+fn validate_request(request: &ChatCompletionRequest, config: &ChatConfig) -> Result<(), ApiError> {
+    let mut errors = request.validation_errors();
+    if let Some(n) = request.n.filter(|n| *n > config.max_n) {
+        errors.push(ApiErrorBody::invalid_request(format!("n is {} (max {})", n, config.max_n), "n"));
+    }
+    if let Some(tools) = &request.tools {
+        if tools.len() > config.max_tools {
+            errors.push(ApiErrorBody::invalid_request(format!("Too many tools: {} (max {})", tools.len(), config.max_tools), "tools"));
+        }
+        let size = serde_json::to_vec(tools).map(|b| b.len()).unwrap_or(usize::MAX);
+        if size > config.max_tools_bytes {
+            errors.push(ApiErrorBody::invalid_request(format!("Tools definition too large: {} bytes (max {})", size, config.max_tools_bytes), "tools"));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::invalid_fields(errors))
+    }
+}
+
+/// Hands a terminal failure (network, parse or upstream 5xx) to the dead-letter log.
+/// Upstream 4xx are the client's problem and wouldn't succeed on replay.
+fn record_dead_letter(req: &HttpRequest, config: &ChatConfig, body: Option<serde_json::Value>, err: &ProviderError) {
+    let (Some(log), Some(body)) = (&config.dead_letters, body) else {
+        return;
+    };
+    let status = match err {
+        ProviderError::Network(_) => 502,
+        ProviderError::Parse(_) => 500,
+        ProviderError::ProviderError { status, .. } => *status,
+    };
+    if status < 500 {
+        return;
+    }
+
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    let api_key = req.extensions()
+        .get::<ValidatedApiKey>()
+        .map(|k| mask_key(&k.key))
+        .unwrap_or_default();
+    warn!(status, "Recording dead letter: {}", err);
+    log.record(request_id, api_key, status, err.to_string(), body);
+}
+
+/// Swaps in the upstream an admin named in `X-Provider`, or rejects the request.
+/// A pinned request skips fallback, routing and the response cache, which is the point
+/// when reproducing a bug, but still waits for a gateway slot (`MAX_CONCURRENT_REQUESTS`).
+fn pin_provider(
+    req: &HttpRequest,
+    role: Option<ApiKeyRole>,
+    provider: &mut web::Data<dyn LLMProvider>,
+    backends: &HealthBackends,
+) -> Option<HttpResponse> {
+    let header = req.headers().get(PROVIDER_HEADER)?;
+    // Otherwise anyone could pin themselves to the most expensive upstream
+    if role != Some(ApiKeyRole::Admin) {
+        let role = role.map_or("none", ApiKeyRole::name);
+        return Some(HttpResponse::Forbidden().json(ApiError::insufficient_scope(role, "choose a provider with X-Provider")));
+    }
+
+    let name = header.to_str().unwrap_or_default().trim();
+    match backends.0.iter().find(|(backend, _)| backend == name) {
+        Some((_, backend)) => {
+            info!("Request pinned to provider '{}'", name);
+            *provider = web::Data::from(backend.clone());
+            None
+        }
+        None => {
+            let known: Vec<&str> = backends.0.iter().map(|(backend, _)| backend.as_str()).collect();
+            let message = format!("Unknown provider '{}' (expected one of {})", name, known.join(", "));
+            Some(HttpResponse::BadRequest().json(ApiError::invalid_fields(vec![ApiErrorBody::invalid_request(message, "X-Provider")])))
+        }
+    }
+}
+
+/// Collapse runs of user or assistant messages into one, joining their content with a
+/// blank line. System and tool messages, and anything carrying tool calls, are never merged.
+fn merge_consecutive_roles(messages: Vec<Message>) -> Vec<Message> {
+    let mut merged: Vec<Message> = Vec::with_capacity(messages.len());
+    for message in messages {
+        match merged.last_mut() {
+            Some(last) if last.role == message.role && is_mergeable(last) && is_mergeable(&message) => {
+                last.content.push_str("\n\n");
+                last.content.append(message.content);
+            }
+            _ => merged.push(message),
+        }
+    }
+    merged
+}
+
+fn is_mergeable(message: &Message) -> bool {
+    matches!(message.role.as_str(), "user" | "assistant") && message.tool_calls.is_none()
+}
+
+/// Everything that looks at a streamed reply on its way to the client: usage, the
+/// session's copy of the reply, the first-chunk span mark and response transformers.
+struct StreamTap {
+    usage: StreamUsageRecorder,
+    session: Option<StreamSessionRecorder>,
+    span: Option<StreamSpan>,
+    transformers: Vec<Arc<dyn ResponseTransformer>>,
+}
+
+impl StreamTap {
+    /// Takes one whole SSE event, returning what to send in its place.
+    fn event(&mut self, event: Bytes) -> Bytes {
+        // Keep-alive comments aren't output, so they don't count as the first chunk
+        if let Some(span) = &mut self.span {
+            if !event.starts_with(b":") {
+                span.on_chunk();
+            }
+        }
+
+        let Some(data) = sse_data(&String::from_utf8_lossy(&event)) else {
+            return event;
+        };
+        let Ok(mut chunk) = serde_json::from_str::<ChatCompletionChunk>(&data) else {
+            return event;
+        };
+        if let Some(usage) = &chunk.usage {
+            self.usage.observe(usage, &chunk.model);
+        }
+        if let Some(session) = &mut self.session {
+            session.observe(&chunk);
+        }
+
+        // Only re-serialize when something may have changed the chunk
+        if self.transformers.is_empty() {
+            return event;
+        }
+        for transformer in self.transformers.iter() {
+            transformer.transform_chunk(&mut chunk);
+        }
+        match serde_json::to_string(&chunk) {
+            Ok(json) => Bytes::from(format!("data: {}\n\n", json)),
+            Err(_) => event,
+        }
+    }
+}
+
+/// Upstream reads don't line up with events, so they're split into whole events
+/// before `tap` sees them.
+fn tap_stream<S>(stream: S, mut tap: StreamTap) -> impl futures::Stream<Item = Result<Bytes, actix_web::Error>>
+where
+    S: futures::Stream<Item = Result<Bytes, ProviderError>>,
+{
+    sse_events(stream).map(move |result| {
+        result
+            .map(|event| tap.event(event))
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+    })
+}
+
+/// Accumulates usage reported across a stream and records it in the tracker when
+/// dropped (stream finished or client went away).
+struct StreamUsageRecorder {
+    tracker: web::Data<RwLock<RequestTracker>>,
+    api_key: String,
+    user: Option<String>,
+    mode: StreamUsage,
+    /// (prompt_tokens, completion_tokens, model)
+    totals: Option<(u64, u64, String)>,
+}
+
+impl StreamUsageRecorder {
+    fn observe(&mut self, usage: &Usage, model: &str) {
+        let prompt = usage.prompt_tokens as u64;
+        let completion = usage.completion_tokens as u64;
+        self.totals = match (self.mode, self.totals.take()) {
+            (StreamUsage::Incremental, Some((p, c, _))) => Some((p + prompt, c + completion, model.to_string())),
+            // Cumulative: the latest usage seen wins, even if a later chunk omits it
+            _ => Some((prompt, completion, model.to_string())),
+        };
+    }
+}
+
+impl Drop for StreamUsageRecorder {
+    fn drop(&mut self) {
+        let Some((prompt_tokens, completion_tokens, model)) = self.totals.take() else {
+            return;
+        };
+
+        if let Ok(mut t) = self.tracker.write() {
+            t.record_tokens(&self.api_key, prompt_tokens, completion_tokens, &model, self.user.as_deref());
+            info!("Recorded streaming tokens: {}p + {}c for {}", prompt_tokens, completion_tokens, mask_key(&self.api_key));
+        } else {
+            error!("Failed to acquire write lock on RequestTracker for streaming usage");
+        }
+    }
+}
+
+/// The session store, caller's key and session ID when the request continues a session.
+fn session_for(req: &HttpRequest, config: &ChatConfig) -> Option<(Arc<SessionStore>, String, String)> {
+    let store = config.sessions.clone()?;
+    let id = req.headers().get(SESSION_HEADER)?.to_str().ok()?.trim();
+    if id.is_empty() {
+        return None;
+    }
+    let api_key = req.extensions().get::<ValidatedApiKey>()?.key.clone();
+    Some((store, api_key, id.to_string()))
+}
+
+/// Collects a streamed reply and adds the turn to its session when the stream ends,
+/// as long as the reply finished. Only text content is kept.
+struct StreamSessionRecorder {
+    store: Arc<SessionStore>,
+    api_key: String,
+    id: String,
+    /// The client's new messages
+    turn: Vec<Message>,
+    reply: String,
+    finished: bool,
+}
+
+impl StreamSessionRecorder {
+    fn observe(&mut self, chunk: &ChatCompletionChunk) {
+        for choice in chunk.choices.iter().filter(|c| c.index == 0) {
+            self.reply.push_str(&choice.delta.content);
+            self.finished |= choice.finish_reason.is_some();
+        }
+    }
+}
+
+impl Drop for StreamSessionRecorder {
+    fn drop(&mut self) {
+        if !self.finished {
+            return;
+        }
+        let mut turn = std::mem::take(&mut self.turn);
+        turn.push(Message {
+            role: String::from("assistant"),
+            content: std::mem::take(&mut self.reply).into(),
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        self.store.append(&self.api_key, &self.id, turn);
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Degraded-mode completion returned when no provider could serve the request.
+fn canned_response(model: &str, content: &str) -> ChatCompletionResponse {
+    ChatCompletionResponse {
+        id: format!("chatcmpl-{}", Uuid::new_v4()),
+        object: String::from("chat.completion"),
+        created: unix_now(),
+        model: model.to_string(),
+        choices: vec![Choice {
+            index: 0,
+            message: Message {
+                role: String::from("assistant"),
+                content: content.to_string().into(),
+                tool_calls: None,
+                tool_call_id: None,
+            },
+            finish_reason: String::from("error"),
+        }],
+        usage: Some(Usage::default()),
+        cached: false,
+    }
+}
+
+/// Streaming equivalent of `canned_response`: a single chunk followed by `[DONE]`.
+fn canned_stream_response(model: &str, content: &str, ndjson: bool) -> HttpResponse {
+    let chunk = ChatCompletionChunk {
+        id: format!("chatcmpl-{}", Uuid::new_v4()),
+        object: String::from("chat.completion.chunk"),
+        created: unix_now(),
+        model: model.to_string(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta: Delta {
+                role: Some(String::from("assistant")),
+                content: content.to_string(),
+                tool_calls: None,
+            },
+            finish_reason: Some(String::from("error")),
+        }],
+        usage: None,
+    };
+
+    let body = format!(
+        "data: {}\n\ndata: [DONE]\n\n",
+        serde_json::to_string(&chunk).unwrap()
+    );
+    let stream = futures::stream::once(async move { Ok::<_, actix_web::Error>(Bytes::from(body)) });
+
+    streaming_response(stream, ndjson)
+}
+
+/// Sends the SSE stream as-is, or re-framed as NDJSON: one chunk object per line,
+/// no `[DONE]` (the end of the body marks completion). Streams are never compressed,
+/// since the encoder would hold chunks back until it had enough to emit.
+fn streaming_response<S>(stream: S, ndjson: bool) -> HttpResponse
+where
+    S: futures::Stream<Item = Result<Bytes, actix_web::Error>> + 'static,
+{
+    if !ndjson {
+        return HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(ContentEncoding::Identity)
+            .streaming(stream);
+    }
+
+    // Upstream reads can split an event, so only complete ones are converted
+    let stream = sse_events(stream).filter_map(|result| async move {
+        match result {
+            Ok(event) => sse_data(&String::from_utf8_lossy(&event)).map(|data| Ok(Bytes::from(format!("{}\n", data)))),
+            Err(e) => Some(Err(e)),
         }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header(ContentEncoding::Identity)
+        .streaming(stream)
+}
+
+/// The `data:` payload of one SSE event. `[DONE]` and events without data (e.g.
+/// comments) have none.
+fn sse_data(event: &str) -> Option<String> {
+    let data: Vec<&str> = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    let data = data.join("\n");
+    let data = data.trim();
+    if data.is_empty() || data == "[DONE]" {
+        return None;
     }
+    Some(data.to_string())
 }
 
-fn error_to_response(err: ProviderError) -> HttpResponse {
+pub(super) fn error_to_response(err: ProviderError) -> HttpResponse {
     match err {
         ProviderError::Network(msg) => {
             HttpResponse::BadGateway().body(format!("Provider unavailable: {}", msg))
@@ -104,10 +669,557 @@ fn error_to_response(err: ProviderError) -> HttpResponse {
         ProviderError::Parse(msg) => {
             HttpResponse::InternalServerError().body(format!("Failed to parse response: {}", msg))
         }
-        ProviderError::ProviderError { status, message } => HttpResponse::build(
+        ProviderError::ProviderError { status, message, .. } => HttpResponse::build(
             actix_web::http::StatusCode::from_u16(status)
                 .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR),
         )
         .body(message),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::StubProvider;
+    use crate::providers::{BoundedProvider, FallbackProvider};
+    use actix_web::dev::ServiceResponse;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    /// Sends `req` to the chat endpoint as a `role` key, the way the auth middleware
+    /// would hand it over.
+    async fn send(provider: Arc<dyn LLMProvider>, backends: HealthBackends, config: ChatConfig, role: ApiKeyRole, req: TestRequest) -> ServiceResponse {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::from(provider))
+                .app_data(web::Data::new(backends))
+                .app_data(tracker())
+                .app_data(web::Data::new(config))
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(ValidatedApiKey { key: String::from("test-key"), role, allowed_models: None });
+                    actix_web::dev::Service::call(srv, req)
+                })
+                .route("/v1/chat/completions", web::post().to(chat_completions)),
+        )
+        .await;
+        call_service(&app, req.to_request()).await
+    }
+
+    fn chat_request(body: serde_json::Value) -> TestRequest {
+        TestRequest::post().uri("/v1/chat/completions").set_json(body)
+    }
+
+    fn hello(stream: bool) -> serde_json::Value {
+        serde_json::json!({"model": "llama3.2", "messages": [{"role": "user", "content": "Hi"}], "stream": stream})
+    }
+
+    async fn json_body(res: ServiceResponse) -> serde_json::Value {
+        serde_json::from_slice(&read_body(res).await).unwrap()
+    }
+
+    fn canned_config() -> ChatConfig {
+        ChatConfig { canned_fallback: Some(String::from("Try again later")), ..ChatConfig::default() }
+    }
+
+    fn fallback(primary: &Arc<StubProvider>, backup: &Arc<StubProvider>) -> Arc<dyn LLMProvider> {
+        Arc::new(FallbackProvider::new(primary.clone(), backup.clone(), None))
+    }
+
+    #[actix_web::test]
+    async fn canned_reply_comes_once_every_provider_failed() {
+        let primary = Arc::new(StubProvider::new("primary").failing(503));
+        let backup = Arc::new(StubProvider::new("backup").failing(500));
+        let res = send(fallback(&primary, &backup), HealthBackends::default(), canned_config(), ApiKeyRole::User, chat_request(hello(false))).await;
+        assert_eq!(res.status(), 200);
+        assert!(res.request().extensions().contains::<RecordAsError>());
+        let body = json_body(res).await;
+        assert_eq!(body["choices"][0]["message"]["content"], "Try again later");
+        assert_eq!(body["choices"][0]["finish_reason"], "error");
+        assert_eq!((primary.models().len(), backup.models().len()), (1, 1));
+    }
+
+    #[actix_web::test]
+    async fn canned_reply_is_not_used_while_a_provider_answers() {
+        let primary = Arc::new(StubProvider::new("primary").failing(503));
+        let backup = Arc::new(StubProvider::new("backup"));
+        let res = send(fallback(&primary, &backup), HealthBackends::default(), canned_config(), ApiKeyRole::User, chat_request(hello(false))).await;
+        assert_eq!(res.status(), 200);
+        assert!(!res.request().extensions().contains::<RecordAsError>());
+        let body = json_body(res).await;
+        assert_eq!(body["choices"][0]["message"]["content"], "backup");
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[actix_web::test]
+    async fn canned_stream_ends_with_an_error_chunk() {
+        let primary = Arc::new(StubProvider::new("primary").failing(503));
+        let backup = Arc::new(StubProvider::new("backup").failing(500));
+        let res = send(fallback(&primary, &backup), HealthBackends::default(), canned_config(), ApiKeyRole::User, chat_request(hello(true))).await;
+        assert_eq!(res.status(), 200);
+        let body = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+        assert!(body.contains("\"content\":\"Try again later\""));
+        assert!(body.contains("\"finish_reason\":\"error\""));
+        assert!(body.ends_with("data: [DONE]\n\n"));
+    }
+
+    #[actix_web::test]
+    async fn without_a_canned_reply_the_error_is_passed_on() {
+        let primary = Arc::new(StubProvider::new("primary").failing(503));
+        let backup = Arc::new(StubProvider::new("backup").failing(500));
+        let res = send(fallback(&primary, &backup), HealthBackends::default(), ChatConfig::default(), ApiKeyRole::User, chat_request(hello(false))).await;
+        assert_eq!(res.status(), 500);
+    }
+
+    #[test]
+    fn model_timeout_falls_back_from_the_tag_to_the_base_model() {
+        let config = ChatConfig {
+            model_timeouts: HashMap::from([
+                (String::from("llama3.1:70b"), Duration::from_secs(120)),
+                (String::from("llama3.1"), Duration::from_secs(30)),
+            ]),
+            ..ChatConfig::default()
+        };
+        assert_eq!(config.timeout_for("llama3.1:70b"), Some(Duration::from_secs(120)));
+        assert_eq!(config.timeout_for("llama3.1:8b"), Some(Duration::from_secs(30)));
+        assert_eq!(config.timeout_for("llama3.1"), Some(Duration::from_secs(30)));
+        assert_eq!(config.timeout_for("mistral"), None);
+    }
+
+    #[actix_web::test]
+    async fn model_timeout_is_sent_with_the_request() {
+        let upstream = Arc::new(StubProvider::new("Hi"));
+        let config = ChatConfig {
+            model_timeouts: HashMap::from([(String::from("llama3.2"), Duration::from_secs(7))]),
+            ..ChatConfig::default()
+        };
+        let res = send(upstream.clone(), HealthBackends::default(), config, ApiKeyRole::User, chat_request(hello(false))).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(upstream.timeouts(), [Some(Duration::from_secs(7))]);
+    }
+
+    fn tools_config() -> ChatConfig {
+        ChatConfig { max_tools: 2, max_tools_bytes: 200, max_n: 1, ..ChatConfig::default() }
+    }
+
+    fn with_tools(tools: Vec<serde_json::Value>) -> ChatCompletionRequest {
+        let mut request = crate::providers::testing::request("llama3.2");
+        request.tools = Some(tools);
+        request
+    }
+
+    fn tool(name: &str) -> serde_json::Value {
+        serde_json::json!({"type": "function", "function": {"name": name}})
+    }
+
+    #[test]
+    fn tools_within_the_caps_pass() {
+        assert!(validate_request(&with_tools(vec![tool("a"), tool("b")]), &tools_config()).is_ok());
+    }
+
+    #[test]
+    fn too_many_tools_are_rejected() {
+        let err = validate_request(&with_tools(vec![tool("a"), tool("b"), tool("c")]), &tools_config()).unwrap_err();
+        assert_eq!(err.error.param.as_deref(), Some("tools"));
+        assert!(err.error.message.contains("Too many tools: 3 (max 2)"), "{}", err.error.message);
+    }
+
+    #[test]
+    fn oversized_tools_are_rejected() {
+        let big = serde_json::json!({"type": "function", "function": {"name": "a", "description": "x".repeat(300)}});
+        let err = validate_request(&with_tools(vec![big]), &tools_config()).unwrap_err();
+        assert_eq!(err.error.param.as_deref(), Some("tools"));
+        assert!(err.error.message.contains("Tools definition too large"), "{}", err.error.message);
+    }
+
+    #[actix_web::test]
+    async fn rejected_tools_never_reach_upstream() {
+        let upstream = Arc::new(StubProvider::new("Hi"));
+        let mut body = hello(false);
+        body["tools"] = serde_json::json!([tool("a"), tool("b"), tool("c")]);
+        let res = send(upstream.clone(), HealthBackends::default(), tools_config(), ApiKeyRole::User, chat_request(body)).await;
+        assert_eq!(res.status(), 400);
+        assert!(upstream.models().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn every_violation_comes_back_in_one_400() {
+        let upstream = Arc::new(StubProvider::new("Hi"));
+        let body = serde_json::json!({
+            "model": "",
+            "messages": [{"role": "robot", "content": "Hi"}],
+            "n": 3,
+            "tools": [tool("a"), tool("b"), tool("c")],
+        });
+        let res = send(upstream.clone(), HealthBackends::default(), tools_config(), ApiKeyRole::User, chat_request(body)).await;
+        assert_eq!(res.status(), 400);
+        let body = json_body(res).await;
+        let params: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|e| e["param"].as_str().unwrap()).collect();
+        assert_eq!(params, ["model", "messages[0].role", "n", "tools"]);
+        assert!(body["error"]["message"].as_str().unwrap().starts_with("4 validation errors: "));
+        assert!(upstream.models().is_empty());
+    }
+
+    fn pinned(name: &str) -> TestRequest {
+        chat_request(hello(false)).insert_header(("X-Provider", name))
+    }
+
+    #[actix_web::test]
+    async fn admin_can_pin_a_backend() {
+        let default: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("default"));
+        let openai: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("openai"));
+        let backends = HealthBackends(vec![(String::from("openai"), openai)]);
+        let res = send(default.clone(), backends.clone(), ChatConfig::default(), ApiKeyRole::Admin, pinned("openai")).await;
+        assert_eq!(json_body(res).await["choices"][0]["message"]["content"], "openai");
+
+        let res = send(default.clone(), backends.clone(), ChatConfig::default(), ApiKeyRole::User, pinned("openai")).await;
+        assert_eq!(res.status(), 403);
+        let res = send(default, backends, ChatConfig::default(), ApiKeyRole::Admin, pinned("ollama")).await;
+        assert_eq!(res.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn pinned_request_waits_for_a_gateway_slot() {
+        let gateway = BoundedProvider::new(Arc::new(StubProvider::new("default")), "Gateway", 1, Duration::from_millis(20));
+        let openai: Arc<dyn LLMProvider> = Arc::new(gateway.sharing(Arc::new(StubProvider::new("openai"))));
+        let backends = HealthBackends(vec![(String::from("openai"), openai)]);
+        let busy = gateway.chat_stream(crate::providers::testing::request("m")).await.unwrap();
+
+        let gateway: Arc<dyn LLMProvider> = Arc::new(gateway);
+        let res = send(gateway.clone(), backends.clone(), ChatConfig::default(), ApiKeyRole::Admin, pinned("openai")).await;
+        assert_eq!(res.status(), 503);
+
+        drop(busy);
+        let res = send(gateway, backends, ChatConfig::default(), ApiKeyRole::Admin, pinned("openai")).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    fn msg(role: &str, content: &str) -> Message {
+        Message { role: role.to_string(), content: content.to_string().into(), tool_calls: None, tool_call_id: None }
+    }
+
+    fn turns(messages: &[Message]) -> Vec<(String, String)> {
+        messages.iter().map(|m| (m.role.clone(), m.content.text().into_owned())).collect()
+    }
+
+    #[test]
+    fn consecutive_user_messages_are_merged() {
+        let merged = merge_consecutive_roles(vec![msg("system", "Be brief"), msg("user", "Hi"), msg("user", "Are you there?")]);
+        assert_eq!(turns(&merged), turns(&[msg("system", "Be brief"), msg("user", "Hi\n\nAre you there?")]));
+    }
+
+    #[test]
+    fn valid_alternation_is_left_alone() {
+        let messages = vec![msg("system", "Be brief"), msg("user", "Hi"), msg("assistant", "Hello"), msg("user", "Bye")];
+        assert_eq!(turns(&merge_consecutive_roles(messages.clone())), turns(&messages));
+    }
+
+    #[test]
+    fn system_tool_and_tool_call_messages_are_never_merged() {
+        let mut call = msg("assistant", "");
+        call.tool_calls = Some(vec![serde_json::json!({"id": "call_1"})]);
+        let messages = vec![
+            msg("system", "A"),
+            msg("system", "B"),
+            call.clone(),
+            msg("assistant", "Done"),
+            msg("tool", "1"),
+            msg("tool", "2"),
+        ];
+        assert_eq!(merge_consecutive_roles(messages).len(), 6);
+    }
+
+    #[actix_web::test]
+    async fn repaired_request_goes_upstream_merged() {
+        let upstream = Arc::new(StubProvider::new("Hi"));
+        let config = ChatConfig { repair_alternation: true, ..ChatConfig::default() };
+        let body = serde_json::json!({"model": "llama3.2", "messages": [{"role": "user", "content": "Hi"}, {"role": "user", "content": "Hello?"}]});
+        let res = send(upstream.clone(), HealthBackends::default(), config, ApiKeyRole::User, chat_request(body)).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(turns(&upstream.messages()[0]), turns(&[msg("user", "Hi\n\nHello?")]));
+    }
+
+    #[actix_web::test]
+    async fn openai_tool_call_delta_without_content_is_forwarded() {
+        let tracker = tracker();
+        let data = serde_json::json!({
+            "id": "chatcmpl-test", "object": "chat.completion.chunk", "created": 0, "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "delta": {"content": null, "tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "get_weather", "arguments": ""}}]},
+                "finish_reason": null,
+            }],
+        });
+        let event = format!("data: {}\n\n", data);
+        let body = run(vec![event.clone(), chunk_event("", Some("tool_calls"), None)], tap(&tracker)).await;
+        assert!(body.starts_with(&event), "{}", body);
+
+        let chunk: ChatCompletionChunk = serde_json::from_value(data).unwrap();
+        assert_eq!(chunk.choices[0].delta.content, "");
+        assert_eq!(chunk.choices[0].delta.tool_calls.as_ref().unwrap()[0]["id"], "call_1");
+    }
+
+    fn dead_letter_config() -> (ChatConfig, Arc<DeadLetterLog>, String) {
+        let path = std::env::temp_dir().join(format!("dead-letters-{}.jsonl", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let log = Arc::new(DeadLetterLog::spawn(&path, false).unwrap());
+        (ChatConfig { dead_letters: Some(log.clone()), ..ChatConfig::default() }, log, path)
+    }
+
+    #[actix_web::test]
+    async fn upstream_failure_is_dead_lettered_as_sent() {
+        let (config, log, path) = dead_letter_config();
+        let upstream: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("Hi").failing(503));
+        let res = send(upstream, HealthBackends::default(), config, ApiKeyRole::User, chat_request(hello(false))).await;
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(res.status(), 503);
+        assert_eq!(log.count(), 1);
+        let letter = &log.recent(1)[0];
+        assert_eq!(letter.status, 503);
+        assert_eq!(letter.request["messages"], hello(false)["messages"]);
+    }
+
+    #[actix_web::test]
+    async fn client_errors_are_not_dead_lettered() {
+        let (config, log, path) = dead_letter_config();
+        let upstream: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("Hi").failing(400));
+        let res = send(upstream, HealthBackends::default(), config, ApiKeyRole::User, chat_request(hello(false))).await;
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(res.status(), 400);
+        assert_eq!(log.count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn reply_without_usage_is_passed_on() {
+        // The stub, like minimal OpenAI-compatible servers, reports no usage
+        let upstream: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("Hi"));
+        let res = send(upstream, HealthBackends::default(), ChatConfig::default(), ApiKeyRole::User, chat_request(hello(false))).await;
+        assert_eq!(res.status(), 200);
+        let body = json_body(res).await;
+        assert_eq!(body["choices"][0]["message"]["content"], "Hi");
+        assert!(body.get("usage").is_none());
+    }
+
+    async fn streamed(accept: Option<&str>) -> (String, String) {
+        let upstream: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("Hello there"));
+        let mut req = chat_request(hello(true));
+        if let Some(accept) = accept {
+            req = req.insert_header(("Accept", accept));
+        }
+        let res = send(upstream, HealthBackends::default(), ChatConfig::default(), ApiKeyRole::User, req).await;
+        assert_eq!(res.status(), 200);
+        let content_type = res.headers().get("Content-Type").unwrap().to_str().unwrap().to_string();
+        (content_type, String::from_utf8(read_body(res).await.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn ndjson_is_sent_when_accepted() {
+        // The stub's 16-byte reads split events, so lines must still come out whole
+        let (content_type, body) = streamed(Some("application/x-ndjson, text/event-stream;q=0.5")).await;
+        assert_eq!(content_type, "application/x-ndjson");
+        assert!(body.ends_with('\n'));
+        let chunks: Vec<serde_json::Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["content"], "Hello there");
+        assert_eq!(chunks[1]["choices"][0]["finish_reason"], "stop");
+        assert!(!body.contains("[DONE]"));
+    }
+
+    #[actix_web::test]
+    async fn sse_is_sent_otherwise() {
+        for accept in [None, Some("text/event-stream"), Some("*/*")] {
+            let (content_type, body) = streamed(accept).await;
+            assert_eq!(content_type, "text/event-stream");
+            assert!(body.starts_with("data: "));
+            assert!(body.ends_with("data: [DONE]\n\n"));
+        }
+    }
+
+    #[test]
+    fn sse_data_joins_lines_and_skips_done_and_comments() {
+        assert_eq!(sse_data("data: {\"a\":\ndata: 1}\n\n").as_deref(), Some("{\"a\":\n1}"));
+        assert_eq!(sse_data("data:{}\n\n").as_deref(), Some("{}"));
+        assert_eq!(sse_data("data: [DONE]\n\n"), None);
+        assert_eq!(sse_data(": keep-alive\n\n"), None);
+    }
+
+    fn tracker() -> web::Data<RwLock<RequestTracker>> {
+        web::Data::new(RwLock::new(RequestTracker::new()))
+    }
+
+    fn tap(tracker: &web::Data<RwLock<RequestTracker>>) -> StreamTap {
+        StreamTap {
+            usage: StreamUsageRecorder {
+                tracker: tracker.clone(),
+                api_key: String::from("test-key"),
+                user: None,
+                mode: StreamUsage::Cumulative,
+                totals: None,
+            },
+            session: None,
+            span: None,
+            transformers: Vec::new(),
+        }
+    }
+
+    fn chunk_event(content: &str, finish_reason: Option<&str>, usage: Option<Usage>) -> String {
+        let chunk = ChatCompletionChunk {
+            id: String::from("chatcmpl-test"),
+            object: String::from("chat.completion.chunk"),
+            created: 0,
+            model: String::from("llama3.2"),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta: Delta { role: None, content: content.to_string(), tool_calls: None },
+                finish_reason: finish_reason.map(String::from),
+            }],
+            usage,
+        };
+        format!("data: {}\n\n", serde_json::to_string(&chunk).unwrap())
+    }
+
+    fn usage(prompt_tokens: u32, completion_tokens: u32) -> Option<Usage> {
+        Some(Usage { prompt_tokens, completion_tokens, total_tokens: prompt_tokens + completion_tokens })
+    }
+
+    /// Runs `reads` through the tap as if they came off the network, returning the body.
+    async fn run(reads: Vec<String>, tap: StreamTap) -> String {
+        let reads = futures::stream::iter(reads.into_iter().map(|read| Ok::<_, ProviderError>(Bytes::from(read))));
+        let events: Vec<_> = tap_stream(reads, tap).collect().await;
+        events.into_iter().map(|event| String::from_utf8(event.unwrap().to_vec()).unwrap()).collect()
+    }
+
+    fn recorded_tokens(tracker: &web::Data<RwLock<RequestTracker>>) -> (u64, u64) {
+        let tracker = tracker.read().unwrap();
+        let stats = tracker.get_stats("test-key").expect("usage was recorded");
+        (stats.total_prompt_tokens, stats.total_completion_tokens)
+    }
+
+    #[actix_web::test]
+    async fn usage_is_found_in_a_read_holding_several_events() {
+        let tracker = tracker();
+        let read = format!("{}{}data: [DONE]\n\n", chunk_event("Hi", None, None), chunk_event("", Some("stop"), usage(5, 7)));
+        run(vec![read], tap(&tracker)).await;
+        assert_eq!(recorded_tokens(&tracker), (5, 7));
+    }
+
+    #[actix_web::test]
+    async fn usage_is_found_in_an_event_split_across_reads() {
+        let tracker = tracker();
+        let event = chunk_event("", Some("stop"), usage(11, 13));
+        let (head, tail) = event.split_at(event.len() / 2);
+        let body = run(vec![chunk_event("Hi", None, None), head.to_string(), tail.to_string()], tap(&tracker)).await;
+        assert_eq!(recorded_tokens(&tracker), (11, 13));
+        assert_eq!(body, format!("{}{}", chunk_event("Hi", None, None), event));
+    }
+
+    #[actix_web::test]
+    async fn latest_usage_wins_when_the_last_chunk_has_none() {
+        let tracker = tracker();
+        let reads = vec![
+            chunk_event("Hel", None, usage(3, 1)),
+            chunk_event("lo", None, usage(3, 2)),
+            chunk_event("", Some("stop"), None),
+            String::from("data: [DONE]\n\n"),
+        ];
+        run(reads, tap(&tracker)).await;
+        assert_eq!(recorded_tokens(&tracker), (3, 2));
+    }
+
+    #[actix_web::test]
+    async fn incremental_usage_is_summed_across_chunks() {
+        let tracker = tracker();
+        let mut tap = tap(&tracker);
+        tap.usage.mode = StreamUsage::Incremental;
+        let reads = vec![
+            chunk_event("Hel", None, usage(3, 1)),
+            chunk_event("lo", None, usage(0, 2)),
+            chunk_event("", Some("stop"), usage(0, 1)),
+            chunk_event("", None, None),
+            String::from("data: [DONE]\n\n"),
+        ];
+        run(reads, tap).await;
+        assert_eq!(recorded_tokens(&tracker), (3, 4));
+    }
+
+    #[actix_web::test]
+    async fn disclaimer_lands_on_the_final_chunk_whatever_the_reads() {
+        let tracker = tracker();
+        let mut tap = tap(&tracker);
+        tap.transformers = vec![Arc::new(crate::transform::AppendDisclaimer::new("AI generated"))];
+        let stream = format!("{}{}data: [DONE]\n\n", chunk_event("Hi", None, None), chunk_event("", Some("stop"), None));
+        // Two events in the first read, and the final chunk split in the middle
+        let (head, tail) = stream.split_at(stream.len() - 30);
+        let body = run(vec![head.to_string(), tail.to_string()], tap).await;
+
+        let contents: Vec<String> = body
+            .split("\n\n")
+            .filter_map(sse_data)
+            .map(|data| serde_json::from_str::<ChatCompletionChunk>(&data).unwrap().choices[0].delta.content.clone())
+            .collect();
+        assert_eq!(contents, ["Hi", "\n\nAI generated"]);
+        assert!(body.ends_with("data: [DONE]\n\n"));
+    }
+
+    fn session_tap(tracker: &web::Data<RwLock<RequestTracker>>, store: &Arc<SessionStore>) -> StreamTap {
+        let mut tap = tap(tracker);
+        tap.session = Some(StreamSessionRecorder {
+            store: store.clone(),
+            api_key: String::from("test-key"),
+            id: String::from("s1"),
+            turn: vec![Message {
+                role: String::from("user"),
+                content: String::from("Hi").into(),
+                tool_calls: None,
+                tool_call_id: None,
+            }],
+            reply: String::new(),
+            finished: false,
+        });
+        tap
+    }
+
+    #[actix_web::test]
+    async fn session_history_is_validated_with_the_new_turn() {
+        let store = Arc::new(SessionStore::new(Duration::from_secs(60), 10));
+        store.append("test-key", "s1", vec![msg("user", "Hi"), msg("assistant", "Hello")]);
+        let config = ChatConfig { sessions: Some(store), max_n: 1, ..ChatConfig::default() };
+        let upstream = Arc::new(StubProvider::new("Hi"));
+        let body = serde_json::json!({"model": "llama3.2", "messages": [{"role": "robot", "content": "And?"}]});
+        let req = chat_request(body).insert_header(("X-Session-Id", "s1"));
+        let res = send(upstream.clone(), HealthBackends::default(), config, ApiKeyRole::User, req).await;
+        assert_eq!(res.status(), 400);
+        // Indexed within the merged request, history first
+        assert_eq!(json_body(res).await["error"]["param"], "messages[2].role");
+        assert!(upstream.models().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn streamed_reply_is_stored_whole_in_the_session() {
+        let tracker = tracker();
+        let store = Arc::new(SessionStore::new(Duration::from_secs(60), 10));
+        let stream = format!(
+            "{}{}{}data: [DONE]\n\n",
+            chunk_event("Hello", None, None),
+            chunk_event(" world", None, None),
+            chunk_event("", Some("stop"), None)
+        );
+        // Cut every few bytes, so no read lines up with an event
+        let reads: Vec<String> = stream.as_bytes().chunks(7).map(|c| String::from_utf8(c.to_vec()).unwrap()).collect();
+        run(reads, session_tap(&tracker, &store)).await;
+
+        let history = store.history("test-key", "s1");
+        let turns: Vec<(String, String)> = history.iter().map(|m| (m.role.clone(), m.content.text().into_owned())).collect();
+        assert_eq!(
+            turns,
+            [(String::from("user"), String::from("Hi")), (String::from("assistant"), String::from("Hello world"))]
+        );
+    }
+
+    #[actix_web::test]
+    async fn unfinished_stream_is_not_stored() {
+        let tracker = tracker();
+        let store = Arc::new(SessionStore::new(Duration::from_secs(60), 10));
+        run(vec![chunk_event("Hel", None, None)], session_tap(&tracker, &store)).await;
+        assert!(store.history("test-key", "s1").is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use actix_web::{web, HttpResponse, HttpRequest, HttpMessage};
+use crate::models::{ApiError, ApiErrorBody, EmbeddingRequest};
+use crate::providers::LLMProvider;
+use crate::tracking::RequestTracker;
+use crate::middleware::auth::{Scope, ValidatedApiKey};
+use crate::middleware::request_id::RequestId;
+use crate::middleware::tracking::RequestModel;
+use super::chat::error_to_response;
+use tracing::{info, warn, error};
+use std::sync::RwLock;
+
+/// Handler-level settings for `/v1/embeddings`.
+#[derive(Debug, Clone)]
+pub struct EmbeddingsConfig {
+    /// Most inputs accepted in one request (`MAX_EMBEDDING_BATCH`)
+    pub max_batch: usize,
+    /// Longest single input, in characters (`MAX_EMBEDDING_INPUT_CHARS`)
+    pub max_input_chars: usize,
+}
+
+pub async fn embeddings(
+    req: HttpRequest,
+    provider: web::Data<dyn LLMProvider>,
+    request_tracker: web::Data<RwLock<RequestTracker>>,
+    config: web::Data<EmbeddingsConfig>,
+    body: web::Json<EmbeddingRequest>,
+) -> HttpResponse {
+    let role = req.extensions().get::<ValidatedApiKey>().map(|key| key.role);
+    if let Some(role) = role.filter(|role| !role.allows(Scope::Chat)) {
+        warn!("Rejected embeddings request from a '{}' key", role.name());
+        return HttpResponse::Forbidden().json(ApiError::insufficient_scope(role.name(), "create embeddings"));
+    }
+    let mut request = body.into_inner();
+    if let Err(message) = validate_request(&request, &config) {
+        warn!("Rejected embeddings request: {}", message);
+        return HttpResponse::BadRequest().json(ApiError::from(ApiErrorBody::invalid_request(message, "input")));
+    }
+    req.extensions_mut().insert(RequestModel(request.model.clone()));
+    request.request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    info!("Embeddings request received");
+
+    match provider.embeddings(request).await {
+        Ok(response) => {
+            if let Some(extensions) = req.extensions().get::<ValidatedApiKey>() {
+                let prompt_tokens = response.usage.prompt_tokens as u64;
+                if let Ok(mut tracker) = request_tracker.write() {
+                    tracker.record_tokens(&extensions.key, prompt_tokens, 0, &response.model, None);
+                } else {
+                    error!("Failed to acquire write lock on RequestTracker");
+                }
+            }
+
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => error_to_response(e),
+    }
+}
+
+/// Checks batch size and input lengths before anything goes upstream.
+fn validate_request(request: &EmbeddingRequest, config: &EmbeddingsConfig) -> Result<(), String> {
+    let inputs = request.input.as_slice();
+    if inputs.len() > config.max_batch {
+        return Err(format!("Too many inputs: {} (max {})", inputs.len(), config.max_batch));
+    }
+    for (index, input) in inputs.iter().enumerate() {
+        let chars = input.chars().count();
+        if chars > config.max_input_chars {
+            return Err(format!("Input {} too long: {} characters (max {})", index, chars, config.max_input_chars));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::auth::ApiKeyRole;
+    use crate::models::EmbeddingInput;
+    use crate::providers::testing::StubProvider;
+    use std::sync::Arc;
+
+    fn config() -> EmbeddingsConfig {
+        EmbeddingsConfig { max_batch: 2, max_input_chars: 5 }
+    }
+
+    fn request(input: EmbeddingInput) -> EmbeddingRequest {
+        EmbeddingRequest { model: String::from("nomic-embed-text"), input, request_id: None }
+    }
+
+    fn batch(inputs: &[&str]) -> EmbeddingRequest {
+        request(EmbeddingInput::Batch(inputs.iter().map(|i| i.to_string()).collect()))
+    }
+
+    #[test]
+    fn inputs_within_the_limits_pass() {
+        assert!(validate_request(&batch(&["one", "two"]), &config()).is_ok());
+        assert!(validate_request(&request(EmbeddingInput::Single(String::from("hello"))), &config()).is_ok());
+    }
+
+    #[test]
+    fn oversized_batch_is_rejected() {
+        let err = validate_request(&batch(&["a", "b", "c"]), &config()).unwrap_err();
+        assert_eq!(err, "Too many inputs: 3 (max 2)");
+    }
+
+    #[test]
+    fn over_length_input_is_rejected_by_index() {
+        let err = validate_request(&batch(&["ok", "too long"]), &config()).unwrap_err();
+        assert_eq!(err, "Input 1 too long: 8 characters (max 5)");
+        // Characters, not bytes
+        assert!(validate_request(&batch(&["héllo"]), &config()).is_ok());
+    }
+
+    /// Status and JSON body of `embeddings` for a request from a key with `role`.
+    async fn call(role: ApiKeyRole, request: EmbeddingRequest) -> (u16, serde_json::Value) {
+        let req = actix_web::test::TestRequest::post().to_http_request();
+        req.extensions_mut().insert(ValidatedApiKey { key: String::from("test-key"), role, allowed_models: None });
+        let provider: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("Hi"));
+        let res = embeddings(
+            req,
+            web::Data::from(provider),
+            web::Data::new(RwLock::new(RequestTracker::new())),
+            web::Data::new(config()),
+            web::Json(request),
+        )
+        .await;
+        let status = res.status().as_u16();
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn limit_violations_get_the_error_envelope() {
+        for request in [batch(&["a", "b", "c"]), batch(&["too long"])] {
+            let (status, body) = call(ApiKeyRole::User, request).await;
+            assert_eq!(status, 400);
+            assert_eq!(body["error"]["param"], "input");
+            assert_eq!(body["error"]["type"], "invalid_request_error");
+        }
+    }
+
+    #[actix_web::test]
+    async fn read_only_key_gets_the_error_envelope() {
+        let (status, body) = call(ApiKeyRole::ReadOnly, batch(&["one"])).await;
+        assert_eq!(status, 403);
+        assert_eq!(body["error"]["message"], "This API key's role ('read_only') may not create embeddings");
+        assert_eq!(body["error"]["code"], "insufficient_scope");
+    }
+}
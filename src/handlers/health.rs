@@ -0,0 +1,64 @@
+use actix_web::{web, HttpResponse};
+use crate::providers::LLMProvider;
+use futures::future::join_all;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Named upstreams, reported individually by `/v1/health` and selectable per chat
+/// request with `X-Provider`.
+#[derive(Clone, Default)]
+pub struct HealthBackends(pub Vec<(String, Arc<dyn LLMProvider>)>);
+
+#[derive(Serialize)]
+pub struct BackendHealth {
+    pub name: String,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+    pub backends: Vec<BackendHealth>,
+}
+
+/// Liveness only: answers as long as the process is serving requests.
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+/// Readiness: 200 when the configured provider can serve requests (e.g. either side
+/// of a fallback is up), 503 otherwise. Each backend's status is listed either way.
+pub async fn provider_health(
+    provider: web::Data<dyn LLMProvider>,
+    backends: web::Data<HealthBackends>,
+) -> HttpResponse {
+    let (overall, results) = futures::join!(
+        provider.health_check(),
+        join_all(backends.0.iter().map(|(_, backend)| backend.health_check()))
+    );
+
+    let backends = backends
+        .0
+        .iter()
+        .zip(results)
+        .map(|((name, _), result)| BackendHealth {
+            name: name.clone(),
+            healthy: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        })
+        .collect();
+
+    match overall {
+        Ok(()) => HttpResponse::Ok().json(HealthResponse { status: "ok", backends }),
+        Err(e) => {
+            warn!("Health check failed: {}", e);
+            HttpResponse::ServiceUnavailable().json(HealthResponse {
+                status: "unavailable",
+                backends,
+            })
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use actix_web::{web, HttpRequest, HttpResponse, HttpMessage};
+use crate::middleware::auth::{mask_key, ApiKeyRole, KeySet, ValidatedApiKey};
+use super::admin::{check_admin_intent, AdminConfig};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tracing::{error, info};
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct KeyEntry {
+    /// Masked, except in the response that creates the key
+    pub key: String,
+    pub role: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct KeysResponse {
+    pub keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+pub struct AddKeyRequest {
+    /// Generated when omitted
+    pub key: Option<String>,
+    /// "user" (default), "admin", "read_only" or "chat_only"
+    pub role: Option<String>,
+}
+
+/// Admin-only gate shared by the key endpoints.
+fn require_admin(req: &HttpRequest) -> Option<HttpResponse> {
+    let Some(validated) = req.extensions().get::<ValidatedApiKey>().cloned() else {
+        return Some(HttpResponse::Unauthorized().body("Missing API key context"));
+    };
+    if !matches!(validated.role, ApiKeyRole::Admin) {
+        return Some(HttpResponse::Forbidden().body("Admin API key required"));
+    }
+    None
+}
+
+/// Every accepted key, masked.
+pub async fn list_keys(req: HttpRequest, keys: web::Data<RwLock<KeySet>>) -> HttpResponse {
+    if let Some(rejection) = require_admin(&req) {
+        return rejection;
+    }
+
+    let Ok(keys) = keys.read() else {
+        return HttpResponse::InternalServerError().body("Failed to read keys");
+    };
+    HttpResponse::Ok().json(KeysResponse {
+        keys: keys
+            .entries()
+            .map(|(key, role)| KeyEntry { key: mask_key(key), role: role.name() })
+            .collect(),
+    })
+}
+
+/// Adds a key that is accepted from the next request on. The full key is only ever
+/// shown in this response.
+pub async fn add_key(
+    req: HttpRequest,
+    keys: web::Data<RwLock<KeySet>>,
+    admin_config: web::Data<AdminConfig>,
+    body: web::Json<AddKeyRequest>,
+) -> HttpResponse {
+    if let Some(rejection) = require_admin(&req) {
+        return rejection;
+    }
+    if let Some(rejection) = check_admin_intent(&req, &admin_config) {
+        return rejection;
+    }
+
+    let body = body.into_inner();
+    let role = match body.role.as_deref().map(str::trim) {
+        None => ApiKeyRole::User,
+        Some(name) => match ApiKeyRole::from_name(name) {
+            Some(role) => role,
+            None => {
+                let expected: Vec<&str> = ApiKeyRole::ALL.iter().map(|role| role.name()).collect();
+                return HttpResponse::BadRequest()
+                    .body(format!("Unknown role '{}' (expected one of {})", name, expected.join(", ")));
+            }
+        },
+    };
+    let key = match body.key.map(|k| k.trim().to_string()) {
+        Some(key) if key.is_empty() => return HttpResponse::BadRequest().body("key must not be empty"),
+        Some(key) => key,
+        None => format!("sk-gw-{}", Uuid::new_v4().simple()),
+    };
+
+    let Ok(mut keys) = keys.write() else {
+        return HttpResponse::InternalServerError().body("Failed to update keys");
+    };
+    match keys.add(key.clone(), role) {
+        Ok(true) => {
+            info!("Added {} key {}", role.name(), mask_key(&key));
+            HttpResponse::Created().json(KeyEntry { key, role: role.name() })
+        }
+        Ok(false) => HttpResponse::Conflict().body("Key already exists"),
+        Err(e) => {
+            error!("Failed to save API keys: {}", e);
+            HttpResponse::InternalServerError().body("Key added but could not be saved")
+        }
+    }
+}
+
+/// Revokes a key immediately.
+pub async fn delete_key(
+    req: HttpRequest,
+    keys: web::Data<RwLock<KeySet>>,
+    admin_config: web::Data<AdminConfig>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    if let Some(rejection) = require_admin(&req) {
+        return rejection;
+    }
+    if let Some(rejection) = check_admin_intent(&req, &admin_config) {
+        return rejection;
+    }
+
+    let Ok(mut keys) = keys.write() else {
+        return HttpResponse::InternalServerError().body("Failed to update keys");
+    };
+    match keys.remove(&path) {
+        Ok(true) => {
+            info!("Removed key {}", mask_key(&path));
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => HttpResponse::NotFound().body("Key not found"),
+        Err(e) => {
+            error!("Failed to save API keys: {}", e);
+            HttpResponse::InternalServerError().body("Key removed but could not be saved")
+        }
+    }
+}
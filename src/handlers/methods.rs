@@ -0,0 +1,22 @@
+use crate::models::ApiError;
+use actix_web::http::{header, Method};
+use actix_web::{web, HttpRequest, HttpResponse, Route};
+
+/// Default service for a resource, answering the methods it has no route for.
+/// `OPTIONS` gets a 204 and anything else a 405, both listing `allowed` in `Allow`.
+pub fn unsupported_method(allowed: &'static str) -> Route {
+    web::to(move |req: HttpRequest| async move {
+        let allow = format!("{}, OPTIONS", allowed);
+        if req.method() == Method::OPTIONS {
+            return HttpResponse::NoContent()
+                .insert_header((header::ALLOW, allow))
+                .finish();
+        }
+        HttpResponse::MethodNotAllowed()
+            .insert_header((header::ALLOW, allow))
+            .json(ApiError::method_not_allowed(
+                req.method().as_str(),
+                req.path(),
+            ))
+    })
+}
@@ -0,0 +1,57 @@
+use actix_web::{web, HttpRequest, HttpResponse, HttpMessage};
+use crate::middleware::auth::{ApiKeyRole, ValidatedApiKey};
+use crate::providers::CacheMetrics;
+use crate::providers::QueueMetrics;
+use crate::tracking::RequestTracker;
+use super::stats::build_summary;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+/// Gateway-wide counters in the Prometheus text format (admin only; scrape with a bearer token).
+pub async fn metrics(
+    req: HttpRequest,
+    tracker: web::Data<RwLock<RequestTracker>>,
+    cache: web::Data<Option<Arc<CacheMetrics>>>,
+    queue: web::Data<Option<Arc<QueueMetrics>>>,
+) -> HttpResponse {
+    let validated_key = req.extensions().get::<ValidatedApiKey>().cloned();
+
+    let Some(validated) = validated_key else {
+        return HttpResponse::Unauthorized().body("Missing API key context");
+    };
+
+    if !matches!(validated.role, ApiKeyRole::Admin) {
+        return HttpResponse::Forbidden().body("Admin API key required");
+    }
+
+    let summary = build_summary(&tracker.read().unwrap(), cache.get_ref(), queue.get_ref());
+
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    };
+
+    metric("gateway_requests_total", "counter", "Requests handled", summary.request_count);
+    metric("gateway_errors_total", "counter", "Requests counted as errors", summary.error_count);
+    metric("gateway_prompt_tokens_total", "counter", "Prompt tokens reported by providers", summary.total_prompt_tokens);
+    metric("gateway_completion_tokens_total", "counter", "Completion tokens reported by providers", summary.total_completion_tokens);
+
+    if let Some(cache) = summary.cache {
+        metric("gateway_cache_hits_total", "counter", "Responses served from the cache", cache.hits);
+        metric("gateway_cache_misses_total", "counter", "Cache lookups that went upstream", cache.misses);
+        metric("gateway_cache_evictions_total", "counter", "Entries evicted or expired", cache.evictions);
+        metric("gateway_cache_entries", "gauge", "Entries currently cached", cache.entries);
+    }
+
+    if let Some(queue) = summary.queue {
+        metric("gateway_requests_in_flight", "gauge", "Requests holding a MAX_CONCURRENT_REQUESTS slot", queue.in_flight);
+        metric("gateway_requests_queued", "gauge", "Requests waiting for a slot", queue.queued);
+        metric("gateway_max_concurrent_requests", "gauge", "Configured MAX_CONCURRENT_REQUESTS", queue.max_concurrent);
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(out)
+}
@@ -1,5 +1,23 @@
+mod admin;
 mod chat;
+mod embeddings;
+mod health;
+mod keys;
+mod methods;
+mod metrics;
+mod models;
+mod sessions;
 mod stats;
+mod tokenize;
 
-pub use chat::chat_completions;
-pub use stats::get_stats;
\ No newline at end of file
+pub use admin::{admin_info, dead_letters, shutdown, AdminConfig, BUILD_TIMESTAMP, GIT_SHA, VERSION};
+pub use chat::{chat_completions, ChatConfig};
+pub use embeddings::{embeddings, EmbeddingsConfig};
+pub use health::{liveness, provider_health, HealthBackends};
+pub use keys::{add_key, delete_key, list_keys};
+pub use methods::unsupported_method;
+pub use metrics::metrics;
+pub use models::{list_models, ModelsSource};
+pub use sessions::delete_session;
+pub use stats::{get_stats, reset_stats, stats_summary};
+pub use tokenize::tokenize;
\ No newline at end of file
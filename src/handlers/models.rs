@@ -0,0 +1,93 @@
+use actix_web::{web, HttpResponse};
+use crate::models::ModelList;
+use crate::providers::LLMProvider;
+use super::chat::error_to_response;
+use std::fs::File;
+use std::io::BufReader;
+use tracing::error;
+
+/// Where `/v1/models` gets its list from (`MODELS_SOURCE`).
+#[derive(Debug, Clone)]
+pub enum ModelsSource {
+    /// Ask the upstream providers on every call
+    Provider,
+    /// Serve a fixed list loaded from `MODELS_FILE` at startup
+    Config(ModelList),
+}
+
+impl ModelsSource {
+    /// Load a models file in OpenAI's list shape (`{"object": "list", "data": [...]}`).
+    pub fn from_file(path: &str) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let list: ModelList = serde_json::from_reader(BufReader::new(file))?;
+        Ok(ModelsSource::Config(list))
+    }
+}
+
+pub async fn list_models(
+    provider: web::Data<dyn LLMProvider>,
+    source: web::Data<ModelsSource>,
+) -> HttpResponse {
+    match source.get_ref() {
+        ModelsSource::Config(list) => HttpResponse::Ok().json(list),
+        ModelsSource::Provider => match provider.list_models().await {
+            Ok(data) => HttpResponse::Ok().json(ModelList {
+                object: String::from("list"),
+                data,
+            }),
+            Err(e) => {
+                error!("Failed to list models: {}", e);
+                error_to_response(e)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::StubProvider;
+    use actix_web::body::to_bytes;
+    use std::sync::Arc;
+
+    fn models_file(contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("models-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    async fn served_ids(source: ModelsSource) -> Vec<String> {
+        let provider: Arc<dyn LLMProvider> = Arc::new(StubProvider::new("Hi"));
+        let res = list_models(web::Data::from(provider), web::Data::new(source)).await;
+        assert_eq!(res.status(), 200);
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let list: ModelList = serde_json::from_slice(&body).unwrap();
+        list.data.into_iter().map(|model| model.id).collect()
+    }
+
+    #[actix_web::test]
+    async fn config_mode_serves_the_file() {
+        let path = models_file(
+            r#"{"object": "list", "data": [{"id": "llama3.2"}, {"id": "gpt-4o", "owned_by": "openai"}]}"#,
+        );
+        let source = ModelsSource::from_file(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(served_ids(source).await, ["llama3.2", "gpt-4o"]);
+    }
+
+    #[actix_web::test]
+    async fn provider_mode_asks_upstream() {
+        // The stub serves no models, unlike any file
+        assert!(served_ids(ModelsSource::Provider).await.is_empty());
+    }
+
+    #[test]
+    fn invalid_models_file_is_an_error() {
+        let path = models_file(r#"{"object": "list", "data": [{"name": "no id"}]}"#);
+        let err = ModelsSource::from_file(&path).unwrap_err();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let missing = ModelsSource::from_file("/nonexistent/models.json").unwrap_err();
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,25 @@
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use crate::middleware::auth::ValidatedApiKey;
+use crate::sessions::SessionStore;
+use std::sync::Arc;
+
+/// Forget a conversation stored under `X-Session-Id`. Keys can only remove their own sessions.
+pub async fn delete_session(
+    req: HttpRequest,
+    store: web::Data<Option<Arc<SessionStore>>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let Some(validated) = req.extensions().get::<ValidatedApiKey>().cloned() else {
+        return HttpResponse::Unauthorized().body("Missing API key context");
+    };
+
+    let Some(store) = store.get_ref() else {
+        return HttpResponse::NotFound().body("Sessions are not enabled (set SESSIONS_ENABLED)");
+    };
+
+    if store.remove(&validated.key, &path) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().body("Session not found")
+    }
+}
@@ -1,14 +1,25 @@
 use actix_web::{web, HttpRequest, HttpResponse, HttpMessage};
-use crate::middleware::auth::{ApiKeyRole, ValidatedApiKey};
-use crate::tracking::RequestTracker;
+use crate::middleware::auth::{mask_key, ApiKeyRole, Scope, ValidatedApiKey};
+use crate::models::ApiError;
+use crate::tracking::{AggregateStats, ModelStats, RequestTracker};
+use crate::providers::CacheMetrics;
+use crate::providers::cache::CacheMetricsSnapshot;
+use crate::providers::QueueMetrics;
+use crate::providers::bounded::QueueMetricsSnapshot;
+use super::admin::{check_admin_intent, AdminConfig};
 use serde::Serialize;
-use std::sync::{RwLock};
+use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{error, info};
 
 
 #[derive(serde::Deserialize)]
 pub struct StatsQuery {
     pub key: Option<String>,
+    /// Admins only: return full keys instead of masked ones
+    #[serde(default)]
+    pub unmask: bool,
 }
 
 #[derive(Serialize)]
@@ -20,8 +31,20 @@ pub struct KeyStatsResponse {
     pub avg_latency_ms: f64,
     pub total_prompt_tokens: u64,
     pub total_completion_tokens: u64,
+    pub cache_hits: u64,
+    pub rate_limited_count: u64,
     pub last_request_timestamp: u64,
-    pub models_used: HashMap<String, u64>,
+    pub models_used: HashMap<String, ModelStats>,
+    /// Per end user, for requests that sent `user`
+    pub users: HashMap<String, ModelStats>,
+    pub requests_last_hour: u64,
+    pub requests_last_24h: u64,
+}
+
+#[derive(Serialize)]
+pub struct AllStatsResponse {
+    pub summary: AggregateStats,
+    pub keys: Vec<KeyStatsResponse>,
 }
 
 pub async fn get_stats(
@@ -42,33 +65,42 @@ pub async fn get_stats(
     // 3. Branch based on role
     match validated.role {
         ApiKeyRole::Admin => {
+            if query.unmask {
+                info!("Admin key {} requested unmasked stats", mask_key(&validated.key));
+            }
             match &query.key {
                 // Admin requesting specific key's stats
                 Some(target_key) => {
                     match tracker_guard.get_stats(target_key) {
                         Some(stats) => {
-                            let response = build_stats_response(target_key, stats);
+                            let response = build_stats_response(&tracker_guard, target_key, stats, query.unmask);
                             HttpResponse::Ok().json(response)
                         }
                         None => HttpResponse::NotFound().body("No stats for that key"),
                     }
                 }
-                // Admin requesting all stats
+                // Admin requesting all stats, with fleet-wide totals
                 None => {
-                    let all_stats: Vec<KeyStatsResponse> = tracker_guard
+                    let keys: Vec<KeyStatsResponse> = tracker_guard
                         .get_all_stats()
                         .iter()
-                        .map(|(key, stats)| build_stats_response(key, stats))
+                        .map(|(key, stats)| build_stats_response(&tracker_guard, key, stats, query.unmask))
                         .collect();
-                    HttpResponse::Ok().json(all_stats)
+                    HttpResponse::Ok().json(AllStatsResponse {
+                        summary: tracker_guard.aggregate(),
+                        keys,
+                    })
                 }
             }
         }
-        ApiKeyRole::User => {
-            // Users can only see their own stats, ignore query.key
+        role if !role.allows(Scope::Stats) => {
+            HttpResponse::Forbidden().json(ApiError::insufficient_scope(role.name(), "read stats"))
+        }
+        _ => {
+            // Users can only see their own stats, ignore query.key and query.unmask
             match tracker_guard.get_stats(&validated.key) {
                 Some(stats) => {
-                    let response = build_stats_response(&validated.key, stats);
+                    let response = build_stats_response(&tracker_guard, &validated.key, stats, false);
                     HttpResponse::Ok().json(response)
                 }
                 None => {
@@ -81,8 +113,13 @@ pub async fn get_stats(
                         avg_latency_ms: 0.0,
                         total_prompt_tokens: 0,
                         total_completion_tokens: 0,
+                        cache_hits: 0,
+                        rate_limited_count: 0,
                         last_request_timestamp: 0,
                         models_used: HashMap::new(),
+                        users: HashMap::new(),
+                        requests_last_hour: 0,
+                        requests_last_24h: 0,
                     })
                 }
             }
@@ -90,7 +127,101 @@ pub async fn get_stats(
     }
 }
 
-fn build_stats_response(key: &str, stats: &crate::tracking::KeyStats) -> KeyStatsResponse {
+pub async fn reset_stats(
+    req: HttpRequest,
+    query: web::Query<StatsQuery>,
+    tracker: web::Data<RwLock<RequestTracker>>,
+    admin_config: web::Data<AdminConfig>,
+) -> HttpResponse {
+    let validated_key = req.extensions().get::<ValidatedApiKey>().cloned();
+
+    let Some(validated) = validated_key else {
+        return HttpResponse::Unauthorized().body("Missing API key context");
+    };
+
+    if !matches!(validated.role, ApiKeyRole::Admin) {
+        return HttpResponse::Forbidden().body("Admin API key required");
+    }
+
+    if let Some(response) = check_admin_intent(&req, &admin_config) {
+        return response;
+    }
+
+    let mut tracker_guard = tracker.write().unwrap();
+
+    match &query.key {
+        Some(target_key) => {
+            if !tracker_guard.reset_key(target_key) {
+                return HttpResponse::NotFound().body("No stats for that key");
+            }
+            info!("Stats reset for key {}", mask_key(target_key));
+        }
+        None => {
+            tracker_guard.reset();
+            info!("All stats reset");
+        }
+    }
+
+    // Persist right away so the reset survives a restart
+    if let Err(e) = tracker_guard.save_to_file(&admin_config.stats_file) {
+        error!("Failed to persist stats after reset: {}", e);
+        return HttpResponse::InternalServerError().body("Stats reset but could not be saved");
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Serialize)]
+pub struct StatsSummaryResponse {
+    pub keys: usize,
+    pub request_count: u64,
+    pub error_count: u64,
+    pub total_prompt_tokens: u64,
+    pub total_completion_tokens: u64,
+    /// `null` when the response cache is disabled
+    pub cache: Option<CacheMetricsSnapshot>,
+    /// `null` unless MAX_CONCURRENT_REQUESTS is set
+    pub queue: Option<QueueMetricsSnapshot>,
+}
+
+/// Gateway-wide totals across all keys (admin only).
+pub async fn stats_summary(
+    req: HttpRequest,
+    tracker: web::Data<RwLock<RequestTracker>>,
+    cache: web::Data<Option<Arc<CacheMetrics>>>,
+    queue: web::Data<Option<Arc<QueueMetrics>>>,
+) -> HttpResponse {
+    let validated_key = req.extensions().get::<ValidatedApiKey>().cloned();
+
+    let Some(validated) = validated_key else {
+        return HttpResponse::Unauthorized().body("Missing API key context");
+    };
+
+    if !matches!(validated.role, ApiKeyRole::Admin) {
+        return HttpResponse::Forbidden().body("Admin API key required");
+    }
+
+    HttpResponse::Ok().json(build_summary(&tracker.read().unwrap(), cache.get_ref(), queue.get_ref()))
+}
+
+pub(super) fn build_summary(
+    tracker: &RequestTracker,
+    cache: &Option<Arc<CacheMetrics>>,
+    queue: &Option<Arc<QueueMetrics>>,
+) -> StatsSummaryResponse {
+    let totals = tracker.aggregate();
+    StatsSummaryResponse {
+        keys: totals.active_keys,
+        request_count: totals.request_count,
+        error_count: totals.error_count,
+        total_prompt_tokens: totals.total_prompt_tokens,
+        total_completion_tokens: totals.total_completion_tokens,
+        cache: cache.as_ref().map(|c| c.snapshot()),
+        queue: queue.as_ref().map(|q| q.snapshot()),
+    }
+}
+
+fn build_stats_response(tracker: &RequestTracker, key: &str, stats: &crate::tracking::KeyStats, unmask: bool) -> KeyStatsResponse {
     let avg_latency = if stats.request_count > 0 {
         stats.total_latency_ms as f64 / stats.request_count as f64
     } else {
@@ -104,24 +235,74 @@ fn build_stats_response(key: &str, stats: &crate::tracking::KeyStats) -> KeyStat
         .as_millis() as u64;
 
     KeyStatsResponse {
-        api_key: mask_key(key),
+        api_key: if unmask { key.to_string() } else { mask_key(key) },
         request_count: stats.request_count,
         error_count: stats.error_count,
         total_latency_ms: stats.total_latency_ms,
         avg_latency_ms: avg_latency,
         total_prompt_tokens: stats.total_prompt_tokens,
         total_completion_tokens: stats.total_completion_tokens,
+        cache_hits: stats.cache_hits,
+        rate_limited_count: stats.rate_limited_count,
         last_request_timestamp: timestamp,
         models_used: stats.models_used.clone(),
+        users: stats.users.clone(),
+        requests_last_hour: tracker.stats_last(key, Duration::from_secs(60 * 60)).requests,
+        requests_last_24h: tracker.stats_last(key, Duration::from_secs(24 * 60 * 60)).requests,
     }
 }
 
-fn mask_key(key: &str) -> String {
-    if key.len() <= 8 {
-        "***".to_string()
-    } else {
-        let prefix = &key[..4];
-        let suffix = &key[key.len() - 4..];
-        format!("{}***{}", prefix, suffix)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{request, StubProvider};
+    use crate::providers::{CacheProvider, LLMProvider};
+    use crate::handlers::metrics;
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+
+    fn admin_request() -> HttpRequest {
+        let req = TestRequest::get().to_http_request();
+        req.extensions_mut().insert(ValidatedApiKey { key: String::from("admin-key"), role: ApiKeyRole::Admin, allowed_models: None });
+        req
+    }
+
+    /// A cache of one entry after a miss, a hit, and a miss that evicts the first entry.
+    async fn used_cache() -> web::Data<Option<Arc<CacheMetrics>>> {
+        let cache = CacheProvider::new(Arc::new(StubProvider::new("Hi")), Duration::from_secs(60), 1);
+        cache.chat(request("llama3.2")).await.unwrap();
+        assert!(cache.chat(request("llama3.2")).await.unwrap().cached);
+        cache.chat(request("mistral")).await.unwrap();
+        web::Data::new(Some(cache.metrics()))
+    }
+
+    fn tracker() -> web::Data<RwLock<RequestTracker>> {
+        web::Data::new(RwLock::new(RequestTracker::new()))
+    }
+
+    #[actix_web::test]
+    async fn summary_reports_cache_counts() {
+        let res = stats_summary(admin_request(), tracker(), used_cache().await, web::Data::new(None)).await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["cache"], serde_json::json!({"hits": 1, "misses": 2, "evictions": 1, "entries": 1}));
+    }
+
+    #[actix_web::test]
+    async fn metrics_report_cache_counts() {
+        let res = metrics(admin_request(), tracker(), used_cache().await, web::Data::new(None)).await;
+        let body = String::from_utf8(to_bytes(res.into_body()).await.unwrap().to_vec()).unwrap();
+        let lines: Vec<&str> = body.lines().filter(|line| line.starts_with("gateway_cache_")).collect();
+        assert_eq!(
+            lines,
+            ["gateway_cache_hits_total 1", "gateway_cache_misses_total 2", "gateway_cache_evictions_total 1", "gateway_cache_entries 1"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn summary_without_a_cache_reports_null() {
+        let res = stats_summary(admin_request(), tracker(), web::Data::new(None), web::Data::new(None)).await;
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert!(body["cache"].is_null());
     }
-}
\ No newline at end of file
+}
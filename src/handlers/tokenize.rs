@@ -0,0 +1,76 @@
+use actix_web::{web, HttpResponse};
+use crate::models::{Message, TokenizeRequest, TokenizeResponse};
+use super::chat::ChatConfig;
+
+/// Framing OpenAI adds around every message, and once to prime the reply
+const TOKENS_PER_MESSAGE: u32 = 3;
+const REPLY_PRIMING_TOKENS: u32 = 3;
+/// What OpenAI charges for a low-detail image; larger images cost more
+const TOKENS_PER_IMAGE: u32 = 85;
+
+/// Rough prompt size for a chat request, without calling upstream, so clients can
+/// check context limits and budget before sending it. Every model gets the
+/// heuristic for now, OpenAI ones included; they have no exact tokenizer yet.
+pub async fn tokenize(config: web::Data<ChatConfig>, body: web::Json<TokenizeRequest>) -> HttpResponse {
+    let request = body.into_inner();
+    HttpResponse::Ok().json(TokenizeResponse {
+        model: config.resolve_model(&request.model),
+        prompt_tokens: estimate_prompt_tokens(&request.messages),
+        method: "heuristic",
+    })
+}
+
+/// About four characters per token, which holds up for English text with most
+/// tokenizers; expect it to be off for code and other languages.
+fn estimate_prompt_tokens(messages: &[Message]) -> u32 {
+    let per_message = messages.iter().map(|message| {
+        let chars = message.content.text().chars().count() as u32;
+        let images = message.content.image_urls().count() as u32;
+        TOKENS_PER_MESSAGE + chars.div_ceil(4) + images * TOKENS_PER_IMAGE
+    });
+    per_message.sum::<u32>() + REPLY_PRIMING_TOKENS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ContentPart, ImageUrl, MessageContent};
+
+    fn message(content: MessageContent) -> Message {
+        Message {
+            role: String::from("user"),
+            content,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    #[test]
+    fn text_costs_a_token_per_four_characters_plus_framing() {
+        let messages = [
+            message(String::from("Hello").into()),
+            message(String::from("12345678").into()),
+        ];
+        // 2 + 2 for the text, 3 per message, 3 to prime the reply
+        assert_eq!(estimate_prompt_tokens(&messages), 2 + 2 + 3 * 2 + 3);
+    }
+
+    #[test]
+    fn images_add_a_flat_cost() {
+        let content = MessageContent::Parts(vec![
+            ContentPart::Text { text: String::from("What is this?") },
+            ContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: String::from("data:image/png;base64,AAAA"),
+                    detail: None,
+                },
+            },
+        ]);
+        assert_eq!(estimate_prompt_tokens(&[message(content)]), 4 + TOKENS_PER_IMAGE + 3 + 3);
+    }
+
+    #[test]
+    fn no_messages_cost_only_the_reply_priming() {
+        assert_eq!(estimate_prompt_tokens(&[]), REPLY_PRIMING_TOKENS);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+use time::{Date, OffsetDateTime};
+use tracing_subscriber::fmt::MakeWriter;
+
+enum Message {
+    Line(Vec<u8>),
+    /// Flush everything queued so far, then acknowledge
+    Flush(Sender<()>),
+}
+
+/// Log file writer that rotates daily (`<path>.YYYY-MM-DD`, UTC).
+/// Events are handed to a background thread, so logging never blocks on disk I/O.
+#[derive(Clone)]
+pub struct DailyFileWriter {
+    tx: Sender<Message>,
+}
+
+/// Flushes queued log lines to disk when dropped. Hold it for the lifetime of the
+/// program, otherwise the last lines before exit can be lost.
+pub struct LogFileGuard {
+    tx: Sender<Message>,
+}
+
+impl DailyFileWriter {
+    pub fn spawn(path: &str) -> io::Result<(Self, LogFileGuard)> {
+        let today = OffsetDateTime::now_utc().date();
+        let file = open_for(path, today)?;
+        let path = path.to_string();
+        let (tx, rx) = mpsc::channel::<Message>();
+
+        std::thread::spawn(move || write_loop(rx, &path, file, today));
+
+        let guard = LogFileGuard { tx: tx.clone() };
+        Ok((Self { tx }, guard))
+    }
+}
+
+fn write_loop(rx: Receiver<Message>, path: &str, mut file: BufWriter<File>, mut current: Date) {
+    while let Ok(mut message) = rx.recv() {
+        // Write everything already queued, then flush once
+        loop {
+            match message {
+                Message::Line(line) => {
+                    let today = OffsetDateTime::now_utc().date();
+                    if today != current {
+                        let _ = file.flush();
+                        match open_for(path, today) {
+                            Ok(next) => {
+                                file = next;
+                                current = today;
+                            }
+                            Err(e) => eprintln!("Failed to rotate log file: {}", e),
+                        }
+                    }
+                    if let Err(e) = file.write_all(&line) {
+                        eprintln!("Failed to write log file: {}", e);
+                    }
+                }
+                Message::Flush(ack) => {
+                    let _ = file.flush();
+                    let _ = ack.send(());
+                }
+            }
+
+            message = match rx.try_recv() {
+                Ok(next) => next,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            };
+        }
+        let _ = file.flush();
+    }
+}
+
+fn open_for(path: &str, date: Date) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{}.{}", path, date))?;
+    Ok(BufWriter::new(file))
+}
+
+impl Drop for LogFileGuard {
+    fn drop(&mut self) {
+        let (ack_tx, ack_rx) = mpsc::channel();
+        if self.tx.send(Message::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv_timeout(Duration::from_secs(2));
+        }
+    }
+}
+
+/// Buffers one formatted event and queues it as a whole when tracing drops the writer.
+pub struct EventWriter {
+    tx: Sender<Message>,
+    buf: Vec<u8>,
+}
+
+impl Write for EventWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for EventWriter {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            let _ = self.tx.send(Message::Line(std::mem::take(&mut self.buf)));
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for DailyFileWriter {
+    type Writer = EventWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        EventWriter {
+            tx: self.tx.clone(),
+            buf: Vec::new(),
+        }
+    }
+}
@@ -1,146 +1,1302 @@
+mod dead_letter;
 mod handlers;
+mod logging;
 mod middleware;
 mod models;
 mod providers;
+mod sessions;
+mod spans;
 mod tracking;
+mod transform;
 
 use crate::{
-    middleware::{AuthMiddleware, RateLimitMiddleware, RateLimiter, TrackingMiddleware},
-    tracking::RequestTracker,
+    dead_letter::DeadLetterLog,
+    logging::DailyFileWriter,
+    middleware::{
+        ApiKeyRole, AuthMiddleware, ConcurrencyLimitMiddleware, ConcurrencyLimiter, CorsConfig,
+        CorsMiddleware, KeySet, LimitDetailsMiddleware, Limiter, PerKeyLimiter,
+        RateLimitMiddleware, RateLimiter, RequestIdMiddleware, SlidingWindowLimiter,
+        SpanMiddleware, TrackingMiddleware,
+    },
+    models::ApiError,
+    sessions::SessionStore,
+    spans::SpanExporter,
+    tracking::{
+        sink::{flush_stats, StatsSink},
+        RequestTracker,
+    },
+    transform::{AppendDisclaimer, InjectSystemPrompt, RequestTransformer, ResponseTransformer},
+};
+use handlers::{
+    add_key, admin_info, chat_completions, dead_letters, delete_key, delete_session, embeddings,
+    get_stats, list_keys, list_models, liveness, metrics, provider_health, reset_stats, shutdown,
+    stats_summary, tokenize, unsupported_method, AdminConfig, ChatConfig, EmbeddingsConfig,
+    HealthBackends, ModelsSource, BUILD_TIMESTAMP, GIT_SHA, VERSION,
+};
+use providers::{
+    ollama::OllamaProvider, openai::OpenAIProvider, BodyLogging, BoundedProvider, CacheMetrics,
+    CacheProvider, FallbackProvider, HealthAwareProvider, HttpVersion, LLMProvider,
+    LoadBalancerProvider, MockProvider, PoolSettings, ProviderTimeouts, QueueMetrics,
+    RetryProvider, Route, RoutingProvider, StreamUsage, SubstitutionProvider,
+    WeightedRouterProvider,
 };
-use handlers::{chat_completions, get_stats};
-use providers::{ollama::OllamaProvider, openai::OpenAIProvider, FallbackProvider, LLMProvider};
 
-use actix_web::{middleware::Logger, web, App, HttpResponse, HttpServer};
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::middleware::{Compress, Condition, Logger};
+use actix_web::{web, App, HttpResponse, HttpServer};
 use dotenv::dotenv;
+use std::collections::HashMap;
 use std::env;
 use std::sync::{Arc, RwLock};
-use tracing::info;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+/// Resolves on SIGINT (Ctrl+C) or, on Unix, SIGTERM (e.g. `docker stop`).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = actix_web::rt::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Failed to install SIGTERM handler: {}", e),
+        }
+    }
+
+    if let Err(e) = actix_web::rt::signal::ctrl_c().await {
+        error!("Failed to listen for Ctrl+C: {}", e);
+    }
+}
+
+/// Read a numeric env var, falling back to `default` when unset or invalid.
+fn env_u64(name: &str, default: u64) -> u64 {
+    match env::var(name) {
+        Ok(raw) => raw.trim().parse().unwrap_or_else(|_| {
+            warn!(
+                "Invalid value '{}' for {}, using default {}",
+                raw, name, default
+            );
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Read a boolean env var ("true"/"1"), falling back to `default` when unset.
+fn env_bool(name: &str, default: bool) -> bool {
+    match env::var(name) {
+        Ok(raw) => matches!(raw.trim().to_lowercase().as_str(), "true" | "1" | "yes"),
+        Err(_) => default,
+    }
+}
+
+/// Read an upstream HTTP version; unlike other settings an invalid value stops startup.
+fn http_version_from_env(name: &str) -> std::io::Result<HttpVersion> {
+    match env::var(name) {
+        Ok(raw) => raw.parse().map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid {}: {}", name, e),
+            )
+        }),
+        Err(_) => Ok(HttpVersion::Auto),
+    }
+}
+
+/// JSON body limit for every endpoint; oversized bodies get an OpenAI-shaped 413.
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(move |err, _req| match err {
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                warn!("Rejected request body over {} bytes", limit);
+                let response =
+                    HttpResponse::PayloadTooLarge().json(ApiError::request_too_large(limit));
+                InternalError::from_response(err, response).into()
+            }
+            err => err.into(),
+        })
+}
+
+/// Cap concurrent upstream calls to `provider` when `var` is set to a non-zero value.
+fn bound_concurrency(
+    provider: Arc<dyn LLMProvider>,
+    name: &str,
+    var: &str,
+    queue_timeout: Duration,
+) -> Arc<dyn LLMProvider> {
+    match env_u64(var, 0) {
+        0 => provider,
+        max => {
+            info!("{} limited to {} concurrent upstream requests", name, max);
+            Arc::new(BoundedProvider::new(
+                provider,
+                name,
+                max as usize,
+                queue_timeout,
+            ))
+        }
+    }
+}
+
+/// How token buckets are shaped: `STRICT_RATE_LIMIT` and `RATE_LIMIT_BURST`.
+#[derive(Debug, Clone, Copy)]
+struct BucketShape {
+    strict: bool,
+    burst: Option<u64>,
+}
+
+/// `limit` requests per `window` for each key, with the `strategy` algorithm unless the
+/// key has an override (`key`, `bucket|sliding`). One limiter per algorithm is shared
+/// by every key using it.
+fn keyed_limiter(
+    limit: u64,
+    window: Duration,
+    shape: BucketShape,
+    strategy: &str,
+    overrides: &[(String, String)],
+) -> std::io::Result<Arc<dyn Limiter>> {
+    let per_minute = window == Duration::from_secs(60);
+    let bucket: Arc<dyn Limiter> = if shape.strict {
+        Arc::new(RateLimiter::strict(limit, window))
+    } else if let Some(burst) = shape.burst {
+        Arc::new(if per_minute {
+            RateLimiter::with_burst(limit, burst)
+        } else {
+            RateLimiter::with_window_and_burst(limit, window, burst)
+        })
+    } else if per_minute {
+        Arc::new(RateLimiter::new(limit)) // RPM
+    } else {
+        Arc::new(RateLimiter::with_window(limit, window))
+    };
+    let sliding: Arc<dyn Limiter> = Arc::new(SlidingWindowLimiter::with_window(limit, window));
+    let named = |name: &str| -> std::io::Result<Arc<dyn Limiter>> {
+        match name.trim() {
+            "bucket" => Ok(bucket.clone()),
+            "sliding" => Ok(sliding.clone()),
+            other => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown rate limit algorithm '{}' (expected bucket or sliding)",
+                    other
+                ),
+            )),
+        }
+    };
 
-async fn health() -> HttpResponse {
-    HttpResponse::Ok().body("ok")
+    let default = named(strategy)?;
+    if overrides.is_empty() {
+        return Ok(default);
+    }
+    let mut by_key = HashMap::new();
+    for (key, algo) in overrides {
+        by_key.insert(key.clone(), named(algo)?);
+    }
+    Ok(Arc::new(PerKeyLimiter::new(default, by_key)))
+}
+
+/// Split a comma-separated env value into trimmed, non-empty entries.
+fn split_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Header list like `X-Title=My App,HTTP-Referer=https://example.com`.
+fn parse_headers(var: &str, raw: &str) -> std::io::Result<reqwest::header::HeaderMap> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for entry in split_list(raw) {
+        let parsed = entry.split_once('=').and_then(|(name, value)| {
+            Some((
+                reqwest::header::HeaderName::from_bytes(name.trim().as_bytes()).ok()?,
+                reqwest::header::HeaderValue::from_str(value.trim()).ok()?,
+            ))
+        });
+        let Some((name, value)) = parsed else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid {} entry '{}' (expected Name=value)", var, entry),
+            ));
+        };
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Per-model upstream timeouts, e.g. `llama3.1:70b=120,llama3.2=20`. The last `=`
+/// splits, since model tags never contain one. Invalid entries are skipped.
+fn parse_model_timeouts(raw: &str) -> HashMap<String, Duration> {
+    let mut timeouts = HashMap::new();
+    for entry in split_list(raw) {
+        match entry
+            .rsplit_once('=')
+            .map(|(model, secs)| (model.trim(), secs.trim().parse::<u64>()))
+        {
+            Some((model, Ok(secs))) if !model.is_empty() => {
+                timeouts.insert(model.to_string(), Duration::from_secs(secs));
+            }
+            _ => warn!("Ignoring invalid MODEL_TIMEOUTS entry '{}'", entry),
+        }
+    }
+    timeouts
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let log_format = std::env::var("LOG_FORMAT").unwrap_or_else(|_| "text".to_string());
+    // Loaded first so logging settings can come from .env too
+    dotenv().ok();
+
+    let log_json = std::env::var("LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json"));
     let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
 
-    if log_format.to_lowercase() == "json" {
-        tracing_subscriber::fmt()
-            .json()
-            .with_env_filter(env_filter)
-            .init();
+    // Optional daily-rotated log file next to stdout; the guard flushes it on exit
+    let (log_file, _log_guard) = match env::var("LOG_FILE") {
+        Ok(path) => match DailyFileWriter::spawn(&path) {
+            Ok((writer, guard)) => (Some(writer), Some(guard)),
+            Err(e) => {
+                eprintln!("Failed to open LOG_FILE '{}': {}", path, e);
+                (None, None)
+            }
+        },
+        Err(_) => (None, None),
+    };
+
+    let stdout_layer = if log_json {
+        tracing_subscriber::fmt::layer().json().boxed()
     } else {
-        tracing_subscriber::fmt().with_env_filter(env_filter).init();
-    }
+        tracing_subscriber::fmt::layer().boxed()
+    };
+    let file_layer = log_file.map(|writer| {
+        if log_json {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(writer)
+                .boxed()
+        } else {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer)
+                .boxed()
+        }
+    });
 
-    dotenv().ok();
+    tracing_subscriber::registry()
+        .with(stdout_layer)
+        .with(file_layer)
+        .with(env_filter)
+        .init();
+
+    info!(
+        "Starting ai-gateway v{} (git {}, built at {})",
+        VERSION, GIT_SHA, BUILD_TIMESTAMP
+    );
 
     let raw_keys = env::var("GATEWAY_API_KEYS").unwrap_or_else(|_| "secret-key".to_string());
     let raw_admin_keys = env::var("ADMIN_API_KEYS").unwrap_or_else(|_| String::new());
 
-    let api_keys: Vec<String> = raw_keys
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
-
-    let admin_keys: Vec<String> = raw_admin_keys
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
+    let api_keys = split_list(&raw_keys);
+    let admin_keys = split_list(&raw_admin_keys);
 
     info!("Loaded {} API keys.", api_keys.len());
     info!("Loaded {} admin API keys.", admin_keys.len());
 
-    let ollama_provider = Arc::new(OllamaProvider::new(
-        env::var("OLLAMA_BASE_URL").unwrap_or_else(|_| "http://localhost:11434".to_string()),
-    ));
+    // Keys added or removed through /v1/keys are saved to API_KEYS_FILE, which then takes
+    // precedence over the env vars on later starts
+    let seed_keys = KeySet::new(api_keys, admin_keys);
+
+    // Narrower roles for user keys, e.g. KEY_SCOPES="dash-key:read_only,bot-key:chat_only"
+    let mut scopes = HashMap::new();
+    for entry in split_list(&env::var("KEY_SCOPES").unwrap_or_default()) {
+        let role = entry.rsplit_once(':').and_then(|(key, role)| {
+            Some((key.trim().to_string(), ApiKeyRole::from_name(role.trim())?))
+        });
+        match role {
+            Some((key, role)) if !matches!(role, ApiKeyRole::Admin) => {
+                scopes.insert(key, role);
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Invalid KEY_SCOPES entry (expected key:read_only or key:chat_only; admin keys go in ADMIN_API_KEYS)",
+                ));
+            }
+        }
+    }
+    if !scopes.is_empty() {
+        info!("Loaded scopes for {} keys", scopes.len());
+    }
+    let key_set = match env::var("API_KEYS_FILE") {
+        Ok(path) => {
+            let keys = KeySet::load_or_seed(&path, seed_keys)?;
+            info!("API keys persisted to {}", path);
+            keys
+        }
+        Err(_) => seed_keys,
+    };
+    let key_set = Arc::new(RwLock::new(key_set.with_scopes(scopes)));
+
+    // Models each key may use, e.g. KEY_ALLOWED_MODELS="key-a:llama3.2|mistral,key-b:gpt-4o";
+    // unlisted keys and admin keys may use any model
+    let mut allowed_models = HashMap::new();
+    for entry in split_list(&env::var("KEY_ALLOWED_MODELS").unwrap_or_default()) {
+        let Some((key, models)) = entry.split_once(':') else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid KEY_ALLOWED_MODELS entry (expected key:model1|model2)",
+            ));
+        };
+        let models: Vec<String> = models
+            .split('|')
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect();
+        allowed_models.insert(key.trim().to_string(), models);
+    }
+    if !allowed_models.is_empty() {
+        info!("Loaded model allowlists for {} keys", allowed_models.len());
+    }
+    let allowed_models = Arc::new(allowed_models);
+
+    // Cumulative tokens per key per calendar month, e.g. KEY_MONTHLY_TOKEN_QUOTAS="key-a:1000000"
+    let mut monthly_token_quotas = HashMap::new();
+    for entry in split_list(&env::var("KEY_MONTHLY_TOKEN_QUOTAS").unwrap_or_default()) {
+        let Some((key, quota)) = entry
+            .rsplit_once(':')
+            .and_then(|(key, quota)| Some((key.trim(), quota.trim().parse::<u64>().ok()?)))
+        else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid KEY_MONTHLY_TOKEN_QUOTAS entry (expected key:tokens)",
+            ));
+        };
+        monthly_token_quotas.insert(key.to_string(), quota);
+    }
+    if !monthly_token_quotas.is_empty() {
+        info!(
+            "Loaded monthly token quotas for {} keys",
+            monthly_token_quotas.len()
+        );
+    }
+
+    let timeouts = ProviderTimeouts {
+        request: Duration::from_secs(env_u64("PROVIDER_TIMEOUT_SECS", 120)),
+        connect: Duration::from_secs(env_u64("CONNECT_TIMEOUT_SECS", 10)),
+    };
+    let ollama_http_version = http_version_from_env("OLLAMA_HTTP_VERSION")?;
+    let openai_http_version = http_version_from_env("OPENAI_HTTP_VERSION")?;
+    let pool = PoolSettings {
+        max_idle_per_host: env_u64("PROVIDER_POOL_MAX_IDLE", 32) as usize,
+        idle_timeout: Duration::from_secs(env_u64("PROVIDER_POOL_IDLE_TIMEOUT_SECS", 90)),
+        tcp_keepalive: Duration::from_secs(env_u64("PROVIDER_TCP_KEEPALIVE_SECS", 60).max(1)),
+    };
+
+    // One pooled client for every upstream; a second only if OpenAI needs another HTTP version
+    let ollama_client = timeouts.build_client(ollama_http_version, &pool);
+    let openai_client = if openai_http_version == ollama_http_version {
+        ollama_client.clone()
+    } else {
+        timeouts.build_client(openai_http_version, &pool)
+    };
+
+    // Comment lines on idle Ollama streams; off unless SSE_KEEPALIVE_SECS is set
+    let sse_keepalive = match env_u64("SSE_KEEPALIVE_SECS", 0) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+
+    // Upstream request/response bodies at debug level, cut to LOG_BODIES_MAX_LEN bytes
+    let body_logging = if env_bool("LOG_BODIES", false) {
+        warn!("LOG_BODIES is on: upstream request and response bodies are logged at debug level");
+        Some(BodyLogging {
+            max_len: env_u64("LOG_BODIES_MAX_LEN", 4096) as usize,
+        })
+    } else {
+        None
+    };
+
+    // How long a request waits for a slot once an upstream hits its *_MAX_CONCURRENT
+    let queue_timeout = Duration::from_millis(env_u64("PROVIDER_QUEUE_TIMEOUT_MS", 1000));
 
-    let openai_provider =
+    // Several Ollama instances are load-balanced round-robin, or used in order with
+    // failover when OLLAMA_BALANCE_STRATEGY=failover; otherwise use the single URL
+    let ollama_urls = split_list(&env::var("OLLAMA_BASE_URLS").unwrap_or_default());
+    let balance_strategy =
+        env::var("OLLAMA_BALANCE_STRATEGY").unwrap_or_else(|_| String::from("round_robin"));
+    let ollama_provider: Arc<dyn LLMProvider> = if ollama_urls.len() > 1 {
+        let backends: Vec<Arc<dyn LLMProvider>> = ollama_urls
+            .into_iter()
+            .map(|url| {
+                // Each instance gets its own cap
+                bound_concurrency(
+                    Arc::new(
+                        OllamaProvider::new(url, ollama_client.clone(), timeouts)
+                            .with_keepalive(sse_keepalive)
+                            .with_body_logging(body_logging),
+                    ),
+                    "Ollama",
+                    "OLLAMA_MAX_CONCURRENT",
+                    queue_timeout,
+                )
+            })
+            .collect();
+        match balance_strategy.trim() {
+            "round_robin" => {
+                info!("Load balancing across {} Ollama instances", backends.len());
+                Arc::new(LoadBalancerProvider::new(
+                    backends,
+                    env_u64("LOAD_BALANCER_MAX_TRIES", 1) as usize,
+                ))
+            }
+            "failover" => {
+                info!("Failing over across {} Ollama instances", backends.len());
+                Arc::new(HealthAwareProvider::new(
+                    backends,
+                    Duration::from_secs(env_u64("BACKEND_COOLDOWN_SECS", 30)),
+                ))
+            }
+            other => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Unknown OLLAMA_BALANCE_STRATEGY '{}' (expected round_robin or failover)",
+                        other
+                    ),
+                ));
+            }
+        }
+    } else {
+        let url = ollama_urls.into_iter().next().unwrap_or_else(|| {
+            env::var("OLLAMA_BASE_URL").unwrap_or_else(|_| "http://localhost:11434".to_string())
+        });
+        bound_concurrency(
+            Arc::new(
+                OllamaProvider::new(url, ollama_client.clone(), timeouts)
+                    .with_keepalive(sse_keepalive)
+                    .with_body_logging(body_logging),
+            ),
+            "Ollama",
+            "OLLAMA_MAX_CONCURRENT",
+            queue_timeout,
+        )
+    };
+
+    // Retry transient Ollama failures before falling back
+    let retry_attempts = env_u64("RETRY_MAX_ATTEMPTS", 3) as u32;
+    let ollama_provider: Arc<dyn LLMProvider> = if retry_attempts > 1 {
+        Arc::new(RetryProvider::new(
+            ollama_provider,
+            retry_attempts,
+            Duration::from_millis(env_u64("RETRY_BASE_BACKOFF_MS", 200)),
+        ))
+    } else {
+        ollama_provider
+    };
+
+    // Models to try instead when Ollama doesn't have one, e.g. MODEL_SUBSTITUTIONS="mistral=llama3.2"
+    let mut substitutions = HashMap::new();
+    for entry in split_list(&env::var("MODEL_SUBSTITUTIONS").unwrap_or_default()) {
+        match entry.split_once('=') {
+            Some((model, substitute))
+                if !model.trim().is_empty() && !substitute.trim().is_empty() =>
+            {
+                substitutions.insert(model.trim().to_string(), substitute.trim().to_string());
+            }
+            _ => warn!("Ignoring invalid MODEL_SUBSTITUTIONS entry '{}'", entry),
+        }
+    }
+    let ollama_provider: Arc<dyn LLMProvider> = if substitutions.is_empty() {
+        ollama_provider
+    } else {
+        info!("Loaded {} model substitutions", substitutions.len());
+        Arc::new(SubstitutionProvider::new(ollama_provider, substitutions))
+    };
+
+    let openai_provider: Option<Arc<dyn LLMProvider>> =
         if let (Ok(key), Ok(url)) = (env::var("OPENAI_API_KEY"), env::var("OPENAI_BASE_URL")) {
-            Some(Arc::new(OpenAIProvider::new(url, key)))
+            // For OpenAI-compatible servers that report usage per chunk rather than as a running total
+            let stream_usage = match env::var("OPENAI_STREAM_USAGE") {
+                Ok(raw) => raw.parse().unwrap_or_else(|e| {
+                    warn!("Invalid OPENAI_STREAM_USAGE: {}, using cumulative", e);
+                    StreamUsage::Cumulative
+                }),
+                Err(_) => StreamUsage::Cumulative,
+            };
+            // Other OpenAI-compatible upstreams, e.g. OPENAI_CHAT_PATH=/openai/v1/chat/completions
+            let mut provider = OpenAIProvider::new(url, key, openai_client, timeouts)
+                .with_stream_usage(stream_usage)
+                .with_body_logging(body_logging)
+                .with_extra_headers(parse_headers(
+                    "OPENAI_EXTRA_HEADERS",
+                    &env::var("OPENAI_EXTRA_HEADERS").unwrap_or_default(),
+                )?);
+            if let Ok(chat_path) = env::var("OPENAI_CHAT_PATH") {
+                provider = provider.with_chat_path(chat_path);
+            }
+            let provider = bound_concurrency(
+                Arc::new(provider),
+                "OpenAI",
+                "OPENAI_MAX_CONCURRENT",
+                queue_timeout,
+            );
+            // Off by default; 429s are then retried after the upstream's Retry-After
+            let openai_retry_attempts = env_u64("OPENAI_RETRY_MAX_ATTEMPTS", 1) as u32;
+            if openai_retry_attempts > 1 {
+                Some(Arc::new(RetryProvider::new(
+                    provider,
+                    openai_retry_attempts,
+                    Duration::from_millis(env_u64("RETRY_BASE_BACKOFF_MS", 200)),
+                )) as Arc<dyn LLMProvider>)
+            } else {
+                Some(provider)
+            }
         } else {
             None
         };
 
+    // PROVIDER=mock answers every request locally, for tests and UI work without a model
+    let mock_provider: Option<Arc<dyn LLMProvider>> = match env::var("PROVIDER") {
+        Ok(name) if name.trim() == "mock" => {
+            warn!("PROVIDER=mock: responses echo the request, no upstream is called");
+            Some(Arc::new(MockProvider))
+        }
+        _ => None,
+    };
+
+    // Reported individually by /v1/health, and what X-Provider pins a request to
+    let health_backends = match &mock_provider {
+        Some(mock) => vec![("mock".to_string(), mock.clone())],
+        None => {
+            let mut backends = vec![("ollama".to_string(), ollama_provider.clone())];
+            if let Some(openai) = &openai_provider {
+                backends.push(("openai".to_string(), openai.clone()));
+            }
+            backends
+        }
+    };
+    let mut health_backends = HealthBackends(health_backends);
+
     // Default strategy: Try Ollama, allow fallback to OpenAI if configured
-    let provider: Arc<dyn LLMProvider> = if let Some(secondary) = openai_provider {
+    let provider: Arc<dyn LLMProvider> = if let Some(secondary) = openai_provider.clone() {
         // If we have both, use FallbackProvider
         // We configure a default OpenAI model for fallback in case the original model (e.g. local LLM) doesn't exist in OpenAI
-        Arc::new(FallbackProvider::new(
-            ollama_provider,
+        let fallback = FallbackProvider::new(
+            ollama_provider.clone(),
             secondary,
             Some("gpt-4.1-nano".to_string()),
-        ))
+        );
+        // FALLBACK_HEDGE_DELAY_MS also sends chats to OpenAI when Ollama is slower than that
+        match env_u64("FALLBACK_HEDGE_DELAY_MS", 0) {
+            0 => Arc::new(fallback),
+            delay_ms => {
+                info!("Hedging slow chats with OpenAI after {}ms", delay_ms);
+                Arc::new(fallback.with_hedge_delay(Duration::from_millis(delay_ms)))
+            }
+        }
     } else {
         // If only Ollama, just use Ollama
-        ollama_provider
+        ollama_provider.clone()
     };
 
     info!("AI Provider configured. Fallback strategy active if OpenAI keys present.");
 
-    let request_tracker = match RequestTracker::load_from_file("stats.json") {
+    // Traffic split instead of fallback, e.g. PROVIDER_WEIGHTS=ollama:0.9,openai:0.1.
+    // PROVIDER_WEIGHTS_SEED makes the sequence of picks reproducible.
+    let provider: Arc<dyn LLMProvider> = match env::var("PROVIDER_WEIGHTS") {
+        Ok(raw_weights) => {
+            let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+            let mut backends = Vec::new();
+            for entry in split_list(&raw_weights) {
+                let Some((name, weight)) = entry.split_once(':') else {
+                    return Err(invalid(format!(
+                        "Invalid PROVIDER_WEIGHTS entry '{}' (expected provider:weight)",
+                        entry
+                    )));
+                };
+                let backend = match name.trim() {
+                    "ollama" => ollama_provider.clone(),
+                    "openai" => openai_provider.clone().ok_or_else(|| {
+                        invalid(String::from(
+                            "PROVIDER_WEIGHTS uses openai, but OpenAI is not configured",
+                        ))
+                    })?,
+                    other => {
+                        return Err(invalid(format!(
+                            "Unknown provider '{}' in PROVIDER_WEIGHTS",
+                            other
+                        )))
+                    }
+                };
+                match weight.trim().parse::<f64>() {
+                    Ok(w) if w.is_finite() && w > 0.0 => backends.push((backend, w)),
+                    // A zero weight just leaves the provider out
+                    Ok(0.0) => {}
+                    _ => {
+                        return Err(invalid(format!(
+                            "Invalid weight '{}' in PROVIDER_WEIGHTS",
+                            weight
+                        )))
+                    }
+                }
+            }
+            if backends.is_empty() {
+                return Err(invalid(String::from(
+                    "PROVIDER_WEIGHTS needs at least one positive weight",
+                )));
+            }
+            let seed = env::var("PROVIDER_WEIGHTS_SEED")
+                .ok()
+                .and_then(|raw| raw.trim().parse().ok());
+            info!("Weighted routing enabled: {}", raw_weights);
+            Arc::new(WeightedRouterProvider::new(backends, seed))
+        }
+        Err(_) => provider,
+    };
+
+    // Optional model-based routing, e.g. ROUTES=gpt-*=openai,llama*=ollama.
+    // A target of provider:model also rewrites the model sent upstream
+    // (e.g. gpt-4=ollama:llama3.2). Unmatched models keep using the default strategy above.
+    let provider: Arc<dyn LLMProvider> = match env::var("ROUTES") {
+        Ok(raw_routes) => {
+            let mut named: HashMap<&str, Arc<dyn LLMProvider>> = HashMap::new();
+            named.insert("ollama", ollama_provider.clone());
+            if let Some(openai) = &openai_provider {
+                named.insert("openai", openai.clone());
+            }
+
+            let mut routes = HashMap::new();
+            for entry in split_list(&raw_routes) {
+                let Some((pattern, target)) = entry.split_once('=') else {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid ROUTES entry '{}', expected pattern=provider",
+                            entry
+                        ),
+                    ));
+                };
+                // Split on the first ':' only, Ollama model tags contain colons too
+                let (target, target_model) = match target.split_once(':') {
+                    Some((name, model)) => (name.trim(), Some(model.trim().to_string())),
+                    None => (target.trim(), None),
+                };
+                let Some(target_provider) = named.get(target) else {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("ROUTES entry '{}' targets unknown provider", entry),
+                    ));
+                };
+                routes.insert(
+                    pattern.trim().to_string(),
+                    Route {
+                        provider: target_provider.clone(),
+                        target_model,
+                    },
+                );
+            }
+
+            info!("Model routing enabled with {} routes", routes.len());
+            Arc::new(RoutingProvider::new(routes, provider))
+        }
+        Err(_) => provider,
+    };
+
+    // The mock replaces every upstream, including routed and weighted ones
+    let provider = mock_provider.unwrap_or(provider);
+
+    // Gateway-wide cap on upstream work, for a backend that can only take a few
+    // generations at once; extra requests queue rather than being rejected
+    let mut queue_metrics: Option<Arc<QueueMetrics>> = None;
+    let provider: Arc<dyn LLMProvider> = match env_u64("MAX_CONCURRENT_REQUESTS", 0) {
+        0 => provider,
+        max => {
+            let timeout = Duration::from_secs(env_u64("QUEUE_TIMEOUT_SECS", 30));
+            info!(
+                "Limiting to {} concurrent requests, queueing up to {:?}",
+                max, timeout
+            );
+            let bounded = BoundedProvider::new(provider, "Gateway", max as usize, timeout);
+            queue_metrics = Some(bounded.metrics());
+            // Requests pinned with X-Provider go straight to a backend, but still take a slot
+            for (_, backend) in health_backends.0.iter_mut() {
+                *backend = Arc::new(bounded.sharing(backend.clone()));
+            }
+            Arc::new(bounded)
+        }
+    };
+
+    // Response cache for identical non-streaming requests; CACHE_CAPACITY=0 disables it
+    let cache_capacity = env_u64("CACHE_CAPACITY", 0) as usize;
+    let mut cache_metrics: Option<Arc<CacheMetrics>> = None;
+    let provider: Arc<dyn LLMProvider> = if cache_capacity > 0 {
+        let ttl = Duration::from_secs(env_u64("CACHE_TTL_SECS", 300));
+        info!(
+            "Response cache enabled: {} entries, TTL {:?}",
+            cache_capacity, ttl
+        );
+        let cache = CacheProvider::new(provider, ttl, cache_capacity);
+        cache_metrics = Some(cache.metrics());
+        Arc::new(cache)
+    } else {
+        provider
+    };
+
+    // Somewhere writable, e.g. a mounted volume in a container
+    let stats_file = env::var("STATS_FILE").unwrap_or_else(|_| "stats.json".to_string());
+    let request_tracker = match RequestTracker::load_from_file(&stats_file) {
         Ok(tracker) => {
-            info!("Loaded existing request stats from stats.json");
+            info!("Loaded existing request stats from {}", stats_file);
             Arc::new(RwLock::new(tracker))
         }
-        Err(_) => {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
             info!("No existing stats found, starting fresh");
             Arc::new(RwLock::new(RequestTracker::new()))
         }
+        Err(e) => {
+            // Moved aside so the next save doesn't overwrite what couldn't be read
+            let saved_at = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let backup = format!("{}.unreadable-{}", stats_file, saved_at);
+            match std::fs::rename(&stats_file, &backup) {
+                Ok(()) => warn!(
+                    "Failed to load {} ({}), moved it to {} and starting fresh",
+                    stats_file, e, backup
+                ),
+                Err(rename_err) => warn!(
+                    "Failed to load {} ({}) or move it aside ({}), starting fresh",
+                    stats_file, e, rename_err
+                ),
+            }
+            Arc::new(RwLock::new(RequestTracker::new()))
+        }
+    };
+
+    // CORS stays off unless origins are configured.
+    let cors_config = match env::var("CORS_ALLOWED_ORIGINS") {
+        Ok(raw_origins) => {
+            let expose_headers = split_list(&env::var("CORS_EXPOSE_HEADERS").unwrap_or_default());
+            let config = CorsConfig::new(
+                split_list(&raw_origins),
+                expose_headers,
+                env_bool("CORS_ALLOW_CREDENTIALS", false),
+            )
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            info!("CORS enabled for origins: {}", raw_origins);
+            Some(config)
+        }
+        Err(_) => None,
+    };
+
+    let model_timeouts = parse_model_timeouts(&env::var("MODEL_TIMEOUTS").unwrap_or_default());
+
+    let mut transformers: Vec<Arc<dyn ResponseTransformer>> = Vec::new();
+    if let Some(disclaimer) = env::var("APPEND_DISCLAIMER").ok().filter(|d| !d.is_empty()) {
+        info!("Appending disclaimer to all completions");
+        transformers.push(Arc::new(AppendDisclaimer::new(&disclaimer)));
+    }
+
+    let mut request_transformers: Vec<Arc<dyn RequestTransformer>> = Vec::new();
+    if let Some(prompt) = env::var("SYSTEM_PROMPT").ok().filter(|p| !p.is_empty()) {
+        info!("Adding the configured system prompt to requests without one");
+        request_transformers.push(Arc::new(InjectSystemPrompt::new(&prompt)));
+    }
+
+    // Requests no provider could serve, one JSON line each, for later replay
+    let dead_letter_log = match env::var("DEAD_LETTER_FILE") {
+        Ok(path) => {
+            info!("Recording failed requests to {}", path);
+            Some(Arc::new(DeadLetterLog::spawn(
+                &path,
+                env_bool("DEAD_LETTER_REDACT", false),
+            )?))
+        }
+        Err(_) => None,
+    };
+
+    // Server-side history for clients that send X-Session-Id
+    let session_store = if env_bool("SESSIONS_ENABLED", false) {
+        let ttl = Duration::from_secs(env_u64("SESSION_TTL_SECS", 1800));
+        info!("Sessions enabled (TTL {:?})", ttl);
+        // History is resent with every turn, so by default it can't outgrow a request body
+        let max_bytes = env_u64("SESSION_MAX_BYTES", env_u64("MAX_BODY_BYTES", 1024 * 1024));
+        Some(Arc::new(
+            SessionStore::new(ttl, env_u64("SESSION_MAX_COUNT", 10_000) as usize)
+                .with_history_limits(
+                    env_u64("SESSION_MAX_MESSAGES", 200) as usize,
+                    max_bytes as usize,
+                ),
+        ))
+    } else {
+        None
+    };
+
+    // Friendly model names, e.g. MODEL_ALIASES="fast=llama3.2,smart=gpt-4o"
+    let mut model_aliases = HashMap::new();
+    for entry in split_list(&env::var("MODEL_ALIASES").unwrap_or_default()) {
+        match entry.split_once('=') {
+            Some((alias, model)) if !alias.trim().is_empty() && !model.trim().is_empty() => {
+                model_aliases.insert(alias.trim().to_string(), model.trim().to_string());
+            }
+            _ => warn!("Ignoring invalid MODEL_ALIASES entry '{}'", entry),
+        }
+    }
+    if !model_aliases.is_empty() {
+        info!("Loaded {} model aliases", model_aliases.len());
+    }
+
+    let chat_config = ChatConfig {
+        canned_fallback: if env_bool("CANNED_FALLBACK_ENABLED", false) {
+            Some(env::var("CANNED_FALLBACK_MESSAGE").unwrap_or_else(|_| {
+                "Service is temporarily unavailable, please try again".to_string()
+            }))
+        } else {
+            None
+        },
+        model_timeouts,
+        transformers,
+        request_transformers,
+        repair_alternation: env_bool("REPAIR_ALTERNATION", false),
+        max_tools: env_u64("MAX_TOOLS", 128) as usize,
+        max_tools_bytes: env_u64("MAX_TOOLS_BYTES", 256 * 1024) as usize,
+        max_n: env_u64("MAX_N", 8) as u32,
+        dead_letters: dead_letter_log.clone(),
+        sessions: session_store.clone(),
+        model_aliases,
+        default_model: env::var("DEFAULT_MODEL")
+            .ok()
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty()),
+        monthly_token_quotas,
+    };
+
+    let embeddings_config = EmbeddingsConfig {
+        max_batch: env_u64("MAX_EMBEDDING_BATCH", 2048) as usize,
+        max_input_chars: env_u64("MAX_EMBEDDING_INPUT_CHARS", 32 * 1024) as usize,
+    };
+
+    // Per-request timing spans, one JSON line each
+    let span_exporter = match env::var("SPANS_FILE") {
+        Ok(path) => {
+            info!("Exporting request spans to {}", path);
+            Some(SpanExporter::spawn(&path)?)
+        }
+        Err(_) => None,
     };
 
     let tracker_for_server = request_tracker.clone();
-    let api_keys_for_server = api_keys.clone();
-    let admin_keys_for_server = admin_keys.clone();
     let provider_for_server = provider.clone();
 
-    let rate_limiter = Arc::new(RateLimiter::new(60)); // 60 RPM
+    // RATE_LIMIT_REQUESTS per RATE_LIMIT_WINDOW_SECS, 60 per minute by default
+    let rate_limit = env_u64("RATE_LIMIT_REQUESTS", 60);
+    let rate_window = Duration::from_secs(env_u64("RATE_LIMIT_WINDOW_SECS", 60).max(1));
+    let shape = BucketShape {
+        strict: env_bool("STRICT_RATE_LIMIT", false),
+        // Same sustained rate with a smaller (or larger) burst
+        burst: env::var("RATE_LIMIT_BURST")
+            .is_ok()
+            .then(|| env_u64("RATE_LIMIT_BURST", rate_limit)),
+    };
+    if shape.strict {
+        info!("Strict rate limiting enabled (no burst)");
+    }
+
+    // Default algorithm for every key, e.g. RATE_LIMIT_STRATEGY=sliding
+    let rate_limit_strategy =
+        env::var("RATE_LIMIT_STRATEGY").unwrap_or_else(|_| String::from("bucket"));
+    if rate_limit_strategy.trim() == "sliding" {
+        info!("Sliding-window rate limiting enabled");
+    }
+
+    // Per-key algorithm overrides, e.g. RATE_LIMIT_ALGO="key-a=sliding,key-b=bucket"
+    let mut algo_overrides = Vec::new();
+    for entry in split_list(&env::var("RATE_LIMIT_ALGO").unwrap_or_default()) {
+        let Some((key, algo)) = entry.rsplit_once('=') else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid RATE_LIMIT_ALGO entry (expected key=bucket|sliding)",
+            ));
+        };
+        algo_overrides.push((key.trim().to_string(), algo.to_string()));
+    }
+    if !algo_overrides.is_empty() {
+        info!(
+            "Rate limit algorithm overridden for {} keys",
+            algo_overrides.len()
+        );
+    }
+
+    let rate_limiter = keyed_limiter(
+        rate_limit,
+        rate_window,
+        shape,
+        &rate_limit_strategy,
+        &algo_overrides,
+    )?;
+
+    // Per-endpoint budgets that replace the default one, e.g.
+    // RATE_LIMIT_ENDPOINTS="/v1/stats=600,/v1/chat/completions=30" (per RATE_LIMIT_WINDOW_SECS),
+    // with the same algorithm as the default for each key
+    let mut endpoint_limits: Vec<(String, Arc<dyn Limiter>)> = Vec::new();
+    for entry in split_list(&env::var("RATE_LIMIT_ENDPOINTS").unwrap_or_default()) {
+        let parsed = entry
+            .rsplit_once('=')
+            .and_then(|(prefix, limit)| Some((prefix.trim(), limit.trim().parse::<u64>().ok()?)));
+        let Some((prefix, limit)) = parsed.filter(|(prefix, _)| prefix.starts_with('/')) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid RATE_LIMIT_ENDPOINTS entry '{}' (expected /path=limit)",
+                    entry
+                ),
+            ));
+        };
+        info!(
+            "Rate limit for {}: {} requests per {:?}",
+            prefix, limit, rate_window
+        );
+        endpoint_limits.push((
+            prefix.to_string(),
+            keyed_limiter(
+                limit,
+                rate_window,
+                shape,
+                &rate_limit_strategy,
+                &algo_overrides,
+            )?,
+        ));
+    }
+
     let rate_limiter_for_server = rate_limiter.clone();
 
+    // Forget keys idle for RATE_LIMIT_IDLE_TTL_SECS whose budget has refilled, checked every
+    // RATE_LIMIT_SWEEP_INTERVAL_SECS (0 turns this off), so rotating keys don't pile up
+    let sweep_interval = env_u64("RATE_LIMIT_SWEEP_INTERVAL_SECS", 60);
+    if sweep_interval > 0 {
+        let idle_ttl = Duration::from_secs(env_u64("RATE_LIMIT_IDLE_TTL_SECS", 600));
+        let limiters: Vec<Arc<dyn Limiter>> = std::iter::once(rate_limiter.clone())
+            .chain(endpoint_limits.iter().map(|(_, limiter)| limiter.clone()))
+            .collect();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(Duration::from_secs(sweep_interval));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let removed: usize = limiters.iter().map(|limiter| limiter.sweep(idle_ttl)).sum();
+                if removed > 0 {
+                    debug!("Rate limiter sweep forgot {} idle keys", removed);
+                }
+            }
+        });
+    }
+
+    // Simultaneous requests per key, unlimited unless MAX_CONCURRENT_PER_KEY is set
+    let concurrency_limiter = match env_u64("MAX_CONCURRENT_PER_KEY", 0) {
+        0 => None,
+        max => {
+            info!("Limiting in-flight requests to {} per key", max);
+            Some(Arc::new(ConcurrencyLimiter::new(max as usize)))
+        }
+    };
+
+    // Periodically persist stats so a hard kill loses at most one interval
+    let flush_interval = Duration::from_secs(env_u64("STATS_FLUSH_INTERVAL_SECS", 60).max(1));
+    let tracker_for_flush = request_tracker.clone();
+    let flush_file = stats_file.clone();
+
+    // Optionally push the same snapshot to an external endpoint on every flush
+    let stats_sink = env::var("STATS_SINK_URL").ok().map(|url| {
+        info!("Stats sink enabled: {}", url);
+        StatsSink::new(
+            url,
+            env_bool("STATS_SINK_MASK_KEYS", true),
+            timeouts.request,
+        )
+    });
+
+    let flush_task = actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(flush_interval);
+        // The first tick completes immediately; skip it so we don't save right at startup
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            flush_stats(&tracker_for_flush, &flush_file, stats_sink.as_ref()).await;
+        }
+    });
+
+    // /v1/models: live from the providers, or a static list for air-gapped setups
+    let models_source = match env::var("MODELS_SOURCE").as_deref() {
+        Ok("config") => {
+            let path = env::var("MODELS_FILE").unwrap_or_else(|_| "models.json".to_string());
+            let source = ModelsSource::from_file(&path).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid MODELS_FILE '{}': {}", path, e),
+                )
+            })?;
+            info!("Serving /v1/models from {}", path);
+            source
+        }
+        Ok("provider") | Err(_) => ModelsSource::Provider,
+        Ok(other) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "MODELS_SOURCE must be 'provider' or 'config', got '{}'",
+                    other
+                ),
+            ));
+        }
+    };
+
+    // POST /v1/admin/shutdown, off unless explicitly enabled
+    let admin_shutdown = if env_bool("ENABLE_ADMIN_SHUTDOWN", false) {
+        warn!("Admin keys can stop the server with POST /v1/admin/shutdown");
+        Some(Arc::new(Notify::new()))
+    } else {
+        None
+    };
+    let admin_config = AdminConfig {
+        require_intent: env_bool("REQUIRE_ADMIN_INTENT", false),
+        shutdown: admin_shutdown.clone(),
+        stats_file: stats_file.clone(),
+    };
+
+    // Grace period for in-flight requests (including open streams) on shutdown
+    let shutdown_timeout = env_u64("SHUTDOWN_TIMEOUT_SECS", 30);
+
+    let expose_limit_details = env_bool("EXPOSE_LIMIT_DETAILS", false);
+    // gzip/brotli/zstd for clients that send Accept-Encoding
+    let enable_compression = env_bool("ENABLE_COMPRESSION", true);
+    let max_body_bytes = env_u64("MAX_BODY_BYTES", 1024 * 1024) as usize;
+
     let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
+            // Chat streams opt out themselves, so SSE chunks still go out as they arrive
+            .wrap(Condition::new(enable_compression, Compress::default()))
             .wrap(TrackingMiddleware::new(tracker_for_server.clone()))
+            // Runs after rate limiting, so rejected requests never hold a slot
+            .wrap(ConcurrencyLimitMiddleware::new(concurrency_limiter.clone()))
             // AuthMiddleware must run BEFORE RateLimitMiddleware to set the key.
             // Actix middlewares run in REVERSE definition order.
             // So definition: wrap(RateLimit) -> wrap(Auth)
             // Execution: Auth -> RateLimit -> Handler
-            .wrap(RateLimitMiddleware::new(rate_limiter_for_server.clone()))
-            .wrap(AuthMiddleware::new(
-                api_keys_for_server.clone(),
-                admin_keys_for_server.clone(),
-            ))
+            .wrap(
+                RateLimitMiddleware::new(rate_limiter_for_server.clone())
+                    .with_endpoint_limits(endpoint_limits.clone())
+                    .with_tracker(tracker_for_server.clone()),
+            )
+            // Wraps both limiters so it sees what each of them recorded
+            .wrap(LimitDetailsMiddleware::new(expose_limit_details))
+            .wrap(AuthMiddleware::new(key_set.clone()).with_allowed_models(allowed_models.clone()))
+            // Spans wrap auth so its timing is included.
+            .wrap(SpanMiddleware::new(span_exporter.clone()))
+            // Outside spans and auth so every response, including errors, carries X-Request-Id.
+            .wrap(RequestIdMiddleware)
+            // CORS is outermost so preflights are answered before auth runs.
+            .wrap(CorsMiddleware::new(cors_config.clone()))
             // We need to wrap in web::Data here explicitly or inside the App?
             // In the previous code: `app_data(web::Data::new(request_tracker.clone()))`
             // `tracker_for_server` is `Arc<RwLock<...>>`. `web::Data` wants to wrap it.
             .app_data(web::Data::from(tracker_for_server.clone()))
             .app_data(web::Data::from(provider_for_server.clone()))
+            .app_data(json_config(max_body_bytes))
+            .app_data(web::Data::new(chat_config.clone()))
+            .app_data(web::Data::new(embeddings_config.clone()))
+            .app_data(web::Data::new(admin_config.clone()))
+            .app_data(web::Data::new(health_backends.clone()))
+            .app_data(web::Data::new(models_source.clone()))
+            .app_data(web::Data::new(cache_metrics.clone()))
+            .app_data(web::Data::new(queue_metrics.clone()))
+            .app_data(web::Data::new(dead_letter_log.clone()))
+            .app_data(web::Data::new(session_store.clone()))
+            .app_data(web::Data::from(key_set.clone()))
+            // Public liveness for orchestrators; the authenticated /v1/health also checks the upstreams
+            // Each resource answers its other methods with a 405 listing the right ones
+            .service(
+                web::resource("/health")
+                    .route(web::get().to(liveness))
+                    .default_service(unsupported_method("GET")),
+            )
+            .service(
+                web::resource("/metrics")
+                    .route(web::get().to(metrics))
+                    .default_service(unsupported_method("GET")),
+            )
             .service(
                 web::scope("/v1")
-                    .route("/health", web::get().to(health))
-                    .route("/chat/completions", web::post().to(chat_completions))
-                    .route("/stats", web::get().to(get_stats)),
+                    .service(
+                        web::resource("/health")
+                            .route(web::get().to(provider_health))
+                            .default_service(unsupported_method("GET")),
+                    )
+                    .service(
+                        web::resource("/chat/completions")
+                            .route(web::post().to(chat_completions))
+                            .default_service(unsupported_method("POST")),
+                    )
+                    .service(
+                        web::resource("/embeddings")
+                            .route(web::post().to(embeddings))
+                            .default_service(unsupported_method("POST")),
+                    )
+                    .service(
+                        web::resource("/tokenize")
+                            .route(web::post().to(tokenize))
+                            .default_service(unsupported_method("POST")),
+                    )
+                    .service(
+                        web::resource("/models")
+                            .route(web::get().to(list_models))
+                            .default_service(unsupported_method("GET")),
+                    )
+                    .service(
+                        web::resource("/stats")
+                            .route(web::get().to(get_stats))
+                            .route(web::delete().to(reset_stats))
+                            .default_service(unsupported_method("GET, DELETE")),
+                    )
+                    .service(
+                        web::resource("/stats/summary")
+                            .route(web::get().to(stats_summary))
+                            .default_service(unsupported_method("GET")),
+                    )
+                    .service(
+                        web::resource("/admin/info")
+                            .route(web::get().to(admin_info))
+                            .default_service(unsupported_method("GET")),
+                    )
+                    .service(
+                        web::resource("/admin/dead-letters")
+                            .route(web::get().to(dead_letters))
+                            .default_service(unsupported_method("GET")),
+                    )
+                    .service(
+                        web::resource("/admin/shutdown")
+                            .route(web::post().to(shutdown))
+                            .default_service(unsupported_method("POST")),
+                    )
+                    .service(
+                        web::resource("/sessions/{id}")
+                            .route(web::delete().to(delete_session))
+                            .default_service(unsupported_method("DELETE")),
+                    )
+                    .service(
+                        web::resource("/keys")
+                            .route(web::get().to(list_keys))
+                            .route(web::post().to(add_key))
+                            .default_service(unsupported_method("GET, POST")),
+                    )
+                    .service(
+                        web::resource("/keys/{key}")
+                            .route(web::delete().to(delete_key))
+                            .default_service(unsupported_method("DELETE")),
+                    ),
             )
     })
     .bind(("127.0.0.1", 8080))?
+    // Signals are handled below so the stats save always runs after the drain
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
     .run();
 
     info!("Server running at http://127.0.0.1:8080");
 
+    let server_handle = server.handle();
+    actix_web::rt::spawn(async move {
+        match admin_shutdown {
+            Some(trigger) => tokio::select! {
+                _ = shutdown_signal() => {}
+                _ = trigger.notified() => {}
+            },
+            None => shutdown_signal().await,
+        }
+        info!(
+            "Shutdown signal received, draining in-flight requests (up to {}s)",
+            shutdown_timeout
+        );
+        server_handle.stop(true).await;
+    });
+
     server.await?;
 
+    // Stop the periodic flush before the final save so they can't overlap
+    flush_task.abort();
+
     info!("Server shutting down, saving stats...");
     // Save the request tracker before exiting
-    if let Err(e) = request_tracker.read().unwrap().save_to_file("stats.json") {
+    if let Err(e) = request_tracker.read().unwrap().save_to_file(&stats_file) {
         eprintln!("Failed to save request stats: {}", e);
     } else {
-        info!("Request stats saved to stats.json");
+        info!("Request stats saved to {}", stats_file);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_timeouts_keep_tags_and_skip_bad_entries() {
+        let timeouts =
+            parse_model_timeouts("llama3.1:70b=120, llama3.2 = 20,broken,=5,mistral=soon");
+        assert_eq!(
+            timeouts,
+            HashMap::from([
+                (String::from("llama3.1:70b"), Duration::from_secs(120)),
+                (String::from("llama3.2"), Duration::from_secs(20)),
+            ])
+        );
+        assert!(parse_model_timeouts("").is_empty());
+    }
+
+    fn admitted(limiter: &dyn Limiter, key: &str, requests: usize) -> usize {
+        (0..requests)
+            .filter(|_| limiter.check_key(key).is_some())
+            .count()
+    }
+
+    #[test]
+    fn keyed_limiter_follows_the_strategy_and_overrides() {
+        // Strict shows which algorithm a key got: a bucket admits one at a time
+        let shape = BucketShape {
+            strict: true,
+            burst: None,
+        };
+        let overrides = [(String::from("tenant"), String::from("sliding"))];
+        let limiter =
+            keyed_limiter(5, Duration::from_secs(1), shape, "bucket", &overrides).unwrap();
+        assert_eq!(admitted(&*limiter, "user:/v1/stats", 5), 1);
+        assert_eq!(admitted(&*limiter, "tenant:/v1/stats", 5), 5);
+
+        let limiter = keyed_limiter(5, Duration::from_secs(1), shape, "sliding", &[]).unwrap();
+        assert_eq!(admitted(&*limiter, "user:/v1/stats", 5), 5);
+    }
+
+    #[test]
+    fn keyed_limiter_applies_the_burst_and_rejects_unknown_algorithms() {
+        let shape = BucketShape {
+            strict: false,
+            burst: Some(2),
+        };
+        let limiter = keyed_limiter(60, Duration::from_secs(60), shape, "bucket", &[]).unwrap();
+        assert_eq!(admitted(&*limiter, "user", 5), 2);
+        assert!(keyed_limiter(60, Duration::from_secs(60), shape, "leaky", &[]).is_err());
+        let overrides = [(String::from("tenant"), String::from("fixed"))];
+        assert!(keyed_limiter(60, Duration::from_secs(60), shape, "bucket", &overrides).is_err());
+    }
+}
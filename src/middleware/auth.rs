@@ -1,37 +1,280 @@
-use actix_web::error::ErrorUnauthorized;
+use actix_web::error::InternalError;
+use actix_web::http::{header::WWW_AUTHENTICATE, Method};
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    Error, HttpMessage, HttpResponse,
 };
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::future::{ready, Ready};
 use std::pin::Pin;
+use std::sync::{Arc, RwLock};
 use std::task::{Context, Poll};
+use std::time::Instant;
+use subtle::{Choice, ConstantTimeEq};
 
-use log::info;
+use crate::models::ApiError;
+use crate::spans::SpanContext;
 
-#[derive(Debug, Clone)]
+use log::{debug, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ApiKeyRole {
     User,
     Admin,
+    /// May read its own stats but not call models
+    ReadOnly,
+    /// May call models but not read stats
+    ChatOnly,
+}
+
+/// What a request needs its key to be allowed to do
+#[derive(Debug, Clone, Copy)]
+pub enum Scope {
+    /// Chat completions and embeddings
+    Chat,
+    /// The key's own `/v1/stats`
+    Stats,
+}
+
+impl ApiKeyRole {
+    pub const ALL: [ApiKeyRole; 4] = [Self::User, Self::Admin, Self::ReadOnly, Self::ChatOnly];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Admin => "admin",
+            Self::ReadOnly => "read_only",
+            Self::ChatOnly => "chat_only",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|role| role.name() == name)
+    }
+
+    /// Admin-only endpoints check for `Admin` themselves; this covers the rest.
+    pub fn allows(self, scope: Scope) -> bool {
+        matches!(
+            (self, scope),
+            (Self::User | Self::Admin, _)
+                | (Self::ReadOnly, Scope::Stats)
+                | (Self::ChatOnly, Scope::Chat)
+        )
+    }
 }
 
 #[derive(Clone)]
 pub struct ValidatedApiKey {
     pub key: String,
     pub role: ApiKeyRole,
+    /// Models this key may use (`KEY_ALLOWED_MODELS`); `None` allows every model
+    pub allowed_models: Option<Vec<String>>,
 }
 
-pub struct AuthMiddleware {
+impl ValidatedApiKey {
+    /// Admin keys may use any model. An entry without a `:tag` also covers the
+    /// model's tagged variants, so `llama3.2` allows `llama3.2:3b`.
+    pub fn may_use_model(&self, model: &str) -> bool {
+        if matches!(self.role, ApiKeyRole::Admin) {
+            return true;
+        }
+        let Some(allowed) = &self.allowed_models else {
+            return true;
+        };
+        let base = model.split_once(':').map_or(model, |(base, _)| base);
+        allowed.iter().any(|m| m == model || m == base)
+    }
+}
+
+/// Liveness probes must work without credentials (load balancers, orchestrators).
+/// `/v1/health` names the upstreams, so it stays behind a key.
+pub const PUBLIC_PATHS: &[&str] = &["/health"];
+
+/// Mask an API key for display, keeping only the first and last 4 characters
+pub fn mask_key(key: &str) -> String {
+    if key.len() <= 8 {
+        "***".to_string()
+    } else {
+        let prefix = &key[..4];
+        let suffix = &key[key.len() - 4..];
+        format!("{}***{}", prefix, suffix)
+    }
+}
+
+/// Constant-time membership check: every configured key is compared in full,
+/// so response timing doesn't reveal how much of a candidate matched. Both sides
+/// are hashed first, so a key's length doesn't leak through an early exit either.
+fn contains_key(keys: &[String], candidate: &str) -> bool {
+    let candidate = digest(&SHA256, candidate.as_bytes());
+    keys.iter()
+        .fold(Choice::from(0), |found, key| {
+            let key = digest(&SHA256, key.as_bytes());
+            found | key.as_ref().ct_eq(candidate.as_ref())
+        })
+        .into()
+}
+
+/// The keys the gateway accepts. Shared between the middleware and the `/v1/keys`
+/// endpoints, so changes apply to the next request.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeySet {
     api_keys: Vec<String>,
     admin_keys: Vec<String>,
+    /// Narrower roles for some user keys, from `KEY_SCOPES` or `/v1/keys`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    scopes: HashMap<String, ApiKeyRole>,
+    /// Where changes are saved (`API_KEYS_FILE`), if anywhere
+    #[serde(skip)]
+    path: Option<String>,
 }
 
-impl AuthMiddleware {
+impl KeySet {
     pub fn new(api_keys: Vec<String>, admin_keys: Vec<String>) -> Self {
         Self {
-            api_keys: api_keys,
+            api_keys,
             admin_keys,
+            scopes: HashMap::new(),
+            path: None,
+        }
+    }
+
+    /// Gives the listed user keys a narrower role than `User`, on top of any
+    /// saved with the keys.
+    pub fn with_scopes(mut self, scopes: HashMap<String, ApiKeyRole>) -> Self {
+        self.scopes.extend(scopes);
+        self
+    }
+
+    /// Keys saved in `path` by an earlier run, or `seed` when the file doesn't exist yet.
+    /// Later changes are written back to `path`.
+    pub fn load_or_seed(path: &str, seed: KeySet) -> std::io::Result<Self> {
+        let mut keys = match std::fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw).map_err(std::io::Error::from)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => seed,
+            Err(e) => return Err(e),
+        };
+        keys.path = Some(path.to_string());
+        Ok(keys)
+    }
+
+    pub fn role_of(&self, token: &str) -> Option<ApiKeyRole> {
+        if contains_key(&self.admin_keys, token) {
+            Some(ApiKeyRole::Admin)
+        } else if contains_key(&self.api_keys, token) {
+            Some(self.user_role(token))
+        } else {
+            None
+        }
+    }
+
+    /// Every key with its role, admins first.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, ApiKeyRole)> {
+        let admins = self
+            .admin_keys
+            .iter()
+            .map(|k| (k.as_str(), ApiKeyRole::Admin));
+        let users = self
+            .api_keys
+            .iter()
+            .map(|k| (k.as_str(), self.user_role(k)));
+        admins.chain(users)
+    }
+
+    fn user_role(&self, key: &str) -> ApiKeyRole {
+        self.scopes.get(key).copied().unwrap_or(ApiKeyRole::User)
+    }
+
+    /// Returns false if the key already exists (under either role).
+    pub fn add(&mut self, key: String, role: ApiKeyRole) -> std::io::Result<bool> {
+        if self.role_of(&key).is_some() {
+            return Ok(false);
+        }
+        match role {
+            ApiKeyRole::Admin => self.admin_keys.push(key),
+            ApiKeyRole::User => self.api_keys.push(key),
+            ApiKeyRole::ReadOnly | ApiKeyRole::ChatOnly => {
+                self.scopes.insert(key.clone(), role);
+                self.api_keys.push(key);
+            }
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Returns false if the key wasn't there.
+    pub fn remove(&mut self, key: &str) -> std::io::Result<bool> {
+        let before = self.api_keys.len() + self.admin_keys.len();
+        self.api_keys.retain(|k| k != key);
+        self.admin_keys.retain(|k| k != key);
+        self.scopes.remove(key);
+        if self.api_keys.len() + self.admin_keys.len() == before {
+            return Ok(false);
         }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Written to a temporary file first so a crash never leaves a half-written key file.
+    fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = format!("{}.tmp", path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // The file holds live credentials, so only the gateway's user may read it
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        std::io::Write::write_all(&mut file, &serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+/// OpenAI-shaped 401 with a `WWW-Authenticate` challenge, telling a client that
+/// sent no key apart from one whose key is wrong.
+fn unauthorized(token: Option<&str>) -> Error {
+    let (message, challenge) = match token {
+        None | Some("") => (
+            String::from(
+                "Missing API key. Send it as 'Authorization: Bearer <key>' or 'x-api-key: <key>'",
+            ),
+            "Bearer",
+        ),
+        Some(token) => (
+            format!("Invalid API key provided: {}", mask_key(token)),
+            "Bearer error=\"invalid_token\"",
+        ),
+    };
+    let response = HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, challenge))
+        .json(ApiError::invalid_api_key(message.clone()));
+    InternalError::from_response(message, response).into()
+}
+
+pub struct AuthMiddleware {
+    keys: Arc<RwLock<KeySet>>,
+    allowed_models: Arc<HashMap<String, Vec<String>>>,
+}
+
+impl AuthMiddleware {
+    pub fn new(keys: Arc<RwLock<KeySet>>) -> Self {
+        Self {
+            keys,
+            allowed_models: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Per-key model allowlists; keys without an entry may use any model.
+    pub fn with_allowed_models(
+        mut self,
+        allowed_models: Arc<HashMap<String, Vec<String>>>,
+    ) -> Self {
+        self.allowed_models = allowed_models;
+        self
     }
 }
 
@@ -50,16 +293,16 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareService {
             service,
-            api_keys: self.api_keys.clone(),
-            admin_keys: self.admin_keys.clone(),
+            keys: self.keys.clone(),
+            allowed_models: self.allowed_models.clone(),
         }))
     }
 }
 
 pub struct AuthMiddlewareService<S> {
     service: S,
-    api_keys: Vec<String>,
-    admin_keys: Vec<String>,
+    keys: Arc<RwLock<KeySet>>,
+    allowed_models: Arc<HashMap<String, Vec<String>>>,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
@@ -77,41 +320,122 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let auth_header = req.headers().get("Authorization");
+        // OPTIONS only reports a resource's methods, so probes don't need a key
+        if PUBLIC_PATHS.contains(&req.path()) || req.method() == Method::OPTIONS {
+            return Box::pin(self.service.call(req));
+        }
 
-        let token_str = auth_header.and_then(|h| h.to_str().ok()).unwrap_or("None");
-        info!("Middleware received header: {}", token_str);
-        info!("Middleware expects one of: {:?}", self.api_keys);
+        let started = Instant::now();
+        let auth_header = req.headers().get("Authorization");
 
+        // `Authorization: Bearer` takes precedence; some clients send `x-api-key` instead
         let token = auth_header
             .and_then(|h| h.to_str().ok())
             .and_then(|s| s.strip_prefix("Bearer "))
-            .map(|t| t.to_string());
-
-        let role = token.as_ref().and_then(|t| {
-            if self.admin_keys.contains(t) {
-                Some(ApiKeyRole::Admin)
-            } else if self.api_keys.contains(t) {
-                Some(ApiKeyRole::User)
-            } else {
-                None
-            }
+            .or_else(|| req.headers().get("x-api-key").and_then(|h| h.to_str().ok()))
+            .map(|t| t.trim().to_string());
+
+        let role = token.as_ref().and_then(|t| match self.keys.read() {
+            Ok(keys) => keys.role_of(t),
+            Err(_) => None,
         });
 
+        if let Some(span) = req.extensions().get::<SpanContext>() {
+            span.record("auth", started);
+        }
+
         match role {
             Some(r) => {
-                info!("Auth Success! Role: {:?}", r);
+                debug!(
+                    "Auth Success! Key: {}, Role: {:?}",
+                    mask_key(token.as_deref().unwrap_or_default()),
+                    r
+                );
+                let key = token.unwrap();
+                let allowed_models = self.allowed_models.get(&key).cloned();
                 req.extensions_mut().insert(ValidatedApiKey {
-                    key: token.unwrap(),
+                    key,
                     role: r,
+                    allowed_models,
                 });
                 let fut = self.service.call(req);
-                Box::pin(async move { fut.await })
+                Box::pin(fut)
             }
             None => {
-                info!("Auth Failed. Token extracted: {:?}", token);
-                Box::pin(async move { Err(ErrorUnauthorized("Invalid or missing API key")) })
+                info!(
+                    "Auth Failed. Token extracted: {}",
+                    token
+                        .as_deref()
+                        .map(mask_key)
+                        .unwrap_or_else(|| "None".to_string())
+                );
+                let error = unauthorized(token.as_deref());
+                Box::pin(async move { Err(error) })
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{init_service, try_call_service, TestRequest};
+    use actix_web::{web, App};
+
+    fn keys() -> Arc<RwLock<KeySet>> {
+        Arc::new(RwLock::new(KeySet::new(
+            vec![String::from("user-key")],
+            vec![String::from("admin-key")],
+        )))
+    }
+
+    async fn status(path: &str, key: Option<&str>) -> u16 {
+        let app = init_service(
+            App::new()
+                .wrap(AuthMiddleware::new(keys()))
+                .route("/health", web::get().to(HttpResponse::Ok))
+                .route("/v1/health", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let mut req = TestRequest::get().uri(path);
+        if let Some(key) = key {
+            req = req.insert_header(("Authorization", format!("Bearer {}", key)));
+        }
+        match try_call_service(&app, req.to_request()).await {
+            Ok(res) => res.status().as_u16(),
+            Err(e) => e.as_response_error().status_code().as_u16(),
+        }
+    }
+
+    #[test]
+    fn keys_match_only_in_full() {
+        let keys = [String::from("user-key"), String::from("other-key")];
+        assert!(contains_key(&keys, "user-key"));
+        assert!(contains_key(&keys, "other-key"));
+        assert!(!contains_key(&keys, "user-ke"));
+        assert!(!contains_key(&keys, "user-key2"));
+        assert!(!contains_key(&keys, ""));
+        assert!(!contains_key(&[], "user-key"));
+    }
+
+    #[test]
+    fn role_follows_the_list_a_key_is_in() {
+        let keys = keys();
+        let keys = keys.read().unwrap();
+        assert_eq!(keys.role_of("admin-key"), Some(ApiKeyRole::Admin));
+        assert_eq!(keys.role_of("user-key"), Some(ApiKeyRole::User));
+        assert_eq!(keys.role_of("admin-key "), None);
+    }
+
+    #[actix_web::test]
+    async fn liveness_is_public() {
+        assert_eq!(status("/health", None).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn upstream_health_needs_a_key() {
+        assert_eq!(status("/v1/health", None).await, 401);
+        assert_eq!(status("/v1/health", Some("wrong-key")).await, 401);
+        assert_eq!(status("/v1/health", Some("user-key")).await, 200);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::middleware::auth::ValidatedApiKey;
+use crate::middleware::limits::record_limit;
+use crate::middleware::rate_limit::Budget;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use futures_util::future::LocalBoxFuture;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Counts in-flight requests per API key and refuses new ones past `max_per_key`.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    in_flight: Mutex<HashMap<String, usize>>,
+    max_per_key: usize,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max_per_key: usize) -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+            max_per_key,
+        }
+    }
+
+    /// Takes a slot for `api_key`, or `None` when all of its slots are in use.
+    fn try_acquire(self: &Arc<Self>, api_key: &str) -> Option<(InFlightGuard, Budget)> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let count = in_flight.entry(api_key.to_string()).or_insert(0);
+        if *count >= self.max_per_key {
+            return None;
+        }
+        *count += 1;
+
+        let guard = InFlightGuard {
+            limiter: self.clone(),
+            api_key: api_key.to_string(),
+        };
+        let budget = Budget {
+            remaining: (self.max_per_key - *count) as u64,
+            limit: self.max_per_key as u64,
+        };
+        Some((guard, budget))
+    }
+}
+
+/// Releases its slot when dropped, whether the response completed, errored, or the
+/// client went away mid-stream.
+struct InFlightGuard {
+    limiter: Arc<ConcurrencyLimiter>,
+    api_key: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = self.limiter.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.api_key) {
+            *count = count.saturating_sub(1);
+            // Idle keys are removed so the map only holds keys with requests in flight
+            if *count == 0 {
+                in_flight.remove(&self.api_key);
+            }
+        }
+    }
+}
+
+/// Response body that holds the request's slot until the body is fully sent or dropped.
+pub struct InFlightBody<B> {
+    body: Pin<Box<B>>,
+    _guard: Option<InFlightGuard>,
+}
+
+impl<B: MessageBody> MessageBody for InFlightBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.get_mut().body.as_mut().poll_next(cx)
+    }
+}
+
+/// Caps simultaneous requests per API key (`MAX_CONCURRENT_PER_KEY`) with a 429.
+/// Requests without a validated key aren't counted.
+pub struct ConcurrencyLimitMiddleware {
+    limiter: Option<Arc<ConcurrencyLimiter>>,
+}
+
+impl ConcurrencyLimitMiddleware {
+    /// `None` disables the limit.
+    pub fn new(limiter: Option<Arc<ConcurrencyLimiter>>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ConcurrencyLimitMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<InFlightBody<B>>;
+    type Error = Error;
+    type Transform = ConcurrencyLimitMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ConcurrencyLimitMiddlewareService {
+            service,
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct ConcurrencyLimitMiddlewareService<S> {
+    service: S,
+    limiter: Option<Arc<ConcurrencyLimiter>>,
+}
+
+impl<S, B> Service<ServiceRequest> for ConcurrencyLimitMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<InFlightBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let api_key = req
+            .extensions()
+            .get::<ValidatedApiKey>()
+            .map(|k| k.key.clone());
+
+        let guard = match (&self.limiter, api_key) {
+            (Some(limiter), Some(key)) => match limiter.try_acquire(&key) {
+                Some((guard, budget)) => {
+                    record_limit(&req, "concurrency", budget);
+                    Some(guard)
+                }
+                None => {
+                    return Box::pin(async {
+                        Err(actix_web::error::ErrorTooManyRequests(
+                            "Too many concurrent requests",
+                        ))
+                    });
+                }
+            },
+            _ => None,
+        };
+
+        // If the handler future is dropped or fails, the guard goes with it
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res.map_body(|_, body| InFlightBody {
+                body: Box::pin(body),
+                _guard: guard,
+            }))
+        })
+    }
+}
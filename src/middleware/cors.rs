@@ -0,0 +1,327 @@
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{Error, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+const ALLOWED_METHODS: &str = "GET, POST, DELETE";
+const ALLOWED_HEADERS: &str =
+    "Authorization, Content-Type, x-api-key, X-Admin-Intent, X-Session-Id, X-Request-Id, X-Provider";
+
+#[derive(Debug, Clone)]
+enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+/// CORS settings for browser-based clients.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    origins: AllowedOrigins,
+    expose_headers: Vec<String>,
+    allow_credentials: bool,
+}
+
+impl CorsConfig {
+    /// Build a config, rejecting credentials mode combined with a wildcard origin
+    /// (browsers refuse `Access-Control-Allow-Origin: *` on credentialed requests).
+    pub fn new(
+        origins: Vec<String>,
+        expose_headers: Vec<String>,
+        allow_credentials: bool,
+    ) -> Result<Self, String> {
+        let origins = if origins.iter().any(|o| o == "*") {
+            AllowedOrigins::Any
+        } else {
+            AllowedOrigins::List(origins)
+        };
+
+        if allow_credentials && matches!(origins, AllowedOrigins::Any) {
+            return Err(
+                "CORS_ALLOW_CREDENTIALS=true cannot be combined with a wildcard origin".to_string(),
+            );
+        }
+
+        Ok(Self {
+            origins,
+            expose_headers,
+            allow_credentials,
+        })
+    }
+
+    fn allow_origin(&self, origin: &str) -> Option<HeaderValue> {
+        match &self.origins {
+            AllowedOrigins::Any => Some(HeaderValue::from_static("*")),
+            AllowedOrigins::List(list) if list.iter().any(|o| o == origin) => {
+                HeaderValue::from_str(origin).ok()
+            }
+            AllowedOrigins::List(_) => None,
+        }
+    }
+
+    fn apply(&self, allow_origin: HeaderValue, headers: &mut HeaderMap) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+
+        if !self.expose_headers.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&self.expose_headers.join(", ")) {
+                headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, value);
+            }
+        }
+    }
+}
+
+/// Adds CORS headers and answers preflight requests before they reach auth.
+/// Passes everything through untouched when no config is given.
+pub struct CorsMiddleware {
+    config: Option<Arc<CorsConfig>>,
+}
+
+impl CorsMiddleware {
+    pub fn new(config: Option<CorsConfig>) -> Self {
+        Self {
+            config: config.map(Arc::new),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CorsMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CorsMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CorsMiddlewareService {
+            service,
+            config: self.config.clone(),
+        }))
+    }
+}
+
+pub struct CorsMiddlewareService<S> {
+    service: S,
+    config: Option<Arc<CorsConfig>>,
+}
+
+impl<S, B> Service<ServiceRequest> for CorsMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some(config) = self.config.clone() else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        };
+
+        let origin = req
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string());
+
+        // Not a cross-origin request, nothing to do.
+        let Some(origin) = origin else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        };
+
+        let allow_origin = config.allow_origin(&origin);
+
+        let is_preflight = req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        if is_preflight {
+            // Preflights carry no credentials, so answer them here instead of letting
+            // them hit AuthMiddleware.
+            let response = match allow_origin {
+                Some(value) => {
+                    let mut response = HttpResponse::NoContent()
+                        .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS))
+                        .insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS))
+                        .finish();
+                    config.apply(value, response.headers_mut());
+                    response
+                }
+                None => HttpResponse::Forbidden().body("Origin not allowed"),
+            };
+            return Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) });
+        }
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(res) => {
+                    let mut response = res.map_into_left_body();
+                    if let Some(value) = allow_origin {
+                        config.apply(value, response.headers_mut());
+                    }
+                    Ok(response)
+                }
+                // Errors from inner middleware (e.g. 401 from auth) need CORS headers too,
+                // otherwise the browser hides the status from the client. The request
+                // can't be held across the call (routing needs it unshared), so the
+                // headers go on the error's own response.
+                Err(e) => {
+                    let mut response = e.error_response();
+                    if let Some(value) = allow_origin {
+                        config.apply(value, response.headers_mut());
+                    }
+                    Err(InternalError::from_response(e.to_string(), response).into())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App};
+
+    fn config(origins: &[&str], allow_credentials: bool) -> Result<CorsConfig, String> {
+        CorsConfig::new(
+            origins.iter().map(|o| o.to_string()).collect(),
+            vec![
+                String::from("X-Request-Id"),
+                String::from("X-RateLimit-Remaining"),
+            ],
+            allow_credentials,
+        )
+    }
+
+    async fn get(config: CorsConfig, origin: &str) -> HeaderMap {
+        let app = init_service(
+            App::new()
+                .wrap(CorsMiddleware::new(Some(config)))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((header::ORIGIN, origin))
+            .to_request();
+        call_service(&app, req).await.headers().clone()
+    }
+
+    #[test]
+    fn credentials_with_a_wildcard_origin_are_rejected() {
+        assert!(config(&["*"], true).is_err());
+        assert!(config(&["https://app.example", "*"], true).is_err());
+        assert!(config(&["*"], false).is_ok());
+        assert!(config(&["https://app.example"], true).is_ok());
+    }
+
+    #[actix_web::test]
+    async fn allowed_origin_gets_credentials_and_exposed_headers() {
+        let headers = get(
+            config(&["https://app.example"], true).unwrap(),
+            "https://app.example",
+        )
+        .await;
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example"
+        );
+        assert_eq!(
+            headers
+                .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .unwrap(),
+            "true"
+        );
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap(),
+            "X-Request-Id, X-RateLimit-Remaining"
+        );
+    }
+
+    #[actix_web::test]
+    async fn other_origins_get_no_cors_headers() {
+        let headers = get(
+            config(&["https://app.example"], true).unwrap(),
+            "https://evil.example",
+        )
+        .await;
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert!(headers.get(header::ACCESS_CONTROL_EXPOSE_HEADERS).is_none());
+    }
+
+    #[actix_web::test]
+    async fn wildcard_origin_omits_credentials() {
+        let headers = get(config(&["*"], false).unwrap(), "https://app.example").await;
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "*"
+        );
+        assert!(headers
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+    }
+
+    #[actix_web::test]
+    async fn preflight_allows_the_gateway_request_headers() {
+        let app = init_service(
+            App::new()
+                .wrap(CorsMiddleware::new(Some(config(&["*"], false).unwrap())))
+                .route("/", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        let req = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .insert_header((header::ORIGIN, "https://app.example"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), 204);
+        let allowed = res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_HEADERS)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_ascii_lowercase();
+        for name in [
+            "x-session-id",
+            "x-request-id",
+            "x-provider",
+            "x-admin-intent",
+        ] {
+            assert!(
+                allowed.split(", ").any(|h| h == name),
+                "{} in {}",
+                name,
+                allowed
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::middleware::rate_limit::Budget;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+
+pub const LIMITS_APPLIED_HEADER: &str = "x-limits-applied";
+
+/// Limits checked for this request and what was left of each, in check order.
+/// Only present when `EXPOSE_LIMIT_DETAILS` is on.
+#[derive(Debug, Default)]
+pub struct AppliedLimits(pub Vec<(&'static str, Budget)>);
+
+/// Called by each limiter that admitted the request.
+pub fn record_limit(req: &ServiceRequest, name: &'static str, budget: Budget) {
+    if let Some(applied) = req.extensions_mut().get_mut::<AppliedLimits>() {
+        applied.0.push((name, budget));
+    }
+}
+
+/// Adds `X-Limits-Applied: rate=57/60, concurrency=3/4` (remaining/limit) to
+/// responses, listing the limits the request passed. Must wrap the limiters.
+pub struct LimitDetailsMiddleware {
+    enabled: bool,
+}
+
+impl LimitDetailsMiddleware {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for LimitDetailsMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = LimitDetailsMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LimitDetailsMiddlewareService {
+            service,
+            enabled: self.enabled,
+        }))
+    }
+}
+
+pub struct LimitDetailsMiddlewareService<S> {
+    service: S,
+    enabled: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for LimitDetailsMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !self.enabled {
+            return Box::pin(self.service.call(req));
+        }
+
+        req.extensions_mut().insert(AppliedLimits::default());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut response = fut.await?;
+            let value = response
+                .request()
+                .extensions()
+                .get::<AppliedLimits>()
+                .filter(|applied| !applied.0.is_empty())
+                .map(|applied| {
+                    applied
+                        .0
+                        .iter()
+                        .map(|(name, budget)| {
+                            format!("{}={}/{}", name, budget.remaining, budget.limit)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                });
+
+            if let Some(value) = value.and_then(|v| HeaderValue::from_str(&v).ok()) {
+                response
+                    .headers_mut()
+                    .insert(HeaderName::from_static(LIMITS_APPLIED_HEADER), value);
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::auth::{ApiKeyRole, ValidatedApiKey};
+    use crate::middleware::concurrency::{ConcurrencyLimitMiddleware, ConcurrencyLimiter};
+    use crate::middleware::rate_limit::{Limiter, RateLimitMiddleware, RateLimiter};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// The `X-Limits-Applied` header of one request through the rate and
+    /// concurrency limiters, or `None` when it's missing.
+    async fn limits_header(enabled: bool, key: Option<&'static str>) -> Option<String> {
+        let limiter: Arc<dyn Limiter> =
+            Arc::new(RateLimiter::with_window(60, Duration::from_secs(60)));
+        let concurrency = Arc::new(ConcurrencyLimiter::new(4));
+        let app = init_service(
+            App::new()
+                .wrap(ConcurrencyLimitMiddleware::new(Some(concurrency)))
+                .wrap(RateLimitMiddleware::new(limiter))
+                .wrap_fn(move |req, srv| {
+                    if let Some(key) = key {
+                        req.extensions_mut().insert(ValidatedApiKey {
+                            key: key.to_string(),
+                            role: ApiKeyRole::User,
+                            allowed_models: None,
+                        });
+                    }
+                    actix_web::dev::Service::call(srv, req)
+                })
+                .wrap(LimitDetailsMiddleware::new(enabled))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let response = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(response.status(), 200);
+        response
+            .headers()
+            .get(LIMITS_APPLIED_HEADER)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[actix_web::test]
+    async fn lists_each_limit_passed_in_check_order() {
+        assert_eq!(
+            limits_header(true, Some("key")).await.as_deref(),
+            Some("rate=59/60, concurrency=3/4")
+        );
+    }
+
+    #[actix_web::test]
+    async fn header_is_off_by_default() {
+        assert_eq!(limits_header(false, Some("key")).await, None);
+    }
+
+    #[actix_web::test]
+    async fn no_header_when_no_limit_applied() {
+        // Public paths carry no key, so no limiter checks them
+        assert_eq!(limits_header(true, None).await, None);
+    }
+}
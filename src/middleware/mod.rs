@@ -1,7 +1,19 @@
 pub mod auth;
+pub mod concurrency;
+pub mod cors;
+pub mod limits;
 pub mod rate_limit;
+pub mod request_id;
+pub mod spans;
 pub mod tracking;
 
-pub use auth::AuthMiddleware;
-pub use rate_limit::{RateLimitMiddleware, RateLimiter};
+pub use auth::{ApiKeyRole, AuthMiddleware, KeySet};
+pub use concurrency::{ConcurrencyLimitMiddleware, ConcurrencyLimiter};
+pub use cors::{CorsConfig, CorsMiddleware};
+pub use limits::LimitDetailsMiddleware;
+pub use rate_limit::{
+    Limiter, PerKeyLimiter, RateLimitMiddleware, RateLimiter, SlidingWindowLimiter,
+};
+pub use request_id::RequestIdMiddleware;
+pub use spans::SpanMiddleware;
 pub use tracking::TrackingMiddleware;
@@ -1,6 +1,58 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+/// A per-key admission check. `RateLimitMiddleware` only sees this trait, so the
+/// algorithm is picked at startup (`RATE_LIMIT_STRATEGY`).
+pub trait Limiter: Send + Sync {
+    /// Records one request for `api_key`. Returns the key's budget after this
+    /// request, or `None` when it should be rejected.
+    fn check_key(&self, api_key: &str) -> Option<Budget>;
+
+    /// Forgets keys unused for at least `idle_ttl` whose state has fully reset, so
+    /// they'd be admitted exactly as if they were still tracked. Returns how many.
+    fn sweep(&self, _idle_ttl: Duration) -> usize {
+        0
+    }
+}
+
+/// Drops the entries `is_idle` picks. They're found under the read lock, so the write
+/// lock is only held to remove them, and each is checked again in case it was just used.
+fn sweep_map<T>(
+    map: &RwLock<HashMap<String, Mutex<T>>>,
+    is_idle: impl Fn(&T, Instant) -> bool,
+) -> usize {
+    let now = Instant::now();
+    let idle: Vec<String> = map
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, entry)| is_idle(&entry.lock().unwrap(), now))
+        .map(|(key, _)| key.clone())
+        .collect();
+    if idle.is_empty() {
+        return 0;
+    }
+
+    let mut map = map.write().unwrap();
+    let before = map.len();
+    for key in idle {
+        if map
+            .get_mut(&key)
+            .is_some_and(|entry| is_idle(entry.get_mut().unwrap(), now))
+        {
+            map.remove(&key);
+        }
+    }
+    before - map.len()
+}
+
+/// A key's remaining allowance out of its limit, e.g. for `X-Limits-Applied`
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub remaining: u64,
+    pub limit: u64,
+}
 
 #[derive(Debug)]
 struct Bucket {
@@ -20,7 +72,13 @@ impl Bucket {
         }
     }
 
-    fn try_consume(&mut self) -> bool {
+    /// Idle long enough, and refilled to capacity by now.
+    fn is_idle(&self, now: Instant, idle_ttl: Duration) -> bool {
+        let idle = now.saturating_duration_since(self.last_updated);
+        idle >= idle_ttl && self.tokens + idle.as_secs_f64() * self.refill_rate >= self.capacity
+    }
+
+    fn try_consume(&mut self) -> Option<Budget> {
         let now = Instant::now();
         let elapsed = now.duration_since(self.last_updated).as_secs_f64();
 
@@ -31,9 +89,12 @@ impl Bucket {
 
         if self.tokens >= 1.0 {
             self.tokens -= 1.0;
-            true
+            Some(Budget {
+                remaining: self.tokens.floor() as u64,
+                limit: self.capacity as u64,
+            })
         } else {
-            false
+            None
         }
     }
 }
@@ -48,17 +109,40 @@ pub struct RateLimiter {
 }
 
 impl RateLimiter {
+    /// Capacity equals the per-minute rate, so a full minute of requests can burst at once.
     pub fn new(requests_per_minute: u64) -> Self {
-        let rate = requests_per_minute as f64 / 60.0;
+        Self::with_burst(requests_per_minute, requests_per_minute)
+    }
+
+    /// `requests_per_minute` sustained, with at most `burst_capacity` at once.
+    pub fn with_burst(requests_per_minute: u64, burst_capacity: u64) -> Self {
+        Self::with_window_and_burst(requests_per_minute, Duration::from_secs(60), burst_capacity)
+    }
+
+    /// `limit` requests per `window`, e.g. 10 per second or 1000 per hour. Capacity
+    /// equals `limit`, so a whole window of requests can burst at once.
+    pub fn with_window(limit: u64, window: Duration) -> Self {
+        Self::with_window_and_burst(limit, window, limit)
+    }
+
+    /// `limit` requests per `window` sustained, with at most `burst_capacity` at once,
+    /// e.g. 60 per minute with a burst of 10.
+    pub fn with_window_and_burst(limit: u64, window: Duration, burst_capacity: u64) -> Self {
         Self {
             buckets: Arc::new(RwLock::new(HashMap::new())),
-            default_capacity: requests_per_minute as f64, // Allow full minute burst? Or maybe smaller? Let's say 2x rate or just N.
-            // Commonly capacity = burst size. Let's start with capacity = requests_per_minute (allow 1 min burst)
-            default_refill_rate: rate,
+            default_capacity: burst_capacity.max(1) as f64,
+            default_refill_rate: limit as f64 / window.as_secs_f64().max(f64::EPSILON),
         }
     }
 
-    pub fn check_key(&self, api_key: &str) -> bool {
+    /// Strict mode: capacity of exactly one token, so requests are gated to one per
+    /// `window / limit` with no burst tolerance at all. Reproducible, but a client
+    /// that sends two requests back-to-back will always have the second rejected.
+    pub fn strict(limit: u64, window: Duration) -> Self {
+        Self::with_window_and_burst(limit, window, 1)
+    }
+
+    pub fn check_key(&self, api_key: &str) -> Option<Budget> {
         // 1. Fast path: Read lock to find existing bucket
         {
             let map = self.buckets.read().unwrap();
@@ -79,12 +163,132 @@ impl RateLimiter {
             Mutex::new(Bucket::new(self.default_capacity, self.default_refill_rate))
         });
 
-        let mut bucket = bucket_mutex.lock().unwrap();
+        let bucket = bucket_mutex.get_mut().unwrap();
         bucket.try_consume()
     }
 }
 
+impl Limiter for RateLimiter {
+    fn check_key(&self, api_key: &str) -> Option<Budget> {
+        RateLimiter::check_key(self, api_key)
+    }
+
+    fn sweep(&self, idle_ttl: Duration) -> usize {
+        sweep_map(&self.buckets, |bucket, now| bucket.is_idle(now, idle_ttl))
+    }
+}
+
+/// Allows at most `limit` requests per key in any trailing window. Unlike the token
+/// bucket there is no burst refill: a key is blocked until its oldest request ages out.
+#[derive(Debug)]
+pub struct SlidingWindowLimiter {
+    windows: RwLock<HashMap<String, Mutex<VecDeque<Instant>>>>,
+    limit: usize,
+    window: Duration,
+}
+
+impl SlidingWindowLimiter {
+    /// `limit` requests per trailing 60 seconds.
+    #[allow(dead_code)]
+    pub fn new(requests_per_minute: u64) -> Self {
+        Self::with_window(requests_per_minute, Duration::from_secs(60))
+    }
+
+    /// `limit` requests per trailing `window`.
+    pub fn with_window(limit: u64, window: Duration) -> Self {
+        Self {
+            windows: RwLock::new(HashMap::new()),
+            limit: limit as usize,
+            window,
+        }
+    }
+
+    fn admit(&self, timestamps: &mut VecDeque<Instant>) -> Option<Budget> {
+        let now = Instant::now();
+        // Drop anything older than the window so each key holds at most `limit` entries
+        while let Some(&oldest) = timestamps.front() {
+            if now.duration_since(oldest) < self.window {
+                break;
+            }
+            timestamps.pop_front();
+        }
+
+        if timestamps.len() < self.limit {
+            timestamps.push_back(now);
+            Some(Budget {
+                remaining: (self.limit - timestamps.len()) as u64,
+                limit: self.limit as u64,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Limiter for SlidingWindowLimiter {
+    fn check_key(&self, api_key: &str) -> Option<Budget> {
+        {
+            let map = self.windows.read().unwrap();
+            if let Some(window) = map.get(api_key) {
+                return self.admit(&mut window.lock().unwrap());
+            }
+        }
+
+        let mut map = self.windows.write().unwrap();
+        let window = map
+            .entry(api_key.to_string())
+            .or_insert_with(|| Mutex::new(VecDeque::new()));
+        self.admit(window.get_mut().unwrap())
+    }
+
+    /// A key is only dropped once all its requests have left the window.
+    fn sweep(&self, idle_ttl: Duration) -> usize {
+        let idle_ttl = idle_ttl.max(self.window);
+        sweep_map(&self.windows, |timestamps, now| {
+            timestamps
+                .back()
+                .is_none_or(|newest| now.saturating_duration_since(*newest) >= idle_ttl)
+        })
+    }
+}
+
+/// Sends each key to the limiter configured for it (`RATE_LIMIT_ALGO`), and every
+/// other key to `default`. Limiters can be shared: their state is per key anyway.
+pub struct PerKeyLimiter {
+    default: Arc<dyn Limiter>,
+    overrides: HashMap<String, Arc<dyn Limiter>>,
+}
+
+impl PerKeyLimiter {
+    pub fn new(default: Arc<dyn Limiter>, overrides: HashMap<String, Arc<dyn Limiter>>) -> Self {
+        Self { default, overrides }
+    }
+}
+
+impl Limiter for PerKeyLimiter {
+    fn check_key(&self, api_key: &str) -> Option<Budget> {
+        // Endpoint buckets are named `key:/route` and still follow the key's override
+        let override_for = self.overrides.get(api_key).or_else(|| {
+            let (key, _) = api_key.rsplit_once(":/")?;
+            self.overrides.get(key)
+        });
+        override_for.unwrap_or(&self.default).check_key(api_key)
+    }
+
+    /// Each shared limiter is swept once.
+    fn sweep(&self, idle_ttl: Duration) -> usize {
+        let mut limiters = vec![&self.default];
+        for limiter in self.overrides.values() {
+            if !limiters.iter().any(|seen| Arc::ptr_eq(seen, limiter)) {
+                limiters.push(limiter);
+            }
+        }
+        limiters.iter().map(|limiter| limiter.sweep(idle_ttl)).sum()
+    }
+}
+
 // Middleware Boilerplate
+use crate::tracking::RequestTracker;
 use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
 use actix_web::Error;
 use futures_util::future::LocalBoxFuture;
@@ -92,12 +296,43 @@ use std::future::{ready, Ready};
 
 // 1. The Middleware Factory
 pub struct RateLimitMiddleware {
-    limiter: Arc<RateLimiter>,
+    limiter: Arc<dyn Limiter>,
+    endpoints: Arc<Vec<(String, Arc<dyn Limiter>)>>,
+    tracker: Option<Arc<RwLock<RequestTracker>>>,
 }
 
 impl RateLimitMiddleware {
-    pub fn new(limiter: Arc<RateLimiter>) -> Self {
-        Self { limiter }
+    pub fn new(limiter: Arc<dyn Limiter>) -> Self {
+        Self {
+            limiter,
+            endpoints: Arc::new(Vec::new()),
+            tracker: None,
+        }
+    }
+
+    /// Count rejections in the key's `rate_limited_count`. Rejected requests never
+    /// reach `TrackingMiddleware`, so they'd go unrecorded otherwise.
+    pub fn with_tracker(mut self, tracker: Arc<RwLock<RequestTracker>>) -> Self {
+        self.tracker = Some(tracker);
+        self
+    }
+
+    /// Requests under one of these path prefixes (`RATE_LIMIT_ENDPOINTS`) use its
+    /// limiter instead of the default one, e.g. a looser budget for `/v1/stats`.
+    /// The longest matching prefix wins.
+    pub fn with_endpoint_limits(mut self, mut endpoints: Vec<(String, Arc<dyn Limiter>)>) -> Self {
+        endpoints.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self.endpoints = Arc::new(endpoints);
+        self
+    }
+}
+
+/// `/v1/stats` covers `/v1/stats` and `/v1/stats/summary`, but not `/v1/statsx`.
+fn path_under(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
     }
 }
 
@@ -118,6 +353,8 @@ where
         ready(Ok(RateLimitMiddlewareService {
             service,
             limiter: self.limiter.clone(),
+            endpoints: self.endpoints.clone(),
+            tracker: self.tracker.clone(),
         }))
     }
 }
@@ -125,7 +362,9 @@ where
 // 3. The Middleware Service
 pub struct RateLimitMiddlewareService<S> {
     service: S,
-    limiter: Arc<RateLimiter>,
+    limiter: Arc<dyn Limiter>,
+    endpoints: Arc<Vec<(String, Arc<dyn Limiter>)>>,
+    tracker: Option<Arc<RwLock<RequestTracker>>>,
 }
 
 impl<S, B> Service<ServiceRequest> for RateLimitMiddlewareService<S>
@@ -142,9 +381,11 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         use crate::middleware::auth::ValidatedApiKey;
+        use crate::middleware::limits::record_limit;
+        use crate::spans::SpanContext;
         use actix_web::HttpMessage;
 
-        let limiter = self.limiter.clone();
+        let started = Instant::now();
 
         // Extract API Key from extensions.
         // Assumes AuthMiddleware ran first (registered LAST in main.rs).
@@ -154,14 +395,35 @@ where
         };
 
         if let Some(key) = api_key {
-            // Check rate limit
-            if !limiter.check_key(&key) {
-                // Rate limit exceeded
-                return Box::pin(async {
-                    Err(actix_web::error::ErrorTooManyRequests(
-                        "Rate limit exceeded",
-                    ))
-                });
+            // An endpoint limit gets its own bucket per key and route, so a limiter
+            // shared by several prefixes still counts each route separately
+            let endpoint = self
+                .endpoints
+                .iter()
+                .find(|(prefix, _)| path_under(req.path(), prefix));
+            let budget = match endpoint {
+                Some((prefix, limiter)) => {
+                    let route = req.match_pattern().unwrap_or_else(|| prefix.clone());
+                    limiter.check_key(&format!("{}:{}", key, route))
+                }
+                None => self.limiter.check_key(&key),
+            };
+            if let Some(span) = req.extensions().get::<SpanContext>() {
+                span.record("rate_limit", started);
+            }
+            match budget {
+                Some(budget) => record_limit(&req, "rate", budget),
+                None => {
+                    // Rate limit exceeded
+                    if let Some(tracker) = &self.tracker {
+                        tracker.write().unwrap().record_rate_limited(&key);
+                    }
+                    return Box::pin(async {
+                        Err(actix_web::error::ErrorTooManyRequests(
+                            "Rate limit exceeded",
+                        ))
+                    });
+                }
             }
         }
 
@@ -174,3 +436,208 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_rejects_a_back_to_back_request() {
+        // 10 per second: one request every 100ms
+        let limiter = RateLimiter::strict(10, Duration::from_secs(1));
+        assert!(limiter.check_key("key").is_some());
+        assert!(limiter.check_key("key").is_none());
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(limiter.check_key("key").is_some());
+        assert!(limiter.check_key("key").is_none());
+    }
+
+    #[test]
+    fn strict_tracks_keys_separately() {
+        let limiter = RateLimiter::strict(10, Duration::from_secs(1));
+        assert!(limiter.check_key("a").is_some());
+        assert!(limiter.check_key("b").is_some());
+        assert!(limiter.check_key("a").is_none());
+    }
+
+    #[test]
+    fn default_bucket_allows_a_whole_window_at_once() {
+        let limiter = RateLimiter::with_window(3, Duration::from_secs(60));
+        let remaining: Vec<_> = (0..3)
+            .map(|_| limiter.check_key("key").unwrap().remaining)
+            .collect();
+        assert_eq!(remaining, [2, 1, 0]);
+        assert!(limiter.check_key("key").is_none());
+    }
+
+    #[test]
+    fn per_minute_shortcuts_match_a_sixty_second_window() {
+        assert_eq!(admitted(&RateLimiter::new(3), 5), 3);
+        assert_eq!(admitted(&RateLimiter::with_burst(60, 2), 5), 2);
+        let budget = RateLimiter::with_burst(60, 2).check_key("key").unwrap();
+        assert_eq!((budget.limit, budget.remaining), (2, 1));
+    }
+
+    fn admitted(limiter: &dyn Limiter, requests: usize) -> usize {
+        (0..requests)
+            .filter(|_| limiter.check_key("key").is_some())
+            .count()
+    }
+
+    #[test]
+    fn bucket_trickles_back_while_the_window_stays_shut() {
+        // The same nominal rate: 5 per second
+        let window = Duration::from_secs(1);
+        let bucket = RateLimiter::with_window(5, window);
+        let sliding = SlidingWindowLimiter::with_window(5, window);
+        let started = Instant::now();
+
+        // Both let a whole window through at once, then nothing more
+        assert_eq!(admitted(&bucket, 8), 5);
+        assert_eq!(admitted(&sliding, 8), 5);
+
+        // The bucket refills a token every 200ms; the window waits for its oldest request
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(admitted(&bucket, 3), 1);
+        assert_eq!(admitted(&sliding, 3), 0);
+
+        // Once the first requests leave the window, it admits a full window again
+        let reopens = started + window + Duration::from_millis(100);
+        std::thread::sleep(reopens.saturating_duration_since(Instant::now()));
+        assert_eq!(admitted(&sliding, 8), 5);
+        assert!(admitted(&bucket, 8) < 5);
+    }
+
+    /// Counts how often it's swept, admitting everything.
+    #[derive(Default)]
+    struct SweepCounter(Mutex<usize>);
+
+    impl Limiter for SweepCounter {
+        fn check_key(&self, _api_key: &str) -> Option<Budget> {
+            Some(Budget {
+                remaining: 0,
+                limit: 0,
+            })
+        }
+
+        fn sweep(&self, _idle_ttl: Duration) -> usize {
+            *self.0.lock().unwrap() += 1;
+            0
+        }
+    }
+
+    #[test]
+    fn per_key_routes_overridden_keys_to_their_limiter() {
+        let default: Arc<dyn Limiter> =
+            Arc::new(RateLimiter::with_window(3, Duration::from_secs(60)));
+        let sliding: Arc<dyn Limiter> = Arc::new(SlidingWindowLimiter::with_window(
+            1,
+            Duration::from_secs(60),
+        ));
+        let limiter = PerKeyLimiter::new(default, HashMap::from([(String::from("slow"), sliding)]));
+
+        assert_eq!(limiter.check_key("slow").unwrap().limit, 1);
+        assert!(limiter.check_key("slow").is_none());
+        // Keys without an override still get the default's budget
+        assert_eq!(limiter.check_key("other").unwrap().limit, 3);
+        assert!(limiter.check_key("other").is_some());
+    }
+
+    #[test]
+    fn per_key_override_covers_the_keys_endpoint_buckets() {
+        let default: Arc<dyn Limiter> =
+            Arc::new(RateLimiter::with_window(3, Duration::from_secs(60)));
+        let sliding: Arc<dyn Limiter> = Arc::new(SlidingWindowLimiter::with_window(
+            1,
+            Duration::from_secs(60),
+        ));
+        let limiter = PerKeyLimiter::new(default, HashMap::from([(String::from("slow"), sliding)]));
+
+        assert_eq!(limiter.check_key("slow:/v1/stats").unwrap().limit, 1);
+        assert!(limiter.check_key("slow:/v1/stats").is_none());
+        // Its own bucket, so the key's default route budget is untouched
+        assert!(limiter.check_key("slow").is_some());
+        assert_eq!(limiter.check_key("other:/v1/stats").unwrap().limit, 3);
+    }
+
+    #[test]
+    fn per_key_sweeps_a_shared_limiter_once() {
+        let default = Arc::new(SweepCounter::default());
+        let shared = Arc::new(SweepCounter::default());
+        let overrides: HashMap<String, Arc<dyn Limiter>> = HashMap::from([
+            (String::from("a"), shared.clone() as Arc<dyn Limiter>),
+            (String::from("b"), shared.clone()),
+            (String::from("c"), default.clone()),
+        ]);
+        let limiter = PerKeyLimiter::new(default.clone(), overrides);
+
+        limiter.sweep(Duration::from_secs(1));
+        assert_eq!(*default.0.lock().unwrap(), 1);
+        assert_eq!(*shared.0.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn path_under_matches_whole_segments() {
+        assert!(path_under("/v1/stats", "/v1/stats"));
+        assert!(path_under("/v1/stats/summary", "/v1/stats"));
+        assert!(path_under("/v1/stats/summary", "/v1/stats/"));
+        assert!(!path_under("/v1/statsx", "/v1/stats"));
+        assert!(!path_under("/v1/chat/completions", "/v1/stats"));
+    }
+
+    /// Records the bucket names it's asked about, admitting everything.
+    #[derive(Default)]
+    struct KeyRecorder(Mutex<Vec<String>>);
+
+    impl Limiter for KeyRecorder {
+        fn check_key(&self, api_key: &str) -> Option<Budget> {
+            self.0.lock().unwrap().push(api_key.to_string());
+            Some(Budget {
+                remaining: 1,
+                limit: 1,
+            })
+        }
+    }
+
+    #[actix_web::test]
+    async fn endpoint_limits_get_a_bucket_per_key_and_route() {
+        use crate::middleware::auth::{ApiKeyRole, ValidatedApiKey};
+        use actix_web::dev::Service as _;
+        use actix_web::test::{call_service, init_service, TestRequest};
+        use actix_web::{web, App, HttpMessage, HttpResponse};
+
+        let default = Arc::new(KeyRecorder::default());
+        let endpoint = Arc::new(KeyRecorder::default());
+        let app = init_service(
+            App::new()
+                .wrap(
+                    RateLimitMiddleware::new(default.clone()).with_endpoint_limits(vec![(
+                        String::from("/v1/stats"),
+                        endpoint.clone() as Arc<dyn Limiter>,
+                    )]),
+                )
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut().insert(ValidatedApiKey {
+                        key: String::from("key"),
+                        role: ApiKeyRole::User,
+                        allowed_models: None,
+                    });
+                    srv.call(req)
+                })
+                .route("/v1/stats", web::get().to(HttpResponse::Ok))
+                .route("/v1/stats/summary", web::get().to(HttpResponse::Ok))
+                .route("/v1/statsx", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for path in ["/v1/stats", "/v1/stats/summary", "/v1/statsx"] {
+            let res = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            assert_eq!(res.status(), 200, "{}", path);
+        }
+        assert_eq!(
+            *endpoint.0.lock().unwrap(),
+            ["key:/v1/stats", "key:/v1/stats/summary"]
+        );
+        assert_eq!(*default.0.lock().unwrap(), ["key"]);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::middleware::request_id::RequestId;
+use crate::spans::{SpanContext, SpanExporter};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+/// Attaches a `SpanContext` to each request so later middleware and handlers can
+/// record timing phases. Does nothing when span export is disabled.
+pub struct SpanMiddleware {
+    exporter: Option<SpanExporter>,
+}
+
+impl SpanMiddleware {
+    pub fn new(exporter: Option<SpanExporter>) -> Self {
+        Self { exporter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SpanMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SpanMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SpanMiddlewareService {
+            service,
+            exporter: self.exporter.clone(),
+        }))
+    }
+}
+
+pub struct SpanMiddlewareService<S> {
+    service: S,
+    exporter: Option<SpanExporter>,
+}
+
+impl<S, B> Service<ServiceRequest> for SpanMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some(exporter) = self.exporter.clone() else {
+            let fut = self.service.call(req);
+            return Box::pin(fut);
+        };
+
+        // Reuse the correlation ID so spans line up with logs and client-side traces
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let span = SpanContext::new(exporter, request_id, req.method().as_str(), req.path());
+        req.extensions_mut().insert(span.clone());
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let result = fut.await;
+            match &result {
+                Ok(res) => span.set_status(res.status().as_u16()),
+                Err(e) => span.set_status(e.as_response_error().status_code().as_u16()),
+            }
+            result
+        })
+    }
+}
@@ -1,5 +1,8 @@
-use crate::middleware::auth::ValidatedApiKey;
+use crate::middleware::auth::{mask_key, ValidatedApiKey, PUBLIC_PATHS};
 use crate::tracking::RequestTracker;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::web::Bytes;
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
     Error, HttpMessage,
@@ -11,6 +14,84 @@ use std::task::{Context, Poll};
 use std::time::Instant;
 use tracing::info;
 
+/// Request extension set by handlers to count a response as an error in stats
+/// even though it was returned with a success status (e.g. canned fallbacks).
+#[derive(Clone, Copy)]
+pub struct RecordAsError;
+
+/// Request extension set by handlers with the model a request asked for, so the
+/// request is also counted in that model's stats.
+#[derive(Clone)]
+pub struct RequestModel(pub String);
+
+/// Request extension set by handlers with the request's end user (`user`), so the
+/// request is also counted in that user's stats under the key.
+#[derive(Clone)]
+pub struct RequestUser(pub String);
+
+/// A request's stats, written when dropped. For streams that's when the body has
+/// been fully sent or the client went away, so latency covers the whole stream.
+struct PendingRecord {
+    tracker: Arc<RwLock<RequestTracker>>,
+    api_key: String,
+    model: Option<String>,
+    user: Option<String>,
+    is_error: bool,
+    start: Instant,
+}
+
+impl Drop for PendingRecord {
+    fn drop(&mut self) {
+        let latency = self.start.elapsed().as_millis() as u64;
+        self.tracker.write().unwrap().record_request(
+            &self.api_key,
+            latency,
+            self.is_error,
+            self.model.as_deref(),
+            self.user.as_deref(),
+        );
+        info!(
+            api_key = %mask_key(&self.api_key),
+            user = self.user.as_deref(),
+            latency_ms = latency,
+            is_error = self.is_error,
+            "Tracked request"
+        );
+    }
+}
+
+/// Response body that carries a streamed request's `PendingRecord` until it ends.
+pub struct TrackedBody<B> {
+    body: Pin<Box<B>>,
+    _record: Option<PendingRecord>,
+}
+
+impl<B: MessageBody> MessageBody for TrackedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.get_mut().body.as_mut().poll_next(cx)
+    }
+}
+
+/// SSE, or NDJSON for clients that asked for it
+fn is_stream<B>(response: &ServiceResponse<B>) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| {
+            ct.starts_with("text/event-stream") || ct.starts_with("application/x-ndjson")
+        })
+}
+
 #[derive(Clone)]
 
 pub struct TrackingMiddleware {
@@ -27,9 +108,9 @@ impl<S, B> Transform<S, ServiceRequest> for TrackingMiddleware
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<TrackedBody<B>>;
     type Error = Error;
     type Transform = TrackingMiddlewareService<S>;
     type InitError = ();
@@ -52,9 +133,9 @@ impl<S, B> Service<ServiceRequest> for TrackingMiddlewareService<S>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<TrackedBody<B>>;
     type Error = Error;
     type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
 
@@ -63,6 +144,17 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Health probes would otherwise pile up under the "unknown" key
+        if PUBLIC_PATHS.contains(&req.path()) {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                Ok(fut.await?.map_body(|_, body| TrackedBody {
+                    body: Box::pin(body),
+                    _record: None,
+                }))
+            });
+        }
+
         let api_key = req
             .extensions()
             .get::<ValidatedApiKey>()
@@ -80,20 +172,38 @@ where
 
         Box::pin(async move {
             let response = fut.await?;
-            let latency = start.elapsed().as_millis() as u64;
-            let is_error = response.status().is_server_error();
-
-            tracker
-                .write()
-                .unwrap()
-                .record_request(&api_key, latency, is_error);
-            info!(
-                api_key = %api_key,
-                latency_ms = latency,
-                is_error = is_error,
-                "Tracked request"
-            );
-            Ok(response)
+            let is_error = response.status().is_server_error()
+                || response.request().extensions().contains::<RecordAsError>();
+            let model = response
+                .request()
+                .extensions()
+                .get::<RequestModel>()
+                .map(|m| m.0.clone());
+            let user = response
+                .request()
+                .extensions()
+                .get::<RequestUser>()
+                .map(|u| u.0.clone());
+
+            let record = PendingRecord {
+                tracker,
+                api_key,
+                model,
+                user,
+                is_error,
+                start,
+            };
+            // A stream's handler returns once headers are ready, so wait for the body
+            let record = if is_stream(&response) {
+                Some(record)
+            } else {
+                drop(record);
+                None
+            };
+            Ok(response.map_body(|_, body| TrackedBody {
+                body: Box::pin(body),
+                _record: record,
+            }))
         })
     }
 }
@@ -1,21 +1,131 @@
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::time::Duration;
 
 // Shared
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    /// `null` on assistant messages that only carry tool calls
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub content: MessageContent,
+    /// OpenAI-shaped tool calls made by the assistant, kept verbatim
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<serde_json::Value>>,
+    /// The call a `tool` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A message's text, or a list of text and image parts as in OpenAI's vision API.
+/// Plain text is still sent back as a bare string.
+#[derive(Debug, Serialize, Deserialize, Clone, Hash)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Hash)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Hash)]
+pub struct ImageUrl {
+    /// An `https://` URL or a `data:image/...;base64,` URL
+    pub url: String,
+    /// "low", "high" or "auto"; OpenAI only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl MessageContent {
+    /// The text parts joined by newlines, leaving out images.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Self::Text(text) => Cow::Borrowed(text),
+            Self::Parts(parts) => {
+                let texts: Vec<&str> = parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text { text } => Some(text.as_str()),
+                        ContentPart::ImageUrl { .. } => None,
+                    })
+                    .collect();
+                Cow::Owned(texts.join("\n"))
+            }
+        }
+    }
+
+    pub fn image_urls(&self) -> impl Iterator<Item = &str> {
+        let parts = match self {
+            Self::Text(_) => &[][..],
+            Self::Parts(parts) => parts.as_slice(),
+        };
+        parts.iter().filter_map(|part| match part {
+            ContentPart::ImageUrl { image_url } => Some(image_url.url.as_str()),
+            ContentPart::Text { .. } => None,
+        })
+    }
+
+    /// Appends to the text, or to the last text part.
+    pub fn push_str(&mut self, text: &str) {
+        match self {
+            Self::Text(own) => own.push_str(text),
+            Self::Parts(parts) => match parts.last_mut() {
+                Some(ContentPart::Text { text: own }) => own.push_str(text),
+                _ => parts.push(ContentPart::Text {
+                    text: text.to_string(),
+                }),
+            },
+        }
+    }
+
+    /// Appends another message's content, switching to parts if either has images.
+    pub fn append(&mut self, other: MessageContent) {
+        match (&mut *self, other) {
+            (Self::Text(own), Self::Text(text)) => own.push_str(&text),
+            (_, other) => {
+                let mut parts = std::mem::take(self).into_parts();
+                parts.extend(other.into_parts());
+                *self = Self::Parts(parts);
+            }
+        }
+    }
+
+    fn into_parts(self) -> Vec<ContentPart> {
+        match self {
+            Self::Text(text) if text.is_empty() => Vec::new(),
+            Self::Text(text) => vec![ContentPart::Text { text }],
+            Self::Parts(parts) => parts,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Choice {
     pub index: u32,
     pub message: Message,
     pub finish_reason: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
@@ -26,7 +136,19 @@ pub struct Usage {
 pub struct Delta {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub role: Option<String>,
+    /// OpenAI sends `null` content on tool-call deltas
+    #[serde(default, deserialize_with = "null_as_default")]
     pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<serde_json::Value>>,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,20 +162,112 @@ pub struct ChunkChoice {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatCompletionRequest {
+    /// May be omitted when the gateway has a `DEFAULT_MODEL`
+    #[serde(default)]
     pub model: String,
     pub messages: Vec<Message>,
     #[serde(default)]
     pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// How many completions to generate; one when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Tool definitions, forwarded as-is to upstreams that support them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<serde_json::Value>>,
+    /// `"auto"`, `"none"`, `"required"` or a specific function; OpenAI only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// The caller's end user, for attributing usage within a key; OpenAI only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Per-request upstream timeout chosen by the gateway, never sent upstream
+    #[serde(skip)]
+    pub timeout: Option<Duration>,
+    /// Correlation ID forwarded to upstreams as `X-Request-Id`
+    #[serde(skip)]
+    pub request_id: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+const VALID_ROLES: &[&str] = &["system", "user", "assistant", "tool"];
+
+impl ChatCompletionRequest {
+    /// Everything that makes the request unservable: blank model, no messages,
+    /// unknown roles. Empty when the request is valid.
+    pub fn validation_errors(&self) -> Vec<ApiErrorBody> {
+        let mut errors = Vec::new();
+        if self.model.trim().is_empty() {
+            errors.push(ApiErrorBody::invalid_request(
+                "model must not be empty",
+                "model",
+            ));
+        }
+        if self.messages.is_empty() {
+            errors.push(ApiErrorBody::invalid_request(
+                "messages must not be empty",
+                "messages",
+            ));
+        }
+        if self.n == Some(0) {
+            errors.push(ApiErrorBody::invalid_request("n must be at least 1", "n"));
+        }
+        for (index, message) in self.messages.iter().enumerate() {
+            if !VALID_ROLES.contains(&message.role.as_str()) {
+                errors.push(ApiErrorBody::invalid_request(
+                    format!(
+                        "Invalid role '{}', expected one of: {}",
+                        message.role,
+                        VALID_ROLES.join(", ")
+                    ),
+                    format!("messages[{}].role", index),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Structured output mode, e.g. `{"type": "json_object"}`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: serde_json::Value },
+}
+
+impl ResponseFormat {
+    /// Ollama's top-level `format`: `"json"`, or the schema itself for structured outputs.
+    pub fn to_ollama_format(&self) -> Option<serde_json::Value> {
+        match self {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(serde_json::Value::String("json".to_string())),
+            ResponseFormat::JsonSchema { json_schema } => Some(
+                json_schema
+                    .get("schema")
+                    .cloned()
+                    .unwrap_or_else(|| json_schema.clone()),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatCompletionResponse {
     pub id: String,
     pub object: String,
     pub created: u64,
     pub model: String,
     pub choices: Vec<Choice>,
-    pub usage: Usage,
+    /// Minimal OpenAI-compatible backends may leave this out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Set when the gateway served this response from its cache
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -67,31 +281,452 @@ pub struct ChatCompletionChunk {
     pub usage: Option<Usage>,
 }
 
+/// OpenAI's error envelope: `{"error": {"message", "type", "param", "code"}}`.
+/// Validation failures also list every problem under `errors`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiError {
+    pub error: ApiErrorBody,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<ApiErrorBody>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiErrorBody {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub param: Option<String>,
+    pub code: Option<String>,
+}
+
+impl ApiErrorBody {
+    pub fn invalid_request(message: impl Into<String>, param: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            kind: String::from("invalid_request_error"),
+            param: Some(param.into()),
+            code: None,
+        }
+    }
+}
+
+impl From<ApiErrorBody> for ApiError {
+    fn from(error: ApiErrorBody) -> Self {
+        Self {
+            error,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl ApiError {
+    /// One 400 for all of a request's problems. `error` is the only problem, or a
+    /// summary when there are several; `errors` always has each one.
+    pub fn invalid_fields(errors: Vec<ApiErrorBody>) -> Self {
+        let error = match errors.as_slice() {
+            [only] => only.clone(),
+            _ => ApiErrorBody {
+                message: format!(
+                    "{} validation errors: {}",
+                    errors.len(),
+                    errors
+                        .iter()
+                        .map(|e| e.message.as_str())
+                        .collect::<Vec<_>>()
+                        .join("; ")
+                ),
+                kind: String::from("invalid_request_error"),
+                param: None,
+                code: None,
+            },
+        };
+        Self { error, errors }
+    }
+
+    /// A mutating admin call without `X-Admin-Intent: true`, returned with a 400
+    pub fn admin_intent_required() -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: String::from("X-Admin-Intent: true header required for this operation"),
+                kind: String::from("invalid_request_error"),
+                param: Some(String::from("X-Admin-Intent")),
+                code: Some(String::from("admin_intent_required")),
+            },
+            errors: Vec::new(),
+        }
+    }
+
+    /// Missing or unknown API key, returned with a 401
+    pub fn invalid_api_key(message: impl Into<String>) -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: message.into(),
+                kind: String::from("invalid_request_error"),
+                param: None,
+                code: Some(String::from("invalid_api_key")),
+            },
+            errors: Vec::new(),
+        }
+    }
+
+    /// The key's role doesn't cover this endpoint, returned with a 403
+    pub fn insufficient_scope(role: &str, action: &str) -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: format!("This API key's role ('{}') may not {}", role, action),
+                kind: String::from("permission_error"),
+                param: None,
+                code: Some(String::from("insufficient_scope")),
+            },
+            errors: Vec::new(),
+        }
+    }
+
+    /// The key's allowlist doesn't include the model, returned with a 403
+    pub fn model_not_allowed(model: &str) -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: format!("This API key is not allowed to use model '{}'", model),
+                kind: String::from("invalid_request_error"),
+                param: Some(String::from("model")),
+                code: Some(String::from("model_not_allowed")),
+            },
+            errors: Vec::new(),
+        }
+    }
+
+    /// The path exists but not for this method, returned with a 405
+    pub fn method_not_allowed(method: &str, path: &str) -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: format!("Method {} is not allowed on {}", method, path),
+                kind: String::from("invalid_request_error"),
+                param: None,
+                code: Some(String::from("method_not_allowed")),
+            },
+            errors: Vec::new(),
+        }
+    }
+
+    /// The key has used up its monthly token quota, returned with a 429
+    pub fn quota_exceeded(quota: u64) -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: format!(
+                    "This API key has used its monthly quota of {} tokens",
+                    quota
+                ),
+                kind: String::from("insufficient_quota"),
+                param: None,
+                code: Some(String::from("insufficient_quota")),
+            },
+            errors: Vec::new(),
+        }
+    }
+
+    /// The upstream connection broke mid-stream, sent as the stream's last event
+    pub fn stream_interrupted(detail: &str) -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: format!("Upstream stream ended unexpectedly: {}", detail),
+                kind: String::from("server_error"),
+                param: None,
+                code: Some(String::from("stream_interrupted")),
+            },
+            errors: Vec::new(),
+        }
+    }
+
+    /// Body over `MAX_BODY_BYTES`, returned with a 413
+    pub fn request_too_large(limit: usize) -> Self {
+        Self {
+            error: ApiErrorBody {
+                message: format!("Request body exceeds the {} byte limit", limit),
+                kind: String::from("invalid_request_error"),
+                param: None,
+                code: Some(String::from("request_too_large")),
+            },
+            errors: Vec::new(),
+        }
+    }
+}
+
+// Embeddings
+
+/// `input` may be a single string or a batch, like the OpenAI API
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbeddingInput::Single(text) => vec![text],
+            EmbeddingInput::Batch(texts) => texts,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            EmbeddingInput::Single(text) => std::slice::from_ref(text),
+            EmbeddingInput::Batch(texts) => texts,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(skip)]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Embedding {
+    pub object: String,
+    pub embedding: Vec<f32>,
+    pub index: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    pub object: String,
+    pub data: Vec<Embedding>,
+    pub model: String,
+    pub usage: EmbeddingUsage,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelInfo {
+    pub id: String,
+    #[serde(default = "model_object")]
+    pub object: String,
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default)]
+    pub owned_by: String,
+}
+
+fn model_object() -> String {
+    String::from("model")
+}
+
+// Tokenize
+
+#[derive(Debug, Deserialize)]
+pub struct TokenizeRequest {
+    #[serde(default)]
+    pub model: String,
+    pub messages: Vec<Message>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenizeResponse {
+    pub model: String,
+    pub prompt_tokens: u32,
+    /// How the count was made. Only "heuristic" for now; exact tokenizer counts for
+    /// OpenAI models would report something else, so clients can tell them apart.
+    pub method: &'static str,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelList {
+    pub object: String,
+    pub data: Vec<ModelInfo>,
+}
+
 // Ollama
 
 #[derive(Debug, Serialize)]
 pub struct OllamaRequest {
     pub model: String,
-    pub messages: Vec<Message>,
+    pub messages: Vec<OllamaMessage>,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+    /// Same shape as OpenAI's function tools
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OllamaMessage {
+    pub role: String,
+    pub content: String,
+    /// Base64 without the `data:` prefix
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct OllamaResponse {
     pub model: String,
+    /// RFC 3339, becomes the completion's `created`
+    #[serde(default)]
     pub created_at: String,
-    pub message: Message,
+    pub message: OllamaStreamMessage,
     pub done: bool,
+    /// "stop", or "length" when generation hit the token limit; newer Ollama only
+    #[serde(default)]
+    pub done_reason: Option<String>,
     pub total_duration: u64,
     pub prompt_eval_count: u32,
     pub eval_count: u32,
 }
 
+/// An assistant message from Ollama, streamed or not
 #[derive(Debug, Deserialize)]
+pub struct OllamaStreamMessage {
+    #[serde(default)]
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    /// Tool calls arrive complete, usually on a chunk with empty content
+    #[serde(default)]
+    pub tool_calls: Option<Vec<OllamaToolCall>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaToolFunction,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaToolFunction {
+    pub name: String,
+    /// An object here, where OpenAI uses a JSON-encoded string
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 pub struct OllamaStreamChunk {
     pub model: String,
-    pub message: Message,
+    pub message: OllamaStreamMessage,
     pub done: bool,
+    /// Only on the final `done: true` chunk, like the token counts below
+    #[serde(default)]
+    pub done_reason: Option<String>,
+    /// Token counts and timing only appear on the final `done: true` chunk
+    #[serde(default)]
     pub prompt_eval_count: Option<u32>,
+    #[serde(default)]
     pub eval_count: Option<u32>,
+    #[serde(default)]
+    pub total_duration: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OllamaEmbeddingRequest {
+    pub model: String,
+    pub prompt: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaEmbeddingResponse {
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaTagsResponse {
+    pub models: Vec<OllamaModelTag>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaModelTag {
+    pub name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_without_usage_deserializes() {
+        let raw = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "local-model",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}]
+        }"#;
+        let response: ChatCompletionResponse = serde_json::from_str(raw).unwrap();
+        assert!(response.usage.is_none());
+        assert!(!response.cached);
+        assert_eq!(response.choices[0].message.content.text(), "Hi");
+
+        let echoed = serde_json::to_value(&response).unwrap();
+        assert!(echoed.get("usage").is_none());
+        assert!(echoed.get("cached").is_none());
+    }
+
+    fn request(model: &str, roles: &[&str]) -> ChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": model,
+            "messages": roles
+                .iter()
+                .map(|role| serde_json::json!({"role": role, "content": "Hi"}))
+                .collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    fn params(errors: &[ApiErrorBody]) -> Vec<&str> {
+        errors.iter().filter_map(|e| e.param.as_deref()).collect()
+    }
+
+    #[test]
+    fn valid_request_has_no_errors() {
+        let request = request("llama3.2", &["system", "user", "assistant", "tool"]);
+        assert!(request.validation_errors().is_empty());
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let mut request = request(" ", &["user", "robot", "narrator"]);
+        request.n = Some(0);
+        let errors = request.validation_errors();
+        assert_eq!(
+            params(&errors),
+            ["model", "n", "messages[1].role", "messages[2].role"]
+        );
+        assert!(errors[2].message.contains("Invalid role 'robot'"));
+    }
+
+    #[test]
+    fn missing_messages_are_reported() {
+        let errors = request("llama3.2", &[]).validation_errors();
+        assert_eq!(params(&errors), ["messages"]);
+    }
+
+    #[test]
+    fn several_errors_are_summarized() {
+        let errors = request("", &[]).validation_errors();
+        let summary = ApiError::invalid_fields(errors);
+        assert_eq!(
+            summary.error.message,
+            "2 validation errors: model must not be empty; messages must not be empty"
+        );
+        assert_eq!(summary.error.param, None);
+        assert_eq!(summary.errors.len(), 2);
+
+        let single = ApiError::invalid_fields(request("", &["user"]).validation_errors());
+        assert_eq!(single.error.param.as_deref(), Some("model"));
+    }
 }
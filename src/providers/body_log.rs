@@ -0,0 +1,93 @@
+use crate::providers::ProviderError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::debug;
+
+/// JSON fields whose values never get logged, whatever their nesting
+const SECRET_FIELDS: &[&str] = &[
+    "api_key",
+    "apikey",
+    "authorization",
+    "password",
+    "secret",
+    "client_secret",
+    "token",
+    "access_token",
+    "refresh_token",
+];
+
+/// Debug logging of upstream request and response bodies (`LOG_BODIES`), to see
+/// exactly what a provider was sent and answered. Bodies are cut to `max_len` bytes
+/// and secret-looking JSON fields are redacted. Off by default since bodies hold
+/// user content.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLogging {
+    pub max_len: usize,
+}
+
+impl BodyLogging {
+    pub fn request(&self, upstream: &str, body: &impl Serialize) {
+        match serde_json::to_value(body) {
+            Ok(value) => debug!("{} request body: {}", upstream, self.render_json(value)),
+            Err(e) => debug!("{} request body could not be serialized: {}", upstream, e),
+        }
+    }
+
+    pub fn response(&self, upstream: &str, body: &[u8]) {
+        let text = match serde_json::from_slice::<Value>(body) {
+            Ok(value) => self.render_json(value),
+            // Stream chunks and error pages aren't a single JSON document
+            Err(_) => self.truncate(String::from_utf8_lossy(body).into_owned()),
+        };
+        debug!("{} response body: {}", upstream, text);
+    }
+
+    fn render_json(&self, mut value: Value) -> String {
+        redact(&mut value);
+        self.truncate(value.to_string())
+    }
+
+    fn truncate(&self, mut text: String) -> String {
+        if text.len() <= self.max_len {
+            return text;
+        }
+        let total = text.len();
+        let mut cut = self.max_len;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        format!("{}... ({} bytes total)", text, total)
+    }
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if SECRET_FIELDS.contains(&key.to_ascii_lowercase().as_str()) {
+                    *field = Value::String(String::from("[REDACTED]"));
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Decode a successful response, logging its raw body first when logging is on.
+pub(super) async fn read_json<T: DeserializeOwned>(
+    response: reqwest::Response,
+    logging: Option<BodyLogging>,
+    upstream: &str,
+) -> Result<T, ProviderError> {
+    let Some(logging) = logging else {
+        return Ok(response.json::<T>().await?);
+    };
+    let body = response.bytes().await?;
+    logging.response(upstream, &body);
+    serde_json::from_slice(&body).map_err(|e| ProviderError::Parse(e.to_string()))
+}
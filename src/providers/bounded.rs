@@ -0,0 +1,256 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// Live slot usage of a `BoundedProvider`, for `/metrics`.
+#[derive(Debug)]
+pub struct QueueMetrics {
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    waiting: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueMetricsSnapshot {
+    pub in_flight: u64,
+    pub queued: u64,
+    pub max_concurrent: u64,
+}
+
+impl QueueMetrics {
+    pub fn snapshot(&self) -> QueueMetricsSnapshot {
+        let free = self.permits.available_permits();
+        QueueMetricsSnapshot {
+            in_flight: self.max_concurrent.saturating_sub(free) as u64,
+            queued: self.waiting.load(Ordering::Relaxed),
+            max_concurrent: self.max_concurrent as u64,
+        }
+    }
+}
+
+/// Counts a request as queued until it gets a slot, times out, or is cancelled.
+struct Waiting<'a>(&'a AtomicU64);
+
+impl<'a> Waiting<'a> {
+    fn start(counter: &'a AtomicU64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A provider that caps how many requests are in flight to the wrapped upstream at
+/// once (`OLLAMA_MAX_CONCURRENT`, `OPENAI_MAX_CONCURRENT`). Requests past the cap wait
+/// up to `queue_timeout` for a slot and then fail with 503. Wrapping the whole
+/// provider stack gives a gateway-wide cap instead (`MAX_CONCURRENT_REQUESTS`).
+pub struct BoundedProvider {
+    inner: Arc<dyn LLMProvider>,
+    name: String,
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    queue_timeout: Duration,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl BoundedProvider {
+    pub fn new(
+        inner: Arc<dyn LLMProvider>,
+        name: impl Into<String>,
+        max_concurrent: usize,
+        queue_timeout: Duration,
+    ) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        let permits = Arc::new(Semaphore::new(max_concurrent));
+        Self {
+            inner,
+            name: name.into(),
+            permits: permits.clone(),
+            max_concurrent,
+            queue_timeout,
+            metrics: Arc::new(QueueMetrics {
+                permits,
+                max_concurrent,
+                waiting: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// `inner` under this provider's cap: both draw on the same slots and queue
+    /// metrics, e.g. so a backend reached directly still counts toward the gateway cap.
+    pub fn sharing(&self, inner: Arc<dyn LLMProvider>) -> Self {
+        Self {
+            inner,
+            name: self.name.clone(),
+            permits: self.permits.clone(),
+            max_concurrent: self.max_concurrent,
+            queue_timeout: self.queue_timeout,
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<QueueMetrics> {
+        self.metrics.clone()
+    }
+
+    async fn acquire(&self) -> Result<OwnedSemaphorePermit, ProviderError> {
+        let _waiting = Waiting::start(&self.metrics.waiting);
+        match tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed, so only the timeout lands here
+            _ => {
+                warn!(
+                    "{} is at its limit of {} concurrent requests",
+                    self.name, self.max_concurrent
+                );
+                Err(ProviderError::ProviderError {
+                    status: 503,
+                    message: format!("{} is at capacity, try again shortly", self.name),
+                    retry_after: None,
+                })
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl LLMProvider for BoundedProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let _permit = self.acquire().await?;
+        self.inner.chat(request).await
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        // The slot is held until the stream finishes or the client goes away
+        let permit = self.acquire().await?;
+        let stream = self.inner.chat_stream(request).await?;
+        Ok(Box::pin(stream.map(move |result| {
+            let _ = &permit;
+            result
+        })))
+    }
+
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        self.inner.health_check().await
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        self.inner.list_models().await
+    }
+
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        self.inner.stream_usage(model)
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        let _permit = self.acquire().await?;
+        self.inner.embeddings(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{collect, request, StubProvider};
+
+    fn bounded(max_concurrent: usize) -> BoundedProvider {
+        BoundedProvider::new(
+            Arc::new(StubProvider::new("Hi")),
+            "Stub",
+            max_concurrent,
+            Duration::from_millis(20),
+        )
+    }
+
+    fn status(error: ProviderError) -> u16 {
+        match error {
+            ProviderError::ProviderError { status, .. } => status,
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn open_stream_holds_its_slot_until_dropped() {
+        let provider = bounded(1);
+        let stream = provider.chat_stream(request("m")).await.unwrap();
+        assert_eq!(provider.metrics().snapshot().in_flight, 1);
+
+        let error = provider.chat(request("m")).await.unwrap_err();
+        assert_eq!(status(error), 503);
+
+        assert!(collect(stream).await.ends_with("data: [DONE]\n\n"));
+        assert_eq!(provider.metrics().snapshot().in_flight, 0);
+        assert!(provider.chat(request("m")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn request_past_the_cap_times_out_with_503() {
+        let provider = bounded(2);
+        let _first = provider.chat_stream(request("m")).await.unwrap();
+        let _second = provider.chat_stream(request("m")).await.unwrap();
+        let snapshot = provider.metrics().snapshot();
+        assert_eq!((snapshot.in_flight, snapshot.max_concurrent), (2, 2));
+
+        assert_eq!(status(provider.chat(request("m")).await.unwrap_err()), 503);
+        // The timed-out request no longer counts as waiting
+        assert_eq!(provider.metrics().snapshot().queued, 0);
+    }
+
+    #[tokio::test]
+    async fn shared_cap_counts_both_providers() {
+        let gateway = bounded(1);
+        let direct = gateway.sharing(Arc::new(StubProvider::new("direct")));
+        let stream = gateway.chat_stream(request("m")).await.unwrap();
+        assert_eq!(status(direct.chat(request("m")).await.unwrap_err()), 503);
+
+        drop(stream);
+        let _held = direct.chat_stream(request("m")).await.unwrap();
+        assert_eq!(gateway.metrics().snapshot().in_flight, 1);
+        assert_eq!(status(gateway.chat(request("m")).await.unwrap_err()), 503);
+    }
+
+    #[tokio::test]
+    async fn waiting_request_is_counted_as_queued() {
+        let provider = BoundedProvider::new(
+            Arc::new(StubProvider::new("Hi")),
+            "Stub",
+            1,
+            Duration::from_secs(5),
+        );
+        let stream = provider.chat_stream(request("m")).await.unwrap();
+        let waiting = provider.chat(request("m"));
+        let check = async {
+            tokio::task::yield_now().await;
+            assert_eq!(provider.metrics().snapshot().queued, 1);
+            drop(stream);
+        };
+        let (result, ()) = tokio::join!(waiting, check);
+        assert!(result.is_ok());
+        assert_eq!(provider.metrics().snapshot().queued, 0);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+struct CacheEntry {
+    response: ChatCompletionResponse,
+    inserted: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<u64, CacheEntry>,
+    /// Monotonic use counter; the entry with the lowest `last_used` is evicted first
+    tick: u64,
+}
+
+/// Counters describing how well the cache is doing, shared with the stats handlers.
+#[derive(Debug, Default)]
+pub struct CacheMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    entries: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheMetricsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: u64,
+}
+
+impl CacheMetrics {
+    pub fn snapshot(&self) -> CacheMetricsSnapshot {
+        CacheMetricsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: self.entries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A provider that caches non-streaming completions for identical requests,
+/// keyed on `(model, messages, temperature)`. Entries expire after `ttl`, and the
+/// least recently used one is evicted once `capacity` is reached.
+/// Streaming requests and embeddings always go to the inner provider.
+pub struct CacheProvider {
+    inner: Arc<dyn LLMProvider>,
+    ttl: Duration,
+    capacity: usize,
+    state: Mutex<CacheState>,
+    metrics: Arc<CacheMetrics>,
+}
+
+impl CacheProvider {
+    pub fn new(inner: Arc<dyn LLMProvider>, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            inner,
+            ttl,
+            capacity: capacity.max(1),
+            state: Mutex::new(CacheState::default()),
+            metrics: Arc::new(CacheMetrics::default()),
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<CacheMetrics> {
+        self.metrics.clone()
+    }
+
+    fn cache_key(request: &ChatCompletionRequest) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        request.model.hash(&mut hasher);
+        for message in &request.messages {
+            message.role.hash(&mut hasher);
+            message.content.hash(&mut hasher);
+            message.tool_call_id.hash(&mut hasher);
+            if let Some(calls) = &message.tool_calls {
+                serde_json::to_string(calls)
+                    .unwrap_or_default()
+                    .hash(&mut hasher);
+            }
+        }
+        request.temperature.map(f32::to_bits).hash(&mut hasher);
+        request.n.hash(&mut hasher);
+        // Different tool definitions or output formats can change the answer
+        if let Some(format) = &request.response_format {
+            serde_json::to_string(format)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+        }
+        if let Some(tools) = &request.tools {
+            serde_json::to_string(tools)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+        }
+        if let Some(choice) = &request.tool_choice {
+            serde_json::to_string(choice)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    fn get(&self, key: u64) -> Option<ChatCompletionResponse> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.tick += 1;
+        let tick = state.tick;
+
+        let hit = match state.entries.get_mut(&key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => {
+                entry.last_used = tick;
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                state.entries.remove(&key);
+                self.metrics.evictions.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => None,
+        };
+
+        match hit {
+            Some(_) => self.metrics.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.metrics.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        self.metrics
+            .entries
+            .store(state.entries.len() as u64, Ordering::Relaxed);
+        hit
+    }
+
+    fn insert(&self, key: u64, response: ChatCompletionResponse) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.tick += 1;
+        let tick = state.tick;
+
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+            // Expired entries go first; otherwise drop the least recently used one
+            let ttl = self.ttl;
+            let before = state.entries.len();
+            state.entries.retain(|_, e| e.inserted.elapsed() < ttl);
+            if state.entries.len() >= self.capacity {
+                if let Some(oldest) = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.last_used)
+                    .map(|(k, _)| *k)
+                {
+                    state.entries.remove(&oldest);
+                }
+            }
+            self.metrics
+                .evictions
+                .fetch_add((before - state.entries.len()) as u64, Ordering::Relaxed);
+        }
+
+        state.entries.insert(
+            key,
+            CacheEntry {
+                response,
+                inserted: Instant::now(),
+                last_used: tick,
+            },
+        );
+        self.metrics
+            .entries
+            .store(state.entries.len() as u64, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl LLMProvider for CacheProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let key = Self::cache_key(&request);
+
+        if let Some(mut response) = self.get(key) {
+            debug!("Cache hit for model {}", request.model);
+            response.cached = true;
+            return Ok(response);
+        }
+
+        let response = self.inner.chat(request).await?;
+        self.insert(key, response.clone());
+        Ok(response)
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        self.inner.chat_stream(request).await
+    }
+
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        self.inner.health_check().await
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        self.inner.list_models().await
+    }
+
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        self.inner.stream_usage(model)
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        self.inner.embeddings(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{collect, request, StubProvider};
+
+    fn cached(ttl: Duration, capacity: usize) -> (Arc<StubProvider>, CacheProvider) {
+        let stub = Arc::new(StubProvider::new("Hi"));
+        let cache = CacheProvider::new(stub.clone(), ttl, capacity);
+        (stub, cache)
+    }
+
+    async fn is_hit(cache: &CacheProvider, request: ChatCompletionRequest) -> bool {
+        cache.chat(request).await.unwrap().cached
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_fetched_again() {
+        let (stub, cache) = cached(Duration::from_millis(50), 10);
+        assert!(!is_hit(&cache, request("a")).await);
+        assert!(is_hit(&cache, request("a")).await);
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert!(!is_hit(&cache, request("a")).await);
+        assert_eq!(stub.models(), ["a", "a"]);
+        assert_eq!(cache.metrics().snapshot().evictions, 1);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entry_is_evicted_at_capacity() {
+        let (stub, cache) = cached(Duration::from_secs(60), 2);
+        assert!(!is_hit(&cache, request("a")).await);
+        assert!(!is_hit(&cache, request("b")).await);
+        // Using `a` again leaves `b` as the least recently used
+        assert!(is_hit(&cache, request("a")).await);
+        assert!(!is_hit(&cache, request("c")).await);
+
+        assert!(is_hit(&cache, request("a")).await);
+        assert!(!is_hit(&cache, request("b")).await);
+        assert_eq!(stub.models(), ["a", "b", "c", "b"]);
+        let snapshot = cache.metrics().snapshot();
+        assert_eq!((snapshot.evictions, snapshot.entries), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn streams_bypass_the_cache() {
+        let (stub, cache) = cached(Duration::from_secs(60), 10);
+        cache.chat(request("a")).await.unwrap();
+        for _ in 0..2 {
+            let stream = cache.chat_stream(request("a")).await.unwrap();
+            assert!(collect(stream).await.ends_with("data: [DONE]\n\n"));
+        }
+        assert_eq!(stub.models(), ["a", "a", "a"]);
+        let snapshot = cache.metrics().snapshot();
+        assert_eq!((snapshot.hits, snapshot.misses), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn temperature_and_tools_get_their_own_entries() {
+        let (stub, cache) = cached(Duration::from_secs(60), 10);
+        let warm = ChatCompletionRequest {
+            temperature: Some(0.9),
+            ..request("a")
+        };
+        let with_tools = ChatCompletionRequest {
+            tools: Some(vec![serde_json::json!({
+                "type": "function",
+                "function": {"name": "get_weather", "parameters": {"type": "object"}}
+            })]),
+            ..request("a")
+        };
+
+        for variant in [request("a"), warm.clone(), with_tools.clone()] {
+            assert!(!is_hit(&cache, variant).await);
+        }
+        for variant in [request("a"), warm, with_tools] {
+            assert!(is_hit(&cache, variant).await);
+        }
+        assert_eq!(stub.models().len(), 3);
+    }
+}
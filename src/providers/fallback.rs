@@ -1,17 +1,23 @@
-use crate::models::{ChatCompletionRequest, ChatCompletionResponse};
-use crate::providers::{LLMProvider, ProviderError};
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::Stream;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{info, warn};
 
 /// A provider that tries a primary provider first, and falls back to a backup if it fails.
+/// With a hedge delay, a chat the primary hasn't answered by then is also sent to the
+/// backup, and whichever answers first wins; the other request is cancelled.
 pub struct FallbackProvider {
     primary: Arc<dyn LLMProvider>,
     backup: Arc<dyn LLMProvider>,
     fallback_model: Option<String>,
+    hedge_delay: Option<Duration>,
 }
 
 impl FallbackProvider {
@@ -24,6 +30,70 @@ impl FallbackProvider {
             primary,
             backup,
             fallback_model,
+            hedge_delay: None,
+        }
+    }
+
+    /// Race the backup against a primary that's slower than `delay` (non-streaming chat only).
+    pub fn with_hedge_delay(mut self, delay: Duration) -> Self {
+        self.hedge_delay = Some(delay);
+        self
+    }
+
+    /// The request as sent to the backup, with the fallback model if one is configured.
+    fn backup_request(&self, mut request: ChatCompletionRequest) -> ChatCompletionRequest {
+        if let Some(model) = &self.fallback_model {
+            info!("Overriding model to '{}' for backup request", model);
+            request.model = model.clone();
+        }
+        request
+    }
+
+    async fn hedged_chat(
+        &self,
+        request: ChatCompletionRequest,
+        delay: Duration,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let backup_req = self.backup_request(request.clone());
+        let mut primary = self.primary.chat(request);
+
+        tokio::select! {
+            result = &mut primary => {
+                return match result {
+                    Ok(response) => Ok(response),
+                    Err(e) => {
+                        warn!("Primary provider failed: {}. Switching to backup.", e);
+                        self.backup.chat(backup_req).await
+                    }
+                };
+            }
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        info!(
+            "Primary provider hasn't answered within {:?}, hedging with backup",
+            delay
+        );
+        let mut backup = self.backup.chat(backup_req);
+        // Returning drops the other future, which cancels its request
+        tokio::select! {
+            result = &mut primary => match result {
+                Ok(response) => Ok(response),
+                Err(e) => {
+                    warn!("Primary provider failed: {}. Waiting for backup.", e);
+                    backup.await
+                }
+            },
+            result = &mut backup => match result {
+                Ok(response) => {
+                    info!("Backup provider answered first");
+                    Ok(response)
+                }
+                Err(e) => {
+                    warn!("Backup provider failed: {}. Waiting for primary.", e);
+                    primary.await
+                }
+            },
         }
     }
 }
@@ -34,6 +104,10 @@ impl LLMProvider for FallbackProvider {
         &self,
         request: ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse, ProviderError> {
+        if let Some(delay) = self.hedge_delay {
+            return self.hedged_chat(request, delay).await;
+        }
+
         // Try Primary. We need to clone because if it fails, we need the request again for backup.
         let req_clone = request.clone();
 
@@ -41,15 +115,7 @@ impl LLMProvider for FallbackProvider {
             Ok(response) => Ok(response),
             Err(e) => {
                 warn!("Primary provider failed: {}. Switching to backup.", e);
-
-                // If a fallback model is configured, override the request
-                let mut backup_req = req_clone;
-                if let Some(model) = &self.fallback_model {
-                    info!("Overriding model to '{}' for backup request", model);
-                    backup_req.model = model.clone();
-                }
-
-                self.backup.chat(backup_req).await
+                self.backup.chat(self.backup_request(req_clone)).await
             }
         }
     }
@@ -69,4 +135,54 @@ impl LLMProvider for FallbackProvider {
         warn!("Streaming fallback is not fully supported in this simple implementation. Using Primary only.");
         self.primary.chat_stream(request).await
     }
+
+    /// Healthy as long as either provider can serve requests.
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        match self.primary.health_check().await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                warn!("Primary provider unhealthy: {}", e);
+                self.backup.health_check().await
+            }
+        }
+    }
+
+    /// Models from both providers; fails only if neither can be listed.
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        let (primary, backup) =
+            futures::join!(self.primary.list_models(), self.backup.list_models());
+        match (primary, backup) {
+            (Ok(mut models), Ok(backup)) => {
+                models.extend(backup);
+                Ok(models)
+            }
+            (Ok(models), Err(e)) | (Err(e), Ok(models)) => {
+                warn!("Could not list models from one provider: {}", e);
+                Ok(models)
+            }
+            (Err(e), Err(_)) => Err(e),
+        }
+    }
+
+    /// Follows the primary; a stream served by the backup is assumed to report alike.
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        self.primary.stream_usage(model)
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        // The fallback model is a chat model, so embeddings keep the requested model
+        match self.primary.embeddings(request.clone()).await {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                warn!(
+                    "Primary provider failed embeddings: {}. Switching to backup.",
+                    e
+                );
+                self.backup.embeddings(request).await
+            }
+        }
+    }
 }
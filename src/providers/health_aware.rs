@@ -0,0 +1,300 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+struct Backend {
+    provider: Arc<dyn LLMProvider>,
+    /// Set after a network failure; the backend is skipped until then
+    unhealthy_until: Mutex<Option<Instant>>,
+    /// Set while one request re-probes the backend after its cooldown
+    probing: AtomicBool,
+}
+
+/// Clears `Backend::probing` when the probe ends, even if its request is cancelled.
+struct Probing<'a>(&'a AtomicBool);
+
+impl Drop for Probing<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// A provider that sends each request to the first healthy backend in list order.
+/// A backend that fails with a network error is skipped for `cooldown`, then
+/// re-probed with `health_check` before it gets traffic again. Requests only fail
+/// outright when every backend is unhealthy.
+pub struct HealthAwareProvider {
+    backends: Vec<Backend>,
+    cooldown: Duration,
+}
+
+impl HealthAwareProvider {
+    pub fn new(backends: Vec<Arc<dyn LLMProvider>>, cooldown: Duration) -> Self {
+        assert!(
+            !backends.is_empty(),
+            "HealthAwareProvider needs at least one backend"
+        );
+        Self {
+            backends: backends
+                .into_iter()
+                .map(|provider| Backend {
+                    provider,
+                    unhealthy_until: Mutex::new(None),
+                    probing: AtomicBool::new(false),
+                })
+                .collect(),
+            cooldown,
+        }
+    }
+
+    fn mark_unhealthy(&self, index: usize) {
+        *self.backends[index].unhealthy_until.lock().unwrap() =
+            Some(Instant::now() + self.cooldown);
+    }
+
+    /// Whether backend `index` may take traffic, re-probing it once its cooldown is over.
+    /// Only one request probes; the others skip the backend until the probe succeeds.
+    async fn is_available(&self, index: usize) -> bool {
+        let backend = &self.backends[index];
+        let until = *backend.unhealthy_until.lock().unwrap();
+        match until {
+            None => return true,
+            Some(until) if Instant::now() < until => return false,
+            Some(_) => {}
+        }
+
+        if backend
+            .probing
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return false;
+        }
+        let _probing = Probing(&backend.probing);
+        // A probe may have finished between the check above and taking the flag
+        if backend.unhealthy_until.lock().unwrap().is_none() {
+            return true;
+        }
+
+        match backend.provider.health_check().await {
+            Ok(()) => {
+                info!("Backend {} is healthy again", index);
+                *backend.unhealthy_until.lock().unwrap() = None;
+                true
+            }
+            Err(e) => {
+                warn!("Backend {} still unhealthy: {}", index, e);
+                self.mark_unhealthy(index);
+                false
+            }
+        }
+    }
+
+    /// Runs `call` against healthy backends in order until one succeeds or fails
+    /// with something other than a network error.
+    async fn route<T, F, Fut>(&self, call: F) -> Result<T, ProviderError>
+    where
+        F: Fn(Arc<dyn LLMProvider>) -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let mut last_error = None;
+        for index in 0..self.backends.len() {
+            if !self.is_available(index).await {
+                continue;
+            }
+            match call(self.backends[index].provider.clone()).await {
+                Err(ProviderError::Network(msg)) => {
+                    warn!(
+                        "Backend {} failed, skipping it for {:?}: {}",
+                        index, self.cooldown, msg
+                    );
+                    self.mark_unhealthy(index);
+                    last_error = Some(ProviderError::Network(msg));
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| ProviderError::ProviderError {
+            status: 503,
+            message: String::from("All backends are unhealthy"),
+            retry_after: None,
+        }))
+    }
+}
+
+#[async_trait]
+impl LLMProvider for HealthAwareProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        self.route(|backend| {
+            let request = request.clone();
+            async move { backend.chat(request).await }
+        })
+        .await
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        // Only establishing the stream fails over; nothing has reached the client yet.
+        self.route(|backend| {
+            let request = request.clone();
+            async move { backend.chat_stream(request).await }
+        })
+        .await
+    }
+
+    /// Healthy as long as one backend is available.
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        self.route(|backend| async move { backend.health_check().await })
+            .await
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        self.route(|backend| async move { backend.list_models().await })
+            .await
+    }
+
+    /// Every backend is an Ollama instance from `OLLAMA_URLS`, so the first speaks for
+    /// whichever one is serving.
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        self.backends[0].provider.stream_usage(model)
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        self.route(|backend| {
+            let request = request.clone();
+            async move { backend.embeddings(request).await }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{request, StubProvider};
+    use std::sync::atomic::AtomicUsize;
+
+    /// Unreachable until `recover` is called, then answers "flaky". Health checks
+    /// take a while and are counted.
+    struct Flaky {
+        up: AtomicBool,
+        probes: AtomicUsize,
+        stub: StubProvider,
+    }
+
+    impl Flaky {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                up: AtomicBool::new(false),
+                probes: AtomicUsize::new(0),
+                stub: StubProvider::new("flaky"),
+            })
+        }
+
+        fn recover(&self) {
+            self.up.store(true, Ordering::SeqCst);
+        }
+
+        fn probes(&self) -> usize {
+            self.probes.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl LLMProvider for Flaky {
+        async fn chat(
+            &self,
+            request: ChatCompletionRequest,
+        ) -> Result<ChatCompletionResponse, ProviderError> {
+            if !self.up.load(Ordering::SeqCst) {
+                return Err(ProviderError::Network(String::from("connection refused")));
+            }
+            self.stub.chat(request).await
+        }
+
+        async fn chat_stream(
+            &self,
+            _request: ChatCompletionRequest,
+        ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+        {
+            Err(ProviderError::Network(String::from(
+                "not streamed in these tests",
+            )))
+        }
+
+        async fn health_check(&self) -> Result<(), ProviderError> {
+            self.probes.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            match self.up.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => Err(ProviderError::Network(String::from("connection refused"))),
+            }
+        }
+
+        async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+            Ok(Vec::new())
+        }
+    }
+
+    async fn answer(provider: &HealthAwareProvider) -> String {
+        let response = provider.chat(request("m")).await.unwrap();
+        response.choices[0].message.content.text().into_owned()
+    }
+
+    /// A provider whose first backend just failed and whose cooldown is already over.
+    async fn after_failure(flaky: &Arc<Flaky>) -> HealthAwareProvider {
+        let provider = HealthAwareProvider::new(
+            vec![flaky.clone(), Arc::new(StubProvider::new("backup"))],
+            Duration::ZERO,
+        );
+        assert_eq!(answer(&provider).await, "backup");
+        provider
+    }
+
+    #[tokio::test]
+    async fn only_one_request_probes_after_the_cooldown() {
+        let flaky = Flaky::new();
+        let provider = after_failure(&flaky).await;
+        flaky.recover();
+
+        let answers = futures::future::join_all((0..5).map(|_| answer(&provider))).await;
+        assert_eq!(flaky.probes(), 1);
+        // The prober goes to the recovered backend, the rest to the backup meanwhile
+        assert_eq!(answers.iter().filter(|a| *a == "flaky").count(), 1);
+
+        assert_eq!(answer(&provider).await, "flaky");
+        assert_eq!(flaky.probes(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_probe_lets_the_next_request_probe_again() {
+        let flaky = Flaky::new();
+        let provider = after_failure(&flaky).await;
+
+        assert_eq!(answer(&provider).await, "backup");
+        assert_eq!(flaky.probes(), 1);
+        flaky.recover();
+        assert_eq!(answer(&provider).await, "flaky");
+        assert_eq!(flaky.probes(), 2);
+    }
+}
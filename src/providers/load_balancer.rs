@@ -0,0 +1,140 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+/// A provider that spreads requests across several backends in round-robin order.
+/// On failure it can advance to the next backend, up to `max_tries` backends per request.
+pub struct LoadBalancerProvider {
+    backends: Vec<Arc<dyn LLMProvider>>,
+    cursor: AtomicUsize,
+    max_tries: usize,
+}
+
+impl LoadBalancerProvider {
+    pub fn new(backends: Vec<Arc<dyn LLMProvider>>, max_tries: usize) -> Self {
+        assert!(
+            !backends.is_empty(),
+            "LoadBalancerProvider needs at least one backend"
+        );
+        let max_tries = max_tries.clamp(1, backends.len());
+        Self {
+            backends,
+            cursor: AtomicUsize::new(0),
+            max_tries,
+        }
+    }
+
+    /// Index of the backend to start this request on.
+    fn next_start(&self) -> usize {
+        self.cursor.fetch_add(1, Ordering::Relaxed) % self.backends.len()
+    }
+}
+
+#[async_trait]
+impl LLMProvider for LoadBalancerProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let start = self.next_start();
+        let mut last_error = None;
+
+        for offset in 0..self.max_tries {
+            let index = (start + offset) % self.backends.len();
+            match self.backends[index].chat(request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    warn!("Backend {} failed: {}", index, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("max_tries is at least 1"))
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        let start = self.next_start();
+        let mut last_error = None;
+
+        for offset in 0..self.max_tries {
+            let index = (start + offset) % self.backends.len();
+            match self.backends[index].chat_stream(request.clone()).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    warn!("Backend {} failed to start stream: {}", index, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("max_tries is at least 1"))
+    }
+
+    /// Healthy as long as at least one backend is reachable.
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        let results =
+            futures::future::join_all(self.backends.iter().map(|b| b.health_check())).await;
+        let mut last_error = None;
+        for (index, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!("Backend {} unhealthy: {}", index, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("at least one backend"))
+    }
+
+    /// Backends serve the same models, so the first one that answers is enough.
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.list_models().await {
+                Ok(models) => return Ok(models),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("at least one backend"))
+    }
+
+    /// Backends are replicas of one upstream, so they report usage the same way.
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        self.backends[0].stream_usage(model)
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        let start = self.next_start();
+        let mut last_error = None;
+
+        for offset in 0..self.max_tries {
+            let index = (start + offset) % self.backends.len();
+            match self.backends[index].embeddings(request.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    warn!("Backend {} failed embeddings: {}", index, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("max_tries is at least 1"))
+    }
+}
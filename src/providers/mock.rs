@@ -0,0 +1,133 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, Choice, Delta, Message, ModelInfo, Usage,
+};
+use crate::providers::ollama::sse_chunk;
+use crate::providers::{LLMProvider, ProviderError};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// A provider that needs no upstream (`PROVIDER=mock`): it answers with the last user
+/// message, streamed word by word when asked. Token counts are word counts, so
+/// results are deterministic for tests and UI work.
+pub struct MockProvider;
+
+/// What the mock replies with: the last user message, or nothing.
+fn echo(request: &ChatCompletionRequest) -> String {
+    request
+        .messages
+        .iter()
+        .rev()
+        .find(|m| m.role == "user")
+        .map(|m| m.content.text().into_owned())
+        .unwrap_or_default()
+}
+
+fn usage_for(request: &ChatCompletionRequest, reply: &str) -> Usage {
+    let prompt_tokens = request
+        .messages
+        .iter()
+        .map(|m| m.content.text().split_whitespace().count() as u32)
+        .sum();
+    let completion_tokens = reply.split_whitespace().count() as u32;
+    Usage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[async_trait]
+impl LLMProvider for MockProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let reply = echo(&request);
+        let usage = usage_for(&request, &reply);
+        Ok(ChatCompletionResponse {
+            id: format!("chatcmpl-{}", Uuid::new_v4()),
+            object: String::from("chat.completion"),
+            created: unix_now(),
+            model: request.model,
+            choices: vec![Choice {
+                index: 0,
+                message: Message {
+                    role: String::from("assistant"),
+                    content: reply.into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                },
+                finish_reason: String::from("stop"),
+            }],
+            usage: Some(usage),
+            cached: false,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        let reply = echo(&request);
+        let usage = usage_for(&request, &reply);
+        let id = format!("chatcmpl-{}", Uuid::new_v4());
+        let created = unix_now();
+        let model = request.model;
+
+        let mut events = Vec::new();
+        for (index, word) in reply.split_whitespace().enumerate() {
+            let (role, content) = if index == 0 {
+                (Some(String::from("assistant")), word.to_string())
+            } else {
+                (None, format!(" {}", word))
+            };
+            let delta = Delta {
+                role,
+                content,
+                tool_calls: None,
+            };
+            events.push(sse_chunk(&id, created, &model, delta, None, None));
+        }
+        let last = Delta {
+            role: events.is_empty().then(|| String::from("assistant")),
+            content: String::new(),
+            tool_calls: None,
+        };
+        events.push(sse_chunk(
+            &id,
+            created,
+            &model,
+            last,
+            Some(String::from("stop")),
+            Some(usage),
+        ));
+        events.push(Bytes::from("data: [DONE]\n\n"));
+
+        Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+    }
+
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        Ok(())
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        Ok(vec![ModelInfo {
+            id: String::from("mock"),
+            object: String::from("model"),
+            created: 0,
+            owned_by: String::from("mock"),
+        }])
+    }
+}
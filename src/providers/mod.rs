@@ -3,19 +3,53 @@ use bytes::Bytes;
 use futures::Stream;
 use std::fmt;
 use std::pin::Pin;
+use std::time::Duration;
+pub mod body_log;
+pub mod bounded;
+pub mod cache;
 pub mod fallback;
+pub mod health_aware;
+pub mod load_balancer;
+pub mod mock;
 pub mod ollama;
 pub mod openai;
+pub mod retry;
+pub mod routing;
+pub mod sse;
+pub mod substitution;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod weighted;
 
+pub use body_log::BodyLogging;
+pub use bounded::{BoundedProvider, QueueMetrics};
+pub use cache::{CacheMetrics, CacheProvider};
 pub use fallback::FallbackProvider;
+pub use health_aware::HealthAwareProvider;
+pub use load_balancer::LoadBalancerProvider;
+pub use mock::MockProvider;
+pub use retry::RetryProvider;
+pub use routing::{Route, RoutingProvider};
+pub use sse::sse_events;
+pub use substitution::SubstitutionProvider;
+pub use weighted::WeightedRouterProvider;
 
-use crate::models::{ChatCompletionRequest, ChatCompletionResponse};
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ProviderError {
     Network(String),
     Parse(String),
-    ProviderError { status: u16, message: String },
+    ProviderError {
+        status: u16,
+        message: String,
+        /// How long the upstream asked us to wait before trying again, from
+        /// `Retry-After` or `x-ratelimit-reset*` headers
+        retry_after: Option<Duration>,
+    },
 }
 
 impl fmt::Display for ProviderError {
@@ -23,7 +57,9 @@ impl fmt::Display for ProviderError {
         match self {
             ProviderError::Network(msg) => write!(f, "Network error: {}", msg),
             ProviderError::Parse(msg) => write!(f, "Parse error: {}", msg),
-            ProviderError::ProviderError { status, message } => {
+            ProviderError::ProviderError {
+                status, message, ..
+            } => {
                 write!(f, "Provider error ({}): {}", status, message)
             }
         }
@@ -32,6 +68,245 @@ impl fmt::Display for ProviderError {
 
 impl std::error::Error for ProviderError {}
 
+impl From<reqwest::Error> for ProviderError {
+    fn from(e: reqwest::Error) -> Self {
+        // Only a body that arrived but couldn't be decoded is a parse problem.
+        // Timeouts while reading the body count as network failures so the
+        // FallbackProvider tries the backup.
+        if e.is_decode() {
+            ProviderError::Parse(e.to_string())
+        } else {
+            ProviderError::Network(e.to_string())
+        }
+    }
+}
+
+/// How a provider reports token usage while streaming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamUsage {
+    /// Each usage block is a running total (or usage only arrives once at the end),
+    /// so the latest one seen is authoritative.
+    #[default]
+    Cumulative,
+    /// Each usage block only covers its own chunk and has to be summed.
+    Incremental,
+}
+
+impl std::str::FromStr for StreamUsage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cumulative" => Ok(StreamUsage::Cumulative),
+            "incremental" => Ok(StreamUsage::Incremental),
+            other => Err(format!("unknown stream usage mode '{}'", other)),
+        }
+    }
+}
+
+/// HTTP version used for an upstream (`OLLAMA_HTTP_VERSION`, `OPENAI_HTTP_VERSION`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// Whatever the client negotiates with the server
+    #[default]
+    Auto,
+    /// Never attempt anything newer than HTTP/1.1
+    Http1,
+}
+
+impl std::str::FromStr for HttpVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(HttpVersion::Auto),
+            "http1" | "http1.1" => Ok(HttpVersion::Http1),
+            // reqwest is built without its `http2` feature, so there's no h2 to prefer
+            "http2" | "http2-prior-knowledge" => Err(String::from(
+                "HTTP/2 is not supported by this build (reqwest `http2` feature is disabled)",
+            )),
+            other => Err(format!(
+                "unknown HTTP version '{}' (expected auto or http1)",
+                other
+            )),
+        }
+    }
+}
+
+/// Timeouts applied to upstream HTTP calls.
+#[derive(Debug, Clone, Copy)]
+pub struct ProviderTimeouts {
+    /// Total time allowed for a non-streaming request, including the body.
+    pub request: Duration,
+    /// Time allowed to establish the connection. Applies to streaming requests too.
+    pub connect: Duration,
+}
+
+/// Connection reuse for upstream clients (`PROVIDER_POOL_*`).
+#[derive(Debug, Clone, Copy)]
+pub struct PoolSettings {
+    /// Idle connections kept open per upstream host
+    pub max_idle_per_host: usize,
+    /// How long an idle connection is kept before it's closed
+    pub idle_timeout: Duration,
+    /// TCP keep-alive interval, so idle pooled connections aren't silently dropped
+    pub tcp_keepalive: Duration,
+}
+
+impl ProviderTimeouts {
+    /// Build a client with the connect timeout only. The total timeout is set per
+    /// request so long-running streams aren't cut off.
+    ///
+    /// Clients pool connections internally and are cheap to clone, so providers with
+    /// the same settings should share one instead of each building their own.
+    pub fn build_client(&self, http_version: HttpVersion, pool: &PoolSettings) -> reqwest::Client {
+        let builder = reqwest::Client::builder()
+            .connect_timeout(self.connect)
+            .pool_max_idle_per_host(pool.max_idle_per_host)
+            .pool_idle_timeout(pool.idle_timeout)
+            .tcp_keepalive(pool.tcp_keepalive);
+        let builder = match http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+        };
+        builder.build().expect("Failed to build HTTP client")
+    }
+}
+
+/// Forwards the gateway's correlation ID upstream as `X-Request-Id`, when there is one.
+trait WithRequestId {
+    fn request_id(self, request_id: Option<&str>) -> Self;
+}
+
+impl WithRequestId for reqwest::RequestBuilder {
+    fn request_id(self, request_id: Option<&str>) -> Self {
+        match request_id {
+            Some(id) => self.header("X-Request-Id", id),
+            None => self,
+        }
+    }
+}
+
+/// Pass a successful upstream response through; anything else becomes
+/// `ProviderError::ProviderError` with the upstream's status and body, so clients see
+/// its own 400/401/429 rather than a parse failure.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, ProviderError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = retry_after(response.headers());
+    Err(ProviderError::ProviderError {
+        status: status.as_u16(),
+        message: response.text().await.unwrap_or_default(),
+        retry_after,
+    })
+}
+
+/// The wait an upstream asked for: `Retry-After` (seconds or an HTTP date), else the
+/// longest of OpenAI's `x-ratelimit-reset-requests`/`-tokens` (`1s`, `6m0s`, `20ms`)
+/// or a plain `x-ratelimit-reset` (seconds, or a UNIX timestamp).
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+
+    if let Some(value) = header("retry-after") {
+        if let Some(delay) = parse_seconds(value) {
+            return Some(delay);
+        }
+        if let Ok(date) = value.parse::<actix_web::http::header::HttpDate>() {
+            let date: std::time::SystemTime = date.into();
+            return Some(
+                date.duration_since(std::time::SystemTime::now())
+                    .unwrap_or_default(),
+            );
+        }
+    }
+
+    [
+        "x-ratelimit-reset-requests",
+        "x-ratelimit-reset-tokens",
+        "x-ratelimit-reset",
+    ]
+    .into_iter()
+    .filter_map(header)
+    .filter_map(|value| parse_go_duration(value).or_else(|| parse_reset(value)))
+    .max()
+}
+
+fn parse_seconds(value: &str) -> Option<Duration> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+/// Seconds from now, or a UNIX timestamp in seconds when it's that large.
+fn parse_reset(value: &str) -> Option<Duration> {
+    let secs = parse_seconds(value)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    if secs.as_secs() > 1_000_000_000 {
+        Some(secs.saturating_sub(now))
+    } else {
+        Some(secs)
+    }
+}
+
+/// Durations like `1h2m3.5s` or `250ms`. Values too large for a `Duration` are `None`.
+fn parse_go_duration(value: &str) -> Option<Duration> {
+    if value.is_empty() {
+        return None;
+    }
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .filter(|len| *len > 0)?;
+        let (number, tail) = rest.split_at(number_len);
+        let number: f64 = number.parse().ok()?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let secs = match unit {
+            "h" => number * 3600.0,
+            "m" => number * 60.0,
+            "s" => number,
+            "ms" => number / 1000.0,
+            _ => return None,
+        };
+        total = total.checked_add(Duration::try_from_secs_f64(secs).ok()?)?;
+        rest = tail;
+    }
+    Some(total)
+}
+
+/// Upper bound for a single health probe, independent of the request timeout.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Send a lightweight probe request; any 2xx response counts as healthy.
+async fn probe(request: reqwest::RequestBuilder) -> Result<(), ProviderError> {
+    let response = request.timeout(HEALTH_CHECK_TIMEOUT).send().await?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(ProviderError::ProviderError {
+            status: status.as_u16(),
+            message: format!("Health check returned {}", status),
+            retry_after: None,
+        })
+    }
+}
+
 #[async_trait]
 pub trait LLMProvider: Send + Sync {
     async fn chat(
@@ -42,4 +317,212 @@ pub trait LLMProvider: Send + Sync {
         &self,
         req: ChatCompletionRequest,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>;
+
+    /// Check that the upstream is reachable, without running a completion.
+    async fn health_check(&self) -> Result<(), ProviderError>;
+
+    /// Models the upstream currently serves, in OpenAI's `/v1/models` shape.
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError>;
+
+    /// How streamed usage for `model` should be interpreted.
+    fn stream_usage(&self, _model: &str) -> StreamUsage {
+        StreamUsage::Cumulative
+    }
+
+    /// Providers without an embeddings API keep the default, which reports 501.
+    async fn embeddings(&self, _req: EmbeddingRequest) -> Result<EmbeddingResponse, ProviderError> {
+        Err(ProviderError::ProviderError {
+            status: 501,
+            message: "Embeddings are not supported by this provider".to_string(),
+            retry_after: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// An HTTP/1.1 server answering `ok` to every request on a connection until the
+    /// client closes it. Returns its URL and how many connections it has accepted.
+    fn counting_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    loop {
+                        // Skip to the blank line ending the request's headers
+                        loop {
+                            line.clear();
+                            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                                return;
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                        }
+                        let reply = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+                        if stream.write_all(reply).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (url, accepted)
+    }
+
+    fn client(max_idle_per_host: usize) -> reqwest::Client {
+        let timeouts = ProviderTimeouts {
+            request: Duration::from_secs(5),
+            connect: Duration::from_secs(5),
+        };
+        let pool = PoolSettings {
+            max_idle_per_host,
+            idle_timeout: Duration::from_secs(30),
+            tcp_keepalive: Duration::from_secs(30),
+        };
+        timeouts.build_client(HttpVersion::Http1, &pool)
+    }
+
+    async fn connections_for_two_requests(max_idle_per_host: usize) -> usize {
+        let (url, accepted) = counting_server();
+        let client = client(max_idle_per_host);
+        for _ in 0..2 {
+            let body = client.get(&url).send().await.unwrap().text().await.unwrap();
+            assert_eq!(body, "ok");
+        }
+        accepted.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn http1_client_speaks_http_1_1() {
+        let (url, _) = counting_server();
+        let client = client(1);
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_11);
+    }
+
+    #[tokio::test]
+    async fn pooled_client_reuses_its_connection() {
+        assert_eq!(connections_for_two_requests(4).await, 1);
+    }
+
+    #[tokio::test]
+    async fn client_without_idle_slots_reconnects() {
+        assert_eq!(connections_for_two_requests(0).await, 2);
+    }
+
+    fn retry_after_from(headers: &[(&'static str, &str)]) -> Option<Duration> {
+        let mut map = reqwest::header::HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, value.parse().unwrap());
+        }
+        retry_after(&map)
+    }
+
+    #[test]
+    fn retry_after_reads_seconds_and_http_dates() {
+        assert_eq!(
+            retry_after_from(&[("retry-after", "2.5")]),
+            Some(Duration::from_millis(2500))
+        );
+        let in_a_minute = std::time::SystemTime::now() + Duration::from_secs(60);
+        let date = actix_web::http::header::HttpDate::from(in_a_minute).to_string();
+        let wait = retry_after_from(&[("retry-after", &date)]).unwrap();
+        // HTTP dates only have whole seconds
+        assert!(
+            wait > Duration::from_secs(58) && wait <= Duration::from_secs(60),
+            "{:?}",
+            wait
+        );
+    }
+
+    #[test]
+    fn retry_after_takes_the_longest_openai_reset() {
+        let wait = retry_after_from(&[
+            ("x-ratelimit-reset-requests", "20ms"),
+            ("x-ratelimit-reset-tokens", "6m0s"),
+        ]);
+        assert_eq!(wait, Some(Duration::from_secs(360)));
+        assert_eq!(
+            retry_after_from(&[("x-ratelimit-reset-requests", "1h2m3.5s")]),
+            Some(Duration::from_millis(3_723_500))
+        );
+    }
+
+    #[test]
+    fn retry_after_reads_a_unix_timestamp_reset() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let reset = (now + 120).to_string();
+        let wait = retry_after_from(&[("x-ratelimit-reset", &reset)]).unwrap();
+        assert!(
+            wait > Duration::from_secs(118) && wait <= Duration::from_secs(120),
+            "{:?}",
+            wait
+        );
+        assert_eq!(
+            retry_after_from(&[("x-ratelimit-reset", "30")]),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn retry_after_ignores_garbage_empty_and_huge_values() {
+        for value in ["soon", "-5", "NaN", "inf", "1e20", "", "5x"] {
+            assert_eq!(
+                retry_after_from(&[("retry-after", value)]),
+                None,
+                "{}",
+                value
+            );
+            assert_eq!(
+                retry_after_from(&[("x-ratelimit-reset", value)]),
+                None,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(
+            retry_after_from(&[("x-ratelimit-reset-requests", "99999999999999999999h")]),
+            None
+        );
+        // Each part fits, but their sum doesn't
+        let overflow = format!("{}h{}h", u64::MAX / 6000, u64::MAX / 6000);
+        assert_eq!(
+            retry_after_from(&[("x-ratelimit-reset-tokens", &overflow)]),
+            None
+        );
+        assert_eq!(retry_after_from(&[]), None);
+    }
+
+    #[test]
+    fn http_version_accepts_auto_and_http1_spellings() {
+        assert_eq!("auto".parse(), Ok(HttpVersion::Auto));
+        assert_eq!(" HTTP1 ".parse(), Ok(HttpVersion::Http1));
+        assert_eq!("http1.1".parse(), Ok(HttpVersion::Http1));
+    }
+
+    #[test]
+    fn http_version_rejects_http2_and_unknown_values() {
+        let http2 = "http2".parse::<HttpVersion>().unwrap_err();
+        assert!(http2.contains("not supported"), "{}", http2);
+        assert!("http2-prior-knowledge".parse::<HttpVersion>().is_err());
+        let unknown = "h3".parse::<HttpVersion>().unwrap_err();
+        assert!(unknown.contains("unknown HTTP version 'h3'"), "{}", unknown);
+    }
 }
@@ -1,46 +1,86 @@
 use crate::models::{
-    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, Choice, ChunkChoice, Delta,
-    OllamaRequest, OllamaResponse, OllamaStreamChunk, Usage,
+    ApiError, ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, Choice,
+    ChunkChoice, Delta, Embedding, EmbeddingRequest, EmbeddingResponse, EmbeddingUsage, Message,
+    ModelInfo, OllamaEmbeddingRequest, OllamaEmbeddingResponse, OllamaMessage, OllamaRequest,
+    OllamaResponse, OllamaStreamChunk, OllamaTagsResponse, OllamaToolCall, ResponseFormat, Usage,
+};
+use crate::providers::body_log::read_json;
+use crate::providers::{
+    check_status, probe, BodyLogging, LLMProvider, ProviderError, ProviderTimeouts, WithRequestId,
 };
-use crate::providers::{LLMProvider, ProviderError};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
 use reqwest::Client;
+use std::future::Future;
 use std::pin::Pin;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::info;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{info, warn};
 use uuid::Uuid;
 
 pub struct OllamaProvider {
     client: Client,
     base_url: String,
+    request_timeout: Duration,
+    keepalive: Option<Duration>,
+    body_logging: Option<BodyLogging>,
 }
 
 impl OllamaProvider {
-    pub fn new(base_url: String) -> Self {
-        let client = Client::new();
+    /// `client` may be shared with other providers; see `ProviderTimeouts::build_client`.
+    pub fn new(base_url: String, client: Client, timeouts: ProviderTimeouts) -> Self {
+        Self {
+            client,
+            base_url,
+            request_timeout: timeouts.request,
+            keepalive: None,
+            body_logging: None,
+        }
+    }
 
-        Self { client, base_url }
+    /// Send an SSE comment on streams that have been idle for `interval` (`SSE_KEEPALIVE_SECS`),
+    /// so proxies don't close them while the model is slow to answer. This includes the
+    /// wait for Ollama's response headers, which can be long while a model loads.
+    pub fn with_keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.keepalive = interval;
+        self
     }
-}
 
-#[async_trait]
-impl LLMProvider for OllamaProvider {
-    async fn chat(
+    /// Log request and response bodies at debug level (`LOG_BODIES`).
+    pub fn with_body_logging(mut self, body_logging: Option<BodyLogging>) -> Self {
+        self.body_logging = body_logging;
+        self
+    }
+
+    /// One non-streaming completion
+    async fn complete(
         &self,
         req: ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse, ProviderError> {
         info!("Processing request...");
+        let timeout = req.timeout.unwrap_or(self.request_timeout);
+        let request_id = req.request_id;
         let ollama_request = OllamaRequest {
             model: req.model,
-            messages: req.messages,
+            messages: to_ollama_messages(req.messages)?,
             stream: false,
+            format: req
+                .response_format
+                .as_ref()
+                .and_then(ResponseFormat::to_ollama_format),
+            tools: req.tools,
         };
+        if let Some(logging) = &self.body_logging {
+            logging.request("Ollama", &ollama_request);
+        }
 
         let response = self
             .client
             .post(format!("{}/api/chat", self.base_url)) // "http://localhost:11434/api/chat"
+            .request_id(request_id.as_deref())
+            .timeout(timeout)
             .json(&ollama_request)
             .send()
             .await;
@@ -52,17 +92,22 @@ impl LLMProvider for OllamaProvider {
             }
         };
 
-        let ollama_data = match ollama_response.json::<OllamaResponse>().await {
-            Ok(data) => data,
-            Err(e) => {
-                return Err(ProviderError::Parse(e.to_string()));
-            }
-        };
+        // e.g. 404 `model "x" not found, try pulling it first`
+        let ollama_response = check_status(ollama_response).await?;
 
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let ollama_data: OllamaResponse =
+            read_json(ollama_response, self.body_logging, "Ollama").await?;
+
+        let timestamp = created_timestamp(&ollama_data.created_at);
+
+        let tool_calls = ollama_data.message.tool_calls.map(to_openai_tool_calls);
+        let finish_reason = finish_reason(ollama_data.done_reason.as_deref(), tool_calls.is_some());
+        let message = Message {
+            role: ollama_data.message.role,
+            content: ollama_data.message.content.into(),
+            tool_calls,
+            tool_call_id: None,
+        };
 
         let chat_completion_response = ChatCompletionResponse {
             id: format!("chatcmpl-{}", Uuid::new_v4()),
@@ -71,39 +116,86 @@ impl LLMProvider for OllamaProvider {
             model: ollama_data.model,
             choices: vec![Choice {
                 index: 0,
-                message: ollama_data.message,
-                finish_reason: String::from("stop"),
+                message,
+                finish_reason,
             }],
-            usage: Usage {
+            usage: Some(Usage {
                 prompt_tokens: ollama_data.prompt_eval_count,
                 completion_tokens: ollama_data.eval_count,
                 total_tokens: ollama_data.prompt_eval_count + ollama_data.eval_count,
-            },
+            }),
+            cached: false,
         };
 
         info!("Request has been processed successfully");
         Ok(chat_completion_response)
     }
+}
+
+#[async_trait]
+impl LLMProvider for OllamaProvider {
+    async fn chat(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        // Ollama has no `n`, so each extra completion is a call of its own
+        let n = req.n.unwrap_or(1);
+        if n <= 1 {
+            return self.complete(req).await;
+        }
+        let results = futures::future::join_all((0..n).map(|_| self.complete(req.clone()))).await;
+        let mut responses = results.into_iter();
+        let mut merged = responses.next().expect("n is at least 2")?;
+        for response in responses {
+            let response = response?;
+            if let (Some(total), Some(usage)) = (&mut merged.usage, response.usage) {
+                total.prompt_tokens += usage.prompt_tokens;
+                total.completion_tokens += usage.completion_tokens;
+                total.total_tokens += usage.total_tokens;
+            }
+            merged.choices.extend(response.choices);
+        }
+        for (index, choice) in merged.choices.iter_mut().enumerate() {
+            choice.index = index as u32;
+        }
+        Ok(merged)
+    }
 
     async fn chat_stream(
         &self,
         req: ChatCompletionRequest,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
     {
+        if let Some(n) = req.n.filter(|n| *n > 1) {
+            warn!("Ollama streams one completion, ignoring n={}", n);
+        }
         let ollama_request = OllamaRequest {
             model: req.model.clone(),
-            messages: req.messages,
+            messages: to_ollama_messages(req.messages)?,
             stream: true,
+            format: req
+                .response_format
+                .as_ref()
+                .and_then(ResponseFormat::to_ollama_format),
+            tools: req.tools,
         };
+        if let Some(logging) = &self.body_logging {
+            logging.request("Ollama", &ollama_request);
+        }
 
         info!("Calling provider...");
-        let response = self
+        let send = self
             .client
             .post(format!("{}/api/chat", self.base_url))
+            .request_id(req.request_id.as_deref())
             .json(&ollama_request)
-            .send()
-            .await
-            .map_err(|e| ProviderError::Network(e.to_string()))?;
+            .send();
+        let mut upstream = Box::pin(async move {
+            let response = send
+                .await
+                .map_err(|e| ProviderError::Network(e.to_string()))?;
+            Ok(check_status(response).await?.bytes_stream())
+        });
 
         let response_id = format!("chatcmpl-{}", Uuid::new_v4());
         let timestamp = SystemTime::now()
@@ -111,74 +203,626 @@ impl LLMProvider for OllamaProvider {
             .unwrap()
             .as_secs();
 
-        let model_name = req.model;
+        // Ollama may not answer at all for a while, e.g. while it loads the model. An
+        // error within the first keep-alive period is returned as usual, so fallbacks
+        // still apply; after that the client is sent keep-alives while it waits.
+        let byte_stream = match self.keepalive {
+            Some(period) => match tokio::time::timeout(period, &mut upstream).await {
+                Ok(byte_stream) => byte_stream?,
+                Err(_) => {
+                    return Ok(Box::pin(keepalive_until_ready(
+                        upstream,
+                        response_id,
+                        timestamp,
+                        req.model,
+                        period,
+                        self.body_logging,
+                    )))
+                }
+            },
+            None => upstream.await?,
+        };
 
-        let sse_stream = async_stream::stream! {
-            let mut byte_stream = response.bytes_stream();
+        Ok(Box::pin(to_sse_stream(
+            byte_stream,
+            response_id,
+            timestamp,
+            req.model,
+            self.keepalive,
+            self.body_logging,
+        )))
+    }
 
-            while let Some(chunk_result) = byte_stream.next().await {
-                match chunk_result {
-                    Ok(bytes) => {
-                        let text = String::from_utf8_lossy(&bytes);
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        probe(self.client.get(format!("{}/api/tags", self.base_url))).await
+    }
 
-                        for line in text.lines() {
-                            if line.trim().is_empty() {
-                                continue;
-                            }
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        let response = self
+            .client
+            .get(format!("{}/api/tags", self.base_url))
+            .timeout(self.request_timeout)
+            .send()
+            .await?;
+        let response = check_status(response).await?;
+
+        let tags = response.json::<OllamaTagsResponse>().await?;
+        Ok(tags
+            .models
+            .into_iter()
+            .map(|tag| ModelInfo {
+                id: tag.name,
+                object: String::from("model"),
+                created: 0,
+                owned_by: String::from("ollama"),
+            })
+            .collect())
+    }
+
+    async fn embeddings(&self, req: EmbeddingRequest) -> Result<EmbeddingResponse, ProviderError> {
+        // /api/embeddings takes one prompt per call, so batches fan out concurrently
+        let inputs = req.input.into_vec();
+        let request_id = req.request_id.as_deref();
+        let calls = inputs.into_iter().map(|prompt| {
+            let body = OllamaEmbeddingRequest {
+                model: req.model.clone(),
+                prompt,
+            };
+            async move {
+                let response = self
+                    .client
+                    .post(format!("{}/api/embeddings", self.base_url))
+                    .request_id(request_id)
+                    .timeout(self.request_timeout)
+                    .json(&body)
+                    .send()
+                    .await
+                    .map_err(|e| ProviderError::Network(e.to_string()))?;
+
+                check_status(response)
+                    .await?
+                    .json::<OllamaEmbeddingResponse>()
+                    .await
+                    .map_err(ProviderError::from)
+            }
+        });
+
+        let results = futures::future::try_join_all(calls).await?;
+
+        Ok(EmbeddingResponse {
+            object: String::from("list"),
+            data: results
+                .into_iter()
+                .enumerate()
+                .map(|(index, result)| Embedding {
+                    object: String::from("embedding"),
+                    embedding: result.embedding,
+                    index: index as u32,
+                })
+                .collect(),
+            model: req.model,
+            // Ollama doesn't report token counts for embeddings
+            usage: EmbeddingUsage {
+                prompt_tokens: 0,
+                total_tokens: 0,
+            },
+        })
+    }
+}
+/// Keep-alives every `keepalive` until `upstream` has answered, then its stream
+/// re-framed by `to_sse_stream`. Failing by then ends the stream with an error event.
+fn keepalive_until_ready<F, S, E>(
+    upstream: F,
+    response_id: String,
+    timestamp: u64,
+    model_name: String,
+    keepalive: Duration,
+    body_logging: Option<BodyLogging>,
+) -> impl Stream<Item = Result<Bytes, ProviderError>> + Send
+where
+    F: Future<Output = Result<S, ProviderError>> + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: std::fmt::Display + Send,
+{
+    async_stream::stream! {
+        // A whole period has already passed without a word from upstream
+        yield Ok::<_, ProviderError>(Bytes::from_static(b": keep-alive\n\n"));
+        let mut upstream = Box::pin(upstream);
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + keepalive, keepalive);
+        let result = loop {
+            // `None` means the ticker fired first
+            let ready = tokio::select! {
+                result = &mut upstream => Some(result),
+                _ = ticker.tick() => None,
+            };
+            match ready {
+                Some(result) => break result,
+                None => yield Ok(Bytes::from_static(b": keep-alive\n\n")),
+            }
+        };
+
+        match result {
+            Ok(byte_stream) => {
+                let mut events = Box::pin(to_sse_stream(byte_stream, response_id, timestamp, model_name, Some(keepalive), body_logging));
+                while let Some(event) = events.next().await {
+                    yield event;
+                }
+            }
+            Err(e) => {
+                warn!("Ollama failed after the stream had started: {}", e);
+                yield Ok(sse_error(&ApiError::stream_interrupted(&e.to_string())));
+                yield Ok(Bytes::from("data: [DONE]\n\n"));
+            }
+        }
+    }
+}
 
-                            match serde_json::from_str::<OllamaStreamChunk>(line) {
-                                Ok(ollama_chunk) => {
-                                    if ollama_chunk.message.content.is_empty() && !ollama_chunk.done {
-                                        continue;
-                                    }
-
-                                    let openai_chunk = ChatCompletionChunk {
-                                        id: response_id.clone(),
-                                        object: String::from("chat.completion.chunk"),
-                                        created: timestamp,
-                                        model: model_name.clone(),
-                                        choices: vec![ChunkChoice {
-                                            index: 0,
-                                            delta: Delta {
-                                                role: None,
-                                                content: ollama_chunk.message.content,
-                                            },
-                                            finish_reason: if ollama_chunk.done {
-                                                Some(String::from("stop"))
-                                            } else {
-                                                None
-                                            },
-                                        }],
-                                        usage: if ollama_chunk.done {
-                                            Some(Usage {
-                                                prompt_tokens: ollama_chunk.prompt_eval_count.unwrap_or(0),
-                                                completion_tokens: ollama_chunk.eval_count.unwrap_or(0),
-                                                total_tokens: ollama_chunk.prompt_eval_count.unwrap_or(0) + ollama_chunk.eval_count.unwrap_or(0),
-                                            })
-                                        } else {
-                                            None
-                                        },
-                                    };
-
-                                    let json = serde_json::to_string(&openai_chunk).unwrap();
-                                    let sse_event = format!("data: {}\n\n", json);
-                                    yield Ok::<_, ProviderError>(Bytes::from(sse_event));
+/// Ollama's NDJSON stream re-framed as OpenAI SSE chunks, ending with `[DONE]`.
+fn to_sse_stream<S, E>(
+    byte_stream: S,
+    response_id: String,
+    timestamp: u64,
+    model_name: String,
+    keepalive: Option<Duration>,
+    body_logging: Option<BodyLogging>,
+) -> impl Stream<Item = Result<Bytes, ProviderError>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: std::fmt::Display + Send,
+{
+    async_stream::stream! {
+        let mut byte_stream = Box::pin(byte_stream);
+        let mut emitted_any = false;
+        let mut finished = false;
+        let mut interrupted = false;
+        let mut saw_tool_calls = false;
+        let mut ticker = keepalive.map(|period| {
+            tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+        });
+
+        loop {
+            // `None` means the ticker fired before upstream sent anything
+            let next = match &mut ticker {
+                Some(ticker) => tokio::select! {
+                    chunk = byte_stream.next() => {
+                        ticker.reset();
+                        Some(chunk)
+                    }
+                    _ = ticker.tick() => None,
+                },
+                None => Some(byte_stream.next().await),
+            };
+            let Some(next) = next else {
+                yield Ok::<_, ProviderError>(Bytes::from_static(b": keep-alive\n\n"));
+                continue;
+            };
+            let Some(chunk_result) = next else {
+                break;
+            };
+
+            match chunk_result {
+                Ok(bytes) => {
+                    if let Some(logging) = &body_logging {
+                        logging.response("Ollama", &bytes);
+                    }
+                    let text = String::from_utf8_lossy(&bytes);
+
+                    for line in text.lines() {
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+
+                        match serde_json::from_str::<OllamaStreamChunk>(line) {
+                            Ok(ollama_chunk) => {
+                                // Content-less chunks only matter if they carry tool calls
+                                let tool_calls = ollama_chunk.message.tool_calls.map(to_openai_tool_call_deltas);
+                                if ollama_chunk.message.content.is_empty() && tool_calls.is_none() && !ollama_chunk.done {
+                                    continue;
                                 }
-                                Err(e) => {
-                                    info!("Failed to parse chunk: {}", e);
+
+                                let upstream_role = &ollama_chunk.message.role;
+                                if !upstream_role.is_empty() && upstream_role != "assistant" {
+                                    warn!("Ignoring unexpected role '{}' in Ollama stream", upstream_role);
                                 }
+
+                                // Like OpenAI, only the first chunk carries the assistant role
+                                let role = if !emitted_any {
+                                    Some(String::from("assistant"))
+                                } else {
+                                    None
+                                };
+
+                                let usage = if ollama_chunk.done {
+                                    Some(Usage {
+                                        prompt_tokens: ollama_chunk.prompt_eval_count.unwrap_or(0),
+                                        completion_tokens: ollama_chunk.eval_count.unwrap_or(0),
+                                        total_tokens: ollama_chunk.prompt_eval_count.unwrap_or(0) + ollama_chunk.eval_count.unwrap_or(0),
+                                    })
+                                } else {
+                                    None
+                                };
+
+                                saw_tool_calls |= tool_calls.is_some();
+                                let finish_reason = ollama_chunk
+                                    .done
+                                    .then(|| finish_reason(ollama_chunk.done_reason.as_deref(), saw_tool_calls));
+
+                                emitted_any = true;
+                                finished |= ollama_chunk.done;
+                                yield Ok::<_, ProviderError>(sse_chunk(
+                                    &response_id,
+                                    timestamp,
+                                    &model_name,
+                                    Delta { role, content: ollama_chunk.message.content, tool_calls },
+                                    finish_reason,
+                                    usage,
+                                ));
+                            }
+                            Err(e) => {
+                                info!("Failed to parse chunk: {}", e);
                             }
                         }
                     }
-                    Err(e) => {
-                        info!("Stream error: {}", e);
-                        break;
-                    }
                 }
+                Err(e) => {
+                    warn!("Ollama stream broke off: {}", e);
+                    // Like OpenAI, an error event tells clients the completion is truncated
+                    yield Ok::<_, ProviderError>(sse_error(&ApiError::stream_interrupted(&e.to_string())));
+                    interrupted = true;
+                    break;
+                }
+            }
+        }
+
+        // Upstream ended without a `done` chunk: close the completion properly
+        // instead of sending a bare [DONE].
+        if !finished && !interrupted {
+            yield Ok::<_, ProviderError>(sse_chunk(
+                &response_id,
+                timestamp,
+                &model_name,
+                Delta {
+                    role: if emitted_any { None } else { Some(String::from("assistant")) },
+                    content: String::new(),
+                    tool_calls: None,
+                },
+                Some(String::from("stop")),
+                None,
+            ));
+        }
+        yield Ok::<_, ProviderError>(Bytes::from("data: [DONE]\n\n"));
+    }
+}
+
+/// OpenAI's `finish_reason` for a completed response. Older Ollama versions send no
+/// `done_reason`, which is treated as a normal stop.
+fn finish_reason(done_reason: Option<&str>, has_tool_calls: bool) -> String {
+    let reason = match done_reason {
+        Some("length") => "length",
+        _ if has_tool_calls => "tool_calls",
+        _ => "stop",
+    };
+    String::from(reason)
+}
+
+/// When Ollama produced the response (its RFC 3339 `created_at`) as Unix seconds,
+/// or now if that's missing or malformed.
+fn created_timestamp(created_at: &str) -> u64 {
+    OffsetDateTime::parse(created_at, &Rfc3339)
+        .ok()
+        .and_then(|created| u64::try_from(created.unix_timestamp()).ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        })
+}
+
+/// OpenAI's shape: with an ID and JSON-encoded arguments.
+fn to_openai_tool_calls(calls: Vec<OllamaToolCall>) -> Vec<serde_json::Value> {
+    calls
+        .into_iter()
+        .map(|call| {
+            serde_json::json!({
+                "id": format!("call_{}", Uuid::new_v4().simple()),
+                "type": "function",
+                "function": {
+                    "name": call.function.name,
+                    "arguments": call.function.arguments.to_string(),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Streamed tool calls additionally carry their position.
+fn to_openai_tool_call_deltas(calls: Vec<OllamaToolCall>) -> Vec<serde_json::Value> {
+    let mut calls = to_openai_tool_calls(calls);
+    for (index, call) in calls.iter_mut().enumerate() {
+        call["index"] = serde_json::json!(index);
+    }
+    calls
+}
+
+/// Ollama expects tool call arguments in the conversation history as objects, where
+/// OpenAI clients send them JSON-encoded, and images as bare base64 next to the text.
+fn to_ollama_messages(messages: Vec<Message>) -> Result<Vec<OllamaMessage>, ProviderError> {
+    messages
+        .into_iter()
+        .map(|mut message| {
+            for call in message.tool_calls.iter_mut().flatten() {
+                let arguments = &mut call["function"]["arguments"];
+                if let Some(parsed) = arguments
+                    .as_str()
+                    .and_then(|raw| serde_json::from_str::<serde_json::Value>(raw).ok())
+                {
+                    *arguments = parsed;
+                }
+            }
+            let images = message
+                .content
+                .image_urls()
+                .map(base64_image)
+                .collect::<Result<_, _>>()?;
+            Ok(OllamaMessage {
+                role: message.role,
+                content: message.content.text().into_owned(),
+                images,
+                tool_calls: message.tool_calls,
+                tool_call_id: message.tool_call_id,
+            })
+        })
+        .collect()
+}
+
+/// The payload of a `data:image/...;base64,` URL; Ollama can't fetch remote images.
+fn base64_image(url: &str) -> Result<String, ProviderError> {
+    url.strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+        .map(|(_, data)| data.to_string())
+        .ok_or_else(|| ProviderError::ProviderError {
+            status: 400,
+            message: String::from("Ollama only accepts images as base64 data: URLs"),
+            retry_after: None,
+        })
+}
+
+/// An OpenAI-style `{"error": ...}` SSE event, for failures after the stream started.
+pub(super) fn sse_error(error: &ApiError) -> Bytes {
+    let json = serde_json::to_string(error).unwrap();
+    Bytes::from(format!("data: {}\n\n", json))
+}
+
+/// Serialize one OpenAI-style chunk as an SSE `data:` event.
+pub(super) fn sse_chunk(
+    id: &str,
+    created: u64,
+    model: &str,
+    delta: Delta,
+    finish_reason: Option<String>,
+    usage: Option<Usage>,
+) -> Bytes {
+    let chunk = ChatCompletionChunk {
+        id: id.to_string(),
+        object: String::from("chat.completion.chunk"),
+        created,
+        model: model.to_string(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+        }],
+        usage,
+    };
+
+    let json = serde_json::to_string(&chunk).unwrap();
+    Bytes::from(format!("data: {}\n\n", json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ChatCompletionChunk;
+
+    /// The chunks `reads` of Ollama NDJSON turn into, and whether `[DONE]` came last.
+    async fn convert(reads: &[&str]) -> (Vec<ChatCompletionChunk>, bool) {
+        let reads: Vec<Result<Bytes, ProviderError>> = reads
+            .iter()
+            .map(|read| Ok(Bytes::from(read.to_string())))
+            .collect();
+        let stream = to_sse_stream(
+            futures::stream::iter(reads),
+            String::from("chatcmpl-test"),
+            0,
+            String::from("llama3.2"),
+            None,
+            None,
+        );
+        let body: Vec<_> = stream.collect().await;
+        let body: String = body
+            .into_iter()
+            .map(|event| String::from_utf8(event.unwrap().to_vec()).unwrap())
+            .collect();
+        let events: Vec<&str> = body
+            .split("\n\n")
+            .filter_map(|event| event.strip_prefix("data: "))
+            .collect();
+        let done = events.last() == Some(&"[DONE]");
+        let chunks = events
+            .iter()
+            .filter(|data| **data != "[DONE]")
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        (chunks, done)
+    }
+
+    fn line(content: &str, done: bool) -> String {
+        let line = serde_json::json!({
+            "model": "llama3.2",
+            "message": {"role": "assistant", "content": content},
+            "done": done,
+        });
+        format!("{}\n", line)
+    }
+
+    #[tokio::test]
+    async fn only_the_first_delta_carries_the_role() {
+        let (chunks, done) = convert(&[
+            &line("Hel", false),
+            &format!("{}{}", line("lo", false), line("", true)),
+        ])
+        .await;
+        assert!(done);
+        let roles: Vec<_> = chunks
+            .iter()
+            .map(|chunk| chunk.choices[0].delta.role.as_deref())
+            .collect();
+        assert_eq!(roles, [Some("assistant"), None, None]);
+        assert_eq!(chunks[2].choices[0].finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn content_less_tool_call_chunk_is_forwarded() {
+        let tool_call = serde_json::json!({
+            "model": "llama3.2",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{"function": {"name": "get_weather", "arguments": {"city": "Paris"}}}],
+            },
+            "done": false,
+        });
+        let (chunks, _) = convert(&[&format!("{}\n", tool_call), &line("", true)]).await;
+        assert_eq!(chunks.len(), 2);
+        let delta = &chunks[0].choices[0].delta;
+        assert_eq!(delta.content, "");
+        let calls = delta.tool_calls.as_ref().expect("tool call forwarded");
+        assert_eq!(calls[0]["function"]["name"], "get_weather");
+        assert_eq!(
+            chunks[1].choices[0].finish_reason.as_deref(),
+            Some("tool_calls")
+        );
+    }
+
+    #[tokio::test]
+    async fn unexpected_upstream_role_is_reported_as_assistant() {
+        let odd = serde_json::json!({
+            "model": "llama3.2",
+            "message": {"role": "user", "content": "Hi"},
+            "done": false,
+        });
+        let (chunks, _) = convert(&[&format!("{}\n", odd), &line("", true)]).await;
+        assert_eq!(
+            chunks[0].choices[0].delta.role.as_deref(),
+            Some("assistant")
+        );
+        assert_eq!(chunks[1].choices[0].delta.role, None);
+    }
+
+    #[tokio::test]
+    async fn immediate_done_gives_one_stop_chunk() {
+        let (chunks, done) = convert(&[&line("", true)]).await;
+        assert!(done);
+        assert_eq!(chunks.len(), 1);
+        let choice = &chunks[0].choices[0];
+        assert_eq!(choice.delta.role.as_deref(), Some("assistant"));
+        assert_eq!(choice.delta.content, "");
+        assert_eq!(choice.finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn empty_stream_still_ends_with_a_stop_chunk() {
+        let (chunks, done) = convert(&[]).await;
+        assert!(done);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].choices[0].finish_reason.as_deref(), Some("stop"));
+    }
+
+    /// An Ollama that takes `delay` to send its response headers, then answers
+    /// `status` with `body`. Returns its base URL.
+    fn slow_ollama(delay: Duration, status: u16, body: String) -> String {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap() > 0 && header != "\r\n" {
+                if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                header.clear();
             }
-            yield Ok::<_, ProviderError>(Bytes::from("data: [DONE]\n\n"));
+            reader.read_exact(&mut vec![0; length]).unwrap();
+            std::thread::sleep(delay);
+            let head = format!(
+                "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(body.as_bytes()).unwrap();
+        });
+        url
+    }
+
+    fn provider(url: String, keepalive: Duration) -> OllamaProvider {
+        let timeouts = ProviderTimeouts {
+            request: Duration::from_secs(5),
+            connect: Duration::from_secs(5),
         };
+        OllamaProvider::new(url, Client::new(), timeouts).with_keepalive(Some(keepalive))
+    }
 
-        Ok(Box::pin(sse_stream))
+    #[tokio::test]
+    async fn keepalives_are_sent_while_waiting_for_headers() {
+        let body = line("Hi", false) + &line("", true);
+        let url = slow_ollama(Duration::from_millis(200), 200, body);
+        let started = std::time::Instant::now();
+        let stream = provider(url, Duration::from_millis(40))
+            .chat_stream(crate::providers::testing::request("llama3.2"))
+            .await
+            .unwrap();
+        // The client gets a response long before Ollama's headers
+        assert!(started.elapsed() < Duration::from_millis(150));
+
+        let body = crate::providers::testing::collect(stream).await;
+        let first_data = body.find("data: ").unwrap();
+        assert!(body.starts_with(": keep-alive\n\n"), "{}", body);
+        assert!(
+            body[..first_data].matches(": keep-alive").count() >= 2,
+            "{}",
+            body
+        );
+        assert!(body[first_data..].contains("\"content\":\"Hi\""));
+        assert!(body.ends_with("data: [DONE]\n\n"));
+    }
+
+    #[tokio::test]
+    async fn early_upstream_error_is_still_returned() {
+        let url = slow_ollama(Duration::ZERO, 404, String::from("model not found"));
+        let result = provider(url, Duration::from_secs(2))
+            .chat_stream(crate::providers::testing::request("llama3.2"))
+            .await;
+        assert!(matches!(
+            result,
+            Err(ProviderError::ProviderError { status: 404, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn late_upstream_error_ends_the_stream() {
+        let url = slow_ollama(Duration::from_millis(100), 503, String::from("busy"));
+        let stream = provider(url, Duration::from_millis(40))
+            .chat_stream(crate::providers::testing::request("llama3.2"))
+            .await
+            .unwrap();
+        let body = crate::providers::testing::collect(stream).await;
+        assert!(body.starts_with(": keep-alive\n\n"));
+        assert!(body.contains("\"code\":\"stream_interrupted\""), "{}", body);
+        assert!(body.ends_with("data: [DONE]\n\n"));
     }
 }
@@ -1,52 +1,114 @@
-use crate::models::{ChatCompletionRequest, ChatCompletionResponse};
-use crate::providers::{LLMProvider, ProviderError};
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+    ModelList,
+};
+use crate::providers::body_log::read_json;
+use crate::providers::{
+    check_status, probe, BodyLogging, LLMProvider, ProviderError, ProviderTimeouts, StreamUsage,
+    WithRequestId,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
 use log::info;
-use reqwest::Client;
+use reqwest::header::HeaderMap;
 use std::pin::Pin;
+use std::time::Duration;
 
+/// Any upstream that speaks the OpenAI chat API (OpenAI, Mistral, Groq, Together,
+/// OpenRouter, ...). Only the base URL, chat path and extra headers differ.
 #[derive(Clone)]
-pub struct OpenAIProvider {
+pub struct GenericOpenAIProvider {
     client: reqwest::Client,
     base_url: String,
     api_key: String,
+    chat_path: String,
+    extra_headers: HeaderMap,
+    request_timeout: Duration,
+    stream_usage: StreamUsage,
+    body_logging: Option<BodyLogging>,
 }
 
-impl OpenAIProvider {
-    pub fn new(base_url: String, api_key: String) -> Self {
-        let client = Client::new();
+/// The original name, kept so existing code and configs keep working
+pub type OpenAIProvider = GenericOpenAIProvider;
 
+impl GenericOpenAIProvider {
+    /// `client` may be shared with other providers; see `ProviderTimeouts::build_client`.
+    pub fn new(
+        base_url: String,
+        api_key: String,
+        client: reqwest::Client,
+        timeouts: ProviderTimeouts,
+    ) -> Self {
         Self {
             client,
             base_url,
             api_key,
+            chat_path: String::from("/v1/chat/completions"),
+            extra_headers: HeaderMap::new(),
+            request_timeout: timeouts.request,
+            stream_usage: StreamUsage::default(),
+            body_logging: None,
         }
     }
+
+    /// Where chat completions are posted, relative to the base URL (`OPENAI_CHAT_PATH`).
+    pub fn with_chat_path(mut self, chat_path: impl Into<String>) -> Self {
+        self.chat_path = chat_path.into();
+        self
+    }
+
+    /// Sent with every request, e.g. OpenRouter's `HTTP-Referer` (`OPENAI_EXTRA_HEADERS`).
+    pub fn with_extra_headers(mut self, headers: HeaderMap) -> Self {
+        self.extra_headers = headers;
+        self
+    }
+
+    /// A request to `path` with the API key and extra headers set.
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.base_url, path))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .headers(self.extra_headers.clone())
+    }
+
+    /// Log request and response bodies at debug level (`LOG_BODIES`).
+    pub fn with_body_logging(mut self, body_logging: Option<BodyLogging>) -> Self {
+        self.body_logging = body_logging;
+        self
+    }
+
+    /// Override how this upstream reports usage in streams (e.g. for compatible
+    /// servers that send per-chunk usage).
+    pub fn with_stream_usage(mut self, stream_usage: StreamUsage) -> Self {
+        self.stream_usage = stream_usage;
+        self
+    }
 }
 
 #[async_trait]
-impl LLMProvider for OpenAIProvider {
+impl LLMProvider for GenericOpenAIProvider {
     async fn chat(
         &self,
         req: ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse, ProviderError> {
         info!("Processing request to OpenAI...");
+        if let Some(logging) = &self.body_logging {
+            logging.request("OpenAI", &req);
+        }
 
         let response = self
-            .client
-            .post(format!("{}/v1/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
+            .request(reqwest::Method::POST, &self.chat_path)
+            .request_id(req.request_id.as_deref())
+            .timeout(req.timeout.unwrap_or(self.request_timeout))
             .json(&req)
             .send()
             .await
             .map_err(|e| ProviderError::Network(e.to_string()))?;
+        let response = check_status(response).await?;
 
-        let openai_response = response
-            .json::<ChatCompletionResponse>()
-            .await
-            .map_err(|e| ProviderError::Parse(e.to_string()))?;
+        let openai_response: ChatCompletionResponse =
+            read_json(response, self.body_logging, "OpenAI").await?;
 
         info!("Request processed successfully");
         Ok(openai_response)
@@ -58,22 +120,29 @@ impl LLMProvider for OpenAIProvider {
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
     {
         info!("Processing streaming request to OpenAI...");
+        if let Some(logging) = &self.body_logging {
+            logging.request("OpenAI", &req);
+        }
 
         let response = self
-            .client
-            .post(format!("{}/v1/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
+            .request(reqwest::Method::POST, &self.chat_path)
+            .request_id(req.request_id.as_deref())
             .json(&req)
             .send()
             .await
             .map_err(|e| ProviderError::Network(e.to_string()))?;
+        let response = check_status(response).await?;
 
+        let body_logging = self.body_logging;
         let stream = async_stream::stream! {
             let mut byte_stream = response.bytes_stream();
 
             while let Some(chunk_result) = byte_stream.next().await {
                 match chunk_result {
                     Ok(bytes) => {
+                        if let Some(logging) = &body_logging {
+                            logging.response("OpenAI", &bytes);
+                        }
                         yield Ok(bytes);
                     }
                     Err(e) => {
@@ -86,4 +155,40 @@ impl LLMProvider for OpenAIProvider {
         };
         Ok(Box::pin(stream))
     }
+
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        probe(self.request(reqwest::Method::GET, "/v1/models")).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        let response = self
+            .request(reqwest::Method::GET, "/v1/models")
+            .timeout(self.request_timeout)
+            .send()
+            .await?;
+        let response = check_status(response).await?;
+
+        Ok(response.json::<ModelList>().await?.data)
+    }
+
+    fn stream_usage(&self, _model: &str) -> StreamUsage {
+        self.stream_usage
+    }
+
+    async fn embeddings(&self, req: EmbeddingRequest) -> Result<EmbeddingResponse, ProviderError> {
+        let response = self
+            .request(reqwest::Method::POST, "/v1/embeddings")
+            .request_id(req.request_id.as_deref())
+            .timeout(self.request_timeout)
+            .json(&req)
+            .send()
+            .await
+            .map_err(|e| ProviderError::Network(e.to_string()))?;
+
+        check_status(response)
+            .await?
+            .json::<EmbeddingResponse>()
+            .await
+            .map_err(ProviderError::from)
+    }
 }
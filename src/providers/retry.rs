@@ -0,0 +1,199 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Longest upstream-requested wait that's honored; past it the error is returned
+/// right away, since the client would likely give up before the retry.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// A provider that retries transient failures of the wrapped provider with
+/// exponential backoff and jitter, or after exactly as long as the upstream asked
+/// (`Retry-After`). Can wrap the primary inside a `FallbackProvider`.
+pub struct RetryProvider {
+    inner: Arc<dyn LLMProvider>,
+    max_attempts: u32,
+    base_backoff: Duration,
+}
+
+impl RetryProvider {
+    pub fn new(inner: Arc<dyn LLMProvider>, max_attempts: u32, base_backoff: Duration) -> Self {
+        Self {
+            inner,
+            max_attempts: max_attempts.max(1),
+            base_backoff,
+        }
+    }
+
+    /// Delay before retry number `attempt` (1-based): a random point between half
+    /// and the full `base * 2^(attempt - 1)`, so concurrent retries don't line up.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_backoff
+            .saturating_mul(1u32 << (attempt - 1).min(16));
+        exp.mul_f64(rand::random_range(0.5..=1.0))
+    }
+
+    /// How long to wait before retrying after `err`, or `None` when the upstream
+    /// asked for longer than `MAX_RETRY_AFTER`.
+    fn delay_for(&self, attempt: u32, err: &ProviderError) -> Option<Duration> {
+        match err {
+            ProviderError::ProviderError {
+                retry_after: Some(wait),
+                ..
+            } => (*wait <= MAX_RETRY_AFTER).then_some(*wait),
+            _ => Some(self.backoff(attempt)),
+        }
+    }
+}
+
+/// Network failures and overload/server statuses are worth retrying; anything
+/// else (bad request, auth, parse errors) will fail the same way again.
+fn is_retryable(err: &ProviderError) -> bool {
+    match err {
+        ProviderError::Network(_) => true,
+        ProviderError::ProviderError { status, .. } => matches!(status, 429 | 500 | 502 | 503),
+        ProviderError::Parse(_) => false,
+    }
+}
+
+#[async_trait]
+impl LLMProvider for RetryProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let mut attempt = 1;
+        loop {
+            match self.inner.chat(request.clone()).await {
+                Err(e) if attempt < self.max_attempts && is_retryable(&e) => {
+                    let Some(delay) = self.delay_for(attempt, &e) else {
+                        warn!(
+                            "Attempt {}/{} failed: {}. Upstream asked to wait too long to retry",
+                            attempt, self.max_attempts, e
+                        );
+                        return Err(e);
+                    };
+                    warn!(
+                        "Attempt {}/{} failed: {}. Retrying in {:?}",
+                        attempt, self.max_attempts, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        // Only establishing the stream is retried; nothing has reached the client yet.
+        let mut attempt = 1;
+        loop {
+            match self.inner.chat_stream(request.clone()).await {
+                Err(e) if attempt < self.max_attempts && is_retryable(&e) => {
+                    let Some(delay) = self.delay_for(attempt, &e) else {
+                        warn!("Stream attempt {}/{} failed: {}. Upstream asked to wait too long to retry", attempt, self.max_attempts, e);
+                        return Err(e);
+                    };
+                    warn!(
+                        "Stream attempt {}/{} failed: {}. Retrying in {:?}",
+                        attempt, self.max_attempts, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        self.inner.health_check().await
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        self.inner.list_models().await
+    }
+
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        self.inner.stream_usage(model)
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        let mut attempt = 1;
+        loop {
+            match self.inner.embeddings(request.clone()).await {
+                Err(e) if attempt < self.max_attempts && is_retryable(&e) => {
+                    let Some(delay) = self.delay_for(attempt, &e) else {
+                        warn!("Embeddings attempt {}/{} failed: {}. Upstream asked to wait too long to retry", attempt, self.max_attempts, e);
+                        return Err(e);
+                    };
+                    warn!(
+                        "Embeddings attempt {}/{} failed: {}. Retrying in {:?}",
+                        attempt, self.max_attempts, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{request, StubProvider};
+
+    fn retrying(upstream: &Arc<StubProvider>) -> RetryProvider {
+        RetryProvider::new(upstream.clone(), 3, Duration::from_secs(10))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_waits_exactly_as_long_as_asked() {
+        let upstream =
+            Arc::new(StubProvider::new("Hi").rate_limited(1, Duration::from_millis(1500)));
+        let started = tokio::time::Instant::now();
+        assert!(retrying(&upstream).chat(request("m")).await.is_ok());
+        // Not the 5-10s backoff: the upstream's own wait
+        assert_eq!(started.elapsed(), Duration::from_millis(1500));
+        assert_eq!(upstream.models().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_past_the_limit_is_returned_right_away() {
+        let wait = MAX_RETRY_AFTER + Duration::from_secs(1);
+        let upstream = Arc::new(StubProvider::new("Hi").rate_limited(1, wait));
+        let started = tokio::time::Instant::now();
+        let err = retrying(&upstream).chat(request("m")).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ProviderError::ProviderError { status: 429, .. }
+        ));
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(upstream.models().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn client_errors_are_not_retried() {
+        let upstream = Arc::new(StubProvider::new("Hi").failing(400));
+        assert!(retrying(&upstream).chat(request("m")).await.is_err());
+        assert_eq!(upstream.models().len(), 1);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::{sse_events, LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::info;
+
+/// Where a route sends matching requests, optionally under a different model name.
+#[derive(Clone)]
+pub struct Route {
+    pub provider: Arc<dyn LLMProvider>,
+    /// Model name the upstream should see; the client still sees the model it asked for.
+    pub target_model: Option<String>,
+}
+
+/// A provider that dispatches each request based on `request.model`.
+/// Routes are exact model names or `*` globs (e.g. `gpt-*`); unmatched models go to the default.
+pub struct RoutingProvider {
+    routes: HashMap<String, Route>,
+    default: Arc<dyn LLMProvider>,
+}
+
+impl RoutingProvider {
+    pub fn new(routes: HashMap<String, Route>, default: Arc<dyn LLMProvider>) -> Self {
+        Self { routes, default }
+    }
+
+    /// An exact route wins; otherwise the longest (most specific) matching glob.
+    fn resolve(&self, model: &str) -> Option<&Route> {
+        if let Some(route) = self.routes.get(model) {
+            return Some(route);
+        }
+
+        self.routes
+            .iter()
+            .filter(|(pattern, _)| pattern.contains('*') && glob_match(pattern, model))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(pattern, route)| {
+                info!("Routing model '{}' via route '{}'", model, pattern);
+                route
+            })
+    }
+
+    /// Pick the provider for a request model, rewriting it if the route says so.
+    /// Returns the model name to echo back when it was rewritten.
+    fn dispatch(&self, model: &mut String) -> (Arc<dyn LLMProvider>, Option<String>) {
+        match self.resolve(model) {
+            Some(route) => match &route.target_model {
+                Some(target) => {
+                    info!("Rewriting model '{}' to '{}'", model, target);
+                    let original = std::mem::replace(model, target.clone());
+                    (route.provider.clone(), Some(original))
+                }
+                None => (route.provider.clone(), None),
+            },
+            None => (self.default.clone(), None),
+        }
+    }
+}
+
+/// Replace the `model` field of every streamed chunk so clients see the model they
+/// requested. The stream is re-framed first, so a chunk split across reads is still
+/// rewritten.
+pub(super) fn rewrite_stream_model<S>(
+    stream: S,
+    model: String,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>
+where
+    S: Stream<Item = Result<Bytes, ProviderError>> + Send + 'static,
+{
+    Box::pin(
+        sse_events(stream)
+            .map(move |result| result.map(|event| rewrite_event_model(event, &model))),
+    )
+}
+
+/// Replace the `model` field of one SSE event. Events that don't parse as JSON
+/// (e.g. `[DONE]`) pass through unchanged.
+fn rewrite_event_model(event: Bytes, model: &str) -> Bytes {
+    let text = String::from_utf8_lossy(&event);
+    let body = text.trim_end_matches(['\r', '\n']);
+    let Some(data) = body.strip_prefix("data: ") else {
+        return event;
+    };
+    match serde_json::from_str::<serde_json::Value>(data) {
+        Ok(mut value) if value.get("model").is_some() => {
+            value["model"] = serde_json::Value::String(model.to_string());
+            Bytes::from(format!("data: {}{}", value, &text[body.len()..]))
+        }
+        _ => event,
+    }
+}
+
+/// Match `text` against a pattern where `*` stands for any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, rest) = parts.split_first().expect("split yields at least one part");
+
+    let Some(mut remaining) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let (last, middle) = rest.split_last().expect("pattern contains a '*'");
+    for part in middle {
+        match remaining.find(part) {
+            Some(pos) => remaining = &remaining[pos + part.len()..],
+            None => return false,
+        }
+    }
+
+    remaining.len() >= last.len() && remaining.ends_with(last)
+}
+
+#[async_trait]
+impl LLMProvider for RoutingProvider {
+    async fn chat(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let (provider, original_model) = self.dispatch(&mut request.model);
+        let mut response = provider.chat(request).await?;
+        if let Some(model) = original_model {
+            response.model = model;
+        }
+        Ok(response)
+    }
+
+    async fn chat_stream(
+        &self,
+        mut request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        let (provider, original_model) = self.dispatch(&mut request.model);
+        let stream = provider.chat_stream(request).await?;
+        match original_model {
+            Some(model) => Ok(rewrite_stream_model(stream, model)),
+            None => Ok(stream),
+        }
+    }
+
+    /// Reflects the default provider; routed providers are reported separately by /v1/health.
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        self.default.health_check().await
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        self.default.list_models().await
+    }
+
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        match self.resolve(model) {
+            Some(route) => route
+                .provider
+                .stream_usage(route.target_model.as_deref().unwrap_or(model)),
+            None => self.default.stream_usage(model),
+        }
+    }
+
+    async fn embeddings(
+        &self,
+        mut request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        let (provider, original_model) = self.dispatch(&mut request.model);
+        let mut response = provider.embeddings(request).await?;
+        if let Some(model) = original_model {
+            response.model = model;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{collect, request, StubProvider};
+
+    fn routed(target: &str) -> (Arc<StubProvider>, RoutingProvider) {
+        let upstream = Arc::new(StubProvider::new("routed"));
+        let routes = HashMap::from([(
+            String::from("gpt-4"),
+            Route {
+                provider: upstream.clone(),
+                target_model: Some(target.to_string()),
+            },
+        )]);
+        let router = RoutingProvider::new(routes, Arc::new(StubProvider::new("default")));
+        (upstream, router)
+    }
+
+    /// The `model` of every JSON event in a stream.
+    fn stream_models(body: &str) -> Vec<String> {
+        body.split("\n\n")
+            .filter_map(|event| event.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+            .map(|chunk| chunk["model"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn upstream_gets_the_target_and_the_client_the_original() {
+        let (upstream, router) = routed("llama3:70b");
+        let response = router.chat(request("gpt-4")).await.unwrap();
+        assert_eq!(upstream.models(), ["llama3:70b"]);
+        assert_eq!(response.model, "gpt-4");
+    }
+
+    #[tokio::test]
+    async fn streamed_chunks_split_across_reads_are_rewritten() {
+        let (upstream, router) = routed("llama3:70b");
+        let body = collect(router.chat_stream(request("gpt-4")).await.unwrap()).await;
+        assert_eq!(upstream.models(), ["llama3:70b"]);
+        assert_eq!(stream_models(&body), ["gpt-4", "gpt-4"]);
+        assert!(body.ends_with("data: [DONE]\n\n"));
+    }
+
+    #[tokio::test]
+    async fn unrouted_models_go_to_the_default_untouched() {
+        let (upstream, router) = routed("llama3:70b");
+        let response = router.chat(request("mistral")).await.unwrap();
+        assert!(upstream.models().is_empty());
+        assert_eq!(response.model, "mistral");
+    }
+
+    #[test]
+    fn exact_routes_beat_globs_and_longer_globs_beat_shorter() {
+        let route = |reply: &str| Route {
+            provider: Arc::new(StubProvider::new(reply)),
+            target_model: Some(reply.to_string()),
+        };
+        let routes = HashMap::from([
+            (String::from("gpt-*"), route("short")),
+            (String::from("gpt-4*"), route("long")),
+            (String::from("gpt-4o"), route("exact")),
+        ]);
+        let router = RoutingProvider::new(routes, Arc::new(StubProvider::new("default")));
+        let target = |model: &str| router.resolve(model).and_then(|r| r.target_model.clone());
+        assert_eq!(target("gpt-4o").as_deref(), Some("exact"));
+        assert_eq!(target("gpt-4-turbo").as_deref(), Some("long"));
+        assert_eq!(target("gpt-3.5").as_deref(), Some("short"));
+        assert_eq!(target("claude"), None);
+    }
+
+    #[test]
+    fn non_json_events_pass_through() {
+        let done = Bytes::from("data: [DONE]\n\n");
+        assert_eq!(rewrite_event_model(done.clone(), "gpt-4"), done);
+        assert_eq!(
+            rewrite_event_model(Bytes::from("data: {\"model\":\"x\"}\r\n\r\n"), "gpt-4"),
+            Bytes::from("data: {\"model\":\"gpt-4\"}\r\n\r\n")
+        );
+    }
+}
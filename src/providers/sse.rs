@@ -0,0 +1,126 @@
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+
+/// Reassembles SSE events from network reads, which may hold several events or
+/// only part of one. Each event comes out whole, with its trailing blank line.
+#[derive(Debug, Default)]
+pub struct SseBuffer {
+    pending: BytesMut,
+}
+
+impl SseBuffer {
+    /// Adds a read and returns every event it completed.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Bytes> {
+        self.pending.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(end) = event_end(&self.pending) {
+            events.push(self.pending.split_to(end).freeze());
+        }
+        events
+    }
+
+    /// What's left once upstream is done, e.g. a last event without its blank line.
+    pub fn finish(self) -> Option<Bytes> {
+        (!self.pending.is_empty()).then(|| self.pending.freeze())
+    }
+}
+
+/// Where the first event in `buf` ends, counting its `\n\n` (or `\r\n\r\n`).
+fn event_end(buf: &[u8]) -> Option<usize> {
+    let find = |separator: &[u8]| {
+        buf.windows(separator.len())
+            .position(|window| window == separator)
+            .map(|pos| pos + separator.len())
+    };
+    match (find(b"\n\n"), find(b"\r\n\r\n")) {
+        (Some(lf), Some(crlf)) => Some(lf.min(crlf)),
+        (lf, crlf) => lf.or(crlf),
+    }
+}
+
+/// `stream` re-chunked so every item is exactly one SSE event. An error is passed
+/// on as soon as it arrives, dropping the partial event before it.
+pub fn sse_events<S, E>(stream: S) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut buffer = SseBuffer::default();
+        while let Some(result) = stream.next().await {
+            match result {
+                Ok(bytes) => {
+                    for event in buffer.push(&bytes) {
+                        yield Ok(event);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        if let Some(rest) = buffer.finish() {
+            yield Ok(rest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_a_read_holding_two_events() {
+        let mut buffer = SseBuffer::default();
+        let events = buffer.push(b"data: {\"a\":1}\n\ndata: {\"b\":2}\n\n");
+        assert_eq!(
+            events,
+            vec![
+                Bytes::from("data: {\"a\":1}\n\n"),
+                Bytes::from("data: {\"b\":2}\n\n")
+            ]
+        );
+        assert_eq!(buffer.finish(), None);
+    }
+
+    #[test]
+    fn joins_an_event_split_across_reads() {
+        let mut buffer = SseBuffer::default();
+        assert!(buffer.push(b"data: {\"a\"").is_empty());
+        assert!(buffer.push(b":1}\n").is_empty());
+        assert_eq!(
+            buffer.push(b"\ndata: [DO"),
+            vec![Bytes::from("data: {\"a\":1}\n\n")]
+        );
+        assert_eq!(buffer.finish(), Some(Bytes::from("data: [DO")));
+    }
+
+    #[test]
+    fn accepts_crlf_separators() {
+        let mut buffer = SseBuffer::default();
+        let events = buffer.push(b"data: 1\r\n\r\ndata: 2\n\n");
+        assert_eq!(
+            events,
+            vec![Bytes::from("data: 1\r\n\r\n"), Bytes::from("data: 2\n\n")]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_yields_whole_events() {
+        let reads = futures::stream::iter(vec![
+            Ok::<_, ()>(Bytes::from("data: 1\n\ndata: ")),
+            Ok(Bytes::from("2\n\n")),
+            Ok(Bytes::from("data: [DONE]")),
+        ]);
+        let events: Vec<_> = sse_events(reads).collect().await;
+        assert_eq!(
+            events,
+            vec![
+                Ok(Bytes::from("data: 1\n\n")),
+                Ok(Bytes::from("data: 2\n\n")),
+                Ok(Bytes::from("data: [DONE]"))
+            ]
+        );
+    }
+}
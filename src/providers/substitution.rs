@@ -0,0 +1,183 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+};
+use crate::providers::routing::rewrite_stream_model;
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::warn;
+
+/// A provider that retries once with a substitute model (`MODEL_SUBSTITUTIONS`)
+/// when the wrapped provider doesn't have the requested one, e.g. an Ollama model
+/// that was never pulled. The client still sees the model it asked for.
+pub struct SubstitutionProvider {
+    inner: Arc<dyn LLMProvider>,
+    substitutions: HashMap<String, String>,
+}
+
+impl SubstitutionProvider {
+    pub fn new(inner: Arc<dyn LLMProvider>, substitutions: HashMap<String, String>) -> Self {
+        Self {
+            inner,
+            substitutions,
+        }
+    }
+
+    /// The substitute for `model`, if `err` says the model doesn't exist upstream.
+    fn substitute_for(&self, model: &str, err: &ProviderError) -> Option<&String> {
+        if !is_model_not_found(err) {
+            return None;
+        }
+        let substitute = self.substitutions.get(model)?;
+        warn!(
+            "Model '{}' not found upstream, substituting '{}'",
+            model, substitute
+        );
+        Some(substitute)
+    }
+}
+
+/// Ollama answers an unknown model with 404 `model "x" not found, try pulling it first`.
+fn is_model_not_found(err: &ProviderError) -> bool {
+    match err {
+        ProviderError::ProviderError {
+            status, message, ..
+        } => *status == 404 && message.to_lowercase().contains("not found"),
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl LLMProvider for SubstitutionProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        let err = match self.inner.chat(request.clone()).await {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+        let Some(substitute) = self.substitute_for(&request.model, &err) else {
+            return Err(err);
+        };
+
+        let original = request.model.clone();
+        let mut request = request;
+        request.model = substitute.clone();
+        let mut response = self.inner.chat(request).await?;
+        response.model = original;
+        Ok(response)
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        // Only establishing the stream can fail this way, so nothing has been sent yet
+        let err = match self.inner.chat_stream(request.clone()).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => e,
+        };
+        let Some(substitute) = self.substitute_for(&request.model, &err) else {
+            return Err(err);
+        };
+
+        let original = request.model.clone();
+        let mut request = request;
+        request.model = substitute.clone();
+        let stream = self.inner.chat_stream(request).await?;
+        Ok(rewrite_stream_model(stream, original))
+    }
+
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        self.inner.health_check().await
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        self.inner.list_models().await
+    }
+
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        self.inner.stream_usage(model)
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        let err = match self.inner.embeddings(request.clone()).await {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+        let Some(substitute) = self.substitute_for(&request.model, &err) else {
+            return Err(err);
+        };
+
+        let original = request.model.clone();
+        let mut request = request;
+        request.model = substitute.clone();
+        let mut response = self.inner.embeddings(request).await?;
+        response.model = original;
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{collect, request, StubProvider};
+
+    fn substituting(upstream: StubProvider) -> (Arc<StubProvider>, SubstitutionProvider) {
+        let upstream = Arc::new(upstream);
+        let substitutions = HashMap::from([(String::from("llama3:70b"), String::from("llama3"))]);
+        let provider = SubstitutionProvider::new(upstream.clone(), substitutions);
+        (upstream, provider)
+    }
+
+    #[tokio::test]
+    async fn missing_model_is_retried_with_its_substitute() {
+        let (upstream, provider) =
+            substituting(StubProvider::new("Hi").without_model("llama3:70b"));
+        let response = provider.chat(request("llama3:70b")).await.unwrap();
+        assert_eq!(upstream.models(), ["llama3:70b", "llama3"]);
+        assert_eq!(response.model, "llama3:70b");
+    }
+
+    #[tokio::test]
+    async fn substituted_stream_reports_the_requested_model() {
+        let (upstream, provider) =
+            substituting(StubProvider::new("Hi").without_model("llama3:70b"));
+        let body = collect(provider.chat_stream(request("llama3:70b")).await.unwrap()).await;
+        assert_eq!(upstream.models(), ["llama3:70b", "llama3"]);
+        assert_eq!(body.matches("\"model\":\"llama3:70b\"").count(), 2);
+        assert!(!body.contains("\"model\":\"llama3\""));
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_not_substituted() {
+        let (upstream, provider) = substituting(StubProvider::new("Hi").failing(500));
+        let err = provider.chat(request("llama3:70b")).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ProviderError::ProviderError { status: 500, .. }
+        ));
+        assert!(provider.chat_stream(request("llama3:70b")).await.is_err());
+        assert_eq!(upstream.models(), ["llama3:70b", "llama3:70b"]);
+    }
+
+    #[tokio::test]
+    async fn models_without_a_substitute_keep_their_error() {
+        let (upstream, provider) = substituting(StubProvider::new("Hi").without_model("mistral"));
+        let err = provider.chat(request("mistral")).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ProviderError::ProviderError { status: 404, .. }
+        ));
+        assert_eq!(upstream.models(), ["mistral"]);
+    }
+}
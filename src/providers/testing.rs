@@ -0,0 +1,231 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, Choice, Delta, Message, ModelInfo, Usage,
+};
+use crate::providers::ollama::sse_chunk;
+use crate::providers::{LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A scripted upstream for unit tests. It replies with a fixed text, remembers every
+/// request, and streams in reads too short to hold a whole event.
+pub struct StubProvider {
+    reply: String,
+    missing: Vec<String>,
+    failure: Option<u16>,
+    /// Requests left to reject with a 429, and the wait reported with each
+    rate_limited: Mutex<Option<(usize, Duration)>>,
+    stream_usage: StreamUsage,
+    requests: Mutex<Vec<ChatCompletionRequest>>,
+}
+
+impl StubProvider {
+    pub fn new(reply: &str) -> Self {
+        Self {
+            reply: reply.to_string(),
+            missing: Vec::new(),
+            failure: None,
+            rate_limited: Mutex::new(None),
+            stream_usage: StreamUsage::Cumulative,
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answer `model` with Ollama's 404 for a model that was never pulled.
+    pub fn without_model(mut self, model: &str) -> Self {
+        self.missing.push(model.to_string());
+        self
+    }
+
+    /// Fail every request with `status`.
+    pub fn failing(mut self, status: u16) -> Self {
+        self.failure = Some(status);
+        self
+    }
+
+    /// Reject the first `times` requests with a 429 asking to retry after `wait`.
+    pub fn rate_limited(self, times: usize, wait: Duration) -> Self {
+        *self.rate_limited.lock().unwrap() = Some((times, wait));
+        self
+    }
+
+    /// Report usage per chunk, like a backend with `StreamUsage::Incremental`:
+    /// 3 prompt and 1 completion token on the first chunk, 1 completion on the last.
+    pub fn incremental(mut self) -> Self {
+        self.stream_usage = StreamUsage::Incremental;
+        self
+    }
+
+    /// Usage for one streamed chunk, only when reporting incrementally.
+    fn chunk_usage(&self, prompt_tokens: u32) -> Option<Usage> {
+        (self.stream_usage == StreamUsage::Incremental).then_some(Usage {
+            prompt_tokens,
+            completion_tokens: 1,
+            total_tokens: prompt_tokens + 1,
+        })
+    }
+
+    /// Models requested so far, in order.
+    pub fn models(&self) -> Vec<String> {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().map(|r| r.model.clone()).collect()
+    }
+
+    /// The messages of each request so far, in order.
+    pub fn messages(&self) -> Vec<Vec<Message>> {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().map(|r| r.messages.clone()).collect()
+    }
+
+    /// Upstream timeouts the requests so far were sent with, in order.
+    pub fn timeouts(&self) -> Vec<Option<Duration>> {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().map(|r| r.timeout).collect()
+    }
+
+    fn answer(&self, request: &ChatCompletionRequest) -> Result<(), ProviderError> {
+        self.requests.lock().unwrap().push(request.clone());
+        if let Some((times, wait)) = self.rate_limited.lock().unwrap().as_mut() {
+            if *times > 0 {
+                *times -= 1;
+                return Err(ProviderError::ProviderError {
+                    status: 429,
+                    message: String::from("rate limited"),
+                    retry_after: Some(*wait),
+                });
+            }
+        }
+        let model = &request.model;
+        let (status, message) = if self.missing.iter().any(|m| m == model) {
+            (
+                404,
+                format!("model \"{}\" not found, try pulling it first", model),
+            )
+        } else if let Some(status) = self.failure {
+            (status, String::from("upstream failed"))
+        } else {
+            return Ok(());
+        };
+        Err(ProviderError::ProviderError {
+            status,
+            message,
+            retry_after: None,
+        })
+    }
+}
+
+pub fn request(model: &str) -> ChatCompletionRequest {
+    ChatCompletionRequest {
+        model: model.to_string(),
+        messages: vec![Message {
+            role: String::from("user"),
+            content: String::from("Hi").into(),
+            tool_calls: None,
+            tool_call_id: None,
+        }],
+        stream: None,
+        temperature: None,
+        n: None,
+        tools: None,
+        tool_choice: None,
+        response_format: None,
+        user: None,
+        timeout: None,
+        request_id: None,
+    }
+}
+
+/// Everything a stream yielded, as one string.
+pub async fn collect<S>(stream: S) -> String
+where
+    S: Stream<Item = Result<Bytes, ProviderError>>,
+{
+    let reads: Vec<_> = stream.collect().await;
+    reads
+        .into_iter()
+        .map(|read| String::from_utf8(read.unwrap().to_vec()).unwrap())
+        .collect()
+}
+
+#[async_trait]
+impl LLMProvider for StubProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        self.answer(&request)?;
+        Ok(ChatCompletionResponse {
+            id: String::from("chatcmpl-stub"),
+            object: String::from("chat.completion"),
+            created: 0,
+            model: request.model,
+            choices: vec![Choice {
+                index: 0,
+                message: Message {
+                    role: String::from("assistant"),
+                    content: self.reply.clone().into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                },
+                finish_reason: String::from("stop"),
+            }],
+            usage: None,
+            cached: false,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        self.answer(&request)?;
+        let delta = Delta {
+            role: Some(String::from("assistant")),
+            content: self.reply.clone(),
+            tool_calls: None,
+        };
+        let mut events = Vec::new();
+        events.extend_from_slice(&sse_chunk(
+            "chatcmpl-stub",
+            0,
+            &request.model,
+            delta,
+            None,
+            self.chunk_usage(3),
+        ));
+        events.extend_from_slice(&sse_chunk(
+            "chatcmpl-stub",
+            0,
+            &request.model,
+            Delta {
+                role: None,
+                content: String::new(),
+                tool_calls: None,
+            },
+            Some(String::from("stop")),
+            self.chunk_usage(0),
+        ));
+        events.extend_from_slice(b"data: [DONE]\n\n");
+        let reads: Vec<Result<Bytes, ProviderError>> = events
+            .chunks(16)
+            .map(|read| Ok(Bytes::copy_from_slice(read)))
+            .collect();
+        Ok(Box::pin(futures::stream::iter(reads)))
+    }
+
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        Ok(())
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        Ok(Vec::new())
+    }
+
+    fn stream_usage(&self, _model: &str) -> StreamUsage {
+        self.stream_usage
+    }
+}
@@ -0,0 +1,251 @@
+use crate::models::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelInfo,
+    Usage,
+};
+use crate::providers::{sse_events, LLMProvider, ProviderError, StreamUsage};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// A provider that sends each request to one backend picked at random in proportion
+/// to its weight (`PROVIDER_WEIGHTS=ollama:0.9,openai:0.1`), e.g. for a gradual
+/// migration. A failed request is not retried elsewhere; wrap backends for that.
+pub struct WeightedRouterProvider {
+    backends: Vec<(Arc<dyn LLMProvider>, f64)>,
+    total_weight: f64,
+    /// Only set when a seed was given, so the sequence of picks is reproducible
+    seeded: Option<Mutex<StdRng>>,
+}
+
+impl WeightedRouterProvider {
+    /// Weights must be positive; they don't need to add up to 1.
+    pub fn new(backends: Vec<(Arc<dyn LLMProvider>, f64)>, seed: Option<u64>) -> Self {
+        assert!(
+            !backends.is_empty(),
+            "WeightedRouterProvider needs at least one backend"
+        );
+        assert!(
+            backends.iter().all(|(_, w)| w.is_finite() && *w > 0.0),
+            "WeightedRouterProvider weights must be positive"
+        );
+        let total_weight = backends.iter().map(|(_, w)| w).sum();
+        Self {
+            backends,
+            total_weight,
+            seeded: seed.map(|seed| Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    fn pick(&self) -> &Arc<dyn LLMProvider> {
+        let point = match &self.seeded {
+            Some(rng) => rng
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .random_range(0.0..self.total_weight),
+            None => rand::random_range(0.0..self.total_weight),
+        };
+
+        let mut cumulative = 0.0;
+        for (index, (backend, weight)) in self.backends.iter().enumerate() {
+            cumulative += weight;
+            if point < cumulative {
+                debug!("Weighted routing picked backend {}", index);
+                return backend;
+            }
+        }
+        // Rounding can leave `point` just past the last boundary
+        &self.backends[self.backends.len() - 1].0
+    }
+}
+
+#[async_trait]
+impl LLMProvider for WeightedRouterProvider {
+    async fn chat(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, ProviderError> {
+        self.pick().chat(request).await
+    }
+
+    async fn chat_stream(
+        &self,
+        request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError>
+    {
+        let model = request.model.clone();
+        let backend = self.pick();
+        let stream = backend.chat_stream(request).await?;
+        // The handler was told `stream_usage`, which may not be how this backend counts
+        if backend.stream_usage(&model) != self.stream_usage(&model) {
+            return Ok(running_totals(stream));
+        }
+        Ok(stream)
+    }
+
+    /// Up while any backend answers. Requests still go to a down backend in proportion
+    /// to its weight, so this says the gateway can serve some traffic, not all of it.
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        let results =
+            futures::future::join_all(self.backends.iter().map(|(b, _)| b.health_check())).await;
+        let mut last_error = None;
+        for result in results {
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("at least one backend"))
+    }
+
+    /// Any backend may serve a request, so their models are combined.
+    async fn list_models(&self) -> Result<Vec<ModelInfo>, ProviderError> {
+        let results =
+            futures::future::join_all(self.backends.iter().map(|(b, _)| b.list_models())).await;
+        let mut seen = HashSet::new();
+        let mut models = Vec::new();
+        let mut last_error = None;
+        for result in results {
+            match result {
+                Ok(list) => models.extend(list.into_iter().filter(|m| seen.insert(m.id.clone()))),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) if models.is_empty() => Err(e),
+            _ => Ok(models),
+        }
+    }
+
+    /// Which backend serves a stream isn't known up front, so backends that disagree
+    /// are reported as `Cumulative` and incremental streams are turned into running totals.
+    fn stream_usage(&self, model: &str) -> StreamUsage {
+        let first = self.backends[0].0.stream_usage(model);
+        if self
+            .backends
+            .iter()
+            .all(|(backend, _)| backend.stream_usage(model) == first)
+        {
+            first
+        } else {
+            StreamUsage::Cumulative
+        }
+    }
+
+    async fn embeddings(
+        &self,
+        request: EmbeddingRequest,
+    ) -> Result<EmbeddingResponse, ProviderError> {
+        self.pick().embeddings(request).await
+    }
+}
+
+/// Replace each streamed usage block with the sum of all of them so far, turning an
+/// incremental stream into a cumulative one. Events are re-framed first, as in routing.
+fn running_totals<S>(stream: S) -> Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>
+where
+    S: Stream<Item = Result<Bytes, ProviderError>> + Send + 'static,
+{
+    let mut totals = Usage::default();
+    Box::pin(
+        sse_events(stream).map(move |result| result.map(|event| add_usage(event, &mut totals))),
+    )
+}
+
+/// Add one event's usage to `totals` and report the totals in its place. Events
+/// without usage pass through unchanged.
+fn add_usage(event: Bytes, totals: &mut Usage) -> Bytes {
+    let text = String::from_utf8_lossy(&event);
+    let body = text.trim_end_matches(['\r', '\n']);
+    let Some(data) = body.strip_prefix("data: ") else {
+        return event;
+    };
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(data) else {
+        return event;
+    };
+    let Some(usage) = value
+        .get("usage")
+        .and_then(|usage| serde_json::from_value::<Usage>(usage.clone()).ok())
+    else {
+        return event;
+    };
+    totals.prompt_tokens += usage.prompt_tokens;
+    totals.completion_tokens += usage.completion_tokens;
+    totals.total_tokens += usage.total_tokens;
+    value["usage"] = serde_json::json!(totals);
+    Bytes::from(format!("data: {}{}", value, &text[body.len()..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{collect, request, StubProvider};
+
+    fn router(backends: Vec<StubProvider>) -> WeightedRouterProvider {
+        let backends = backends
+            .into_iter()
+            .map(|backend| (Arc::new(backend) as Arc<dyn LLMProvider>, 1.0))
+            .collect();
+        WeightedRouterProvider::new(backends, Some(7))
+    }
+
+    fn usages(body: &str) -> Vec<serde_json::Value> {
+        body.split("\n\n")
+            .filter_map(|event| event.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+            .filter_map(|chunk| chunk.get("usage").cloned())
+            .collect()
+    }
+
+    #[test]
+    fn backends_that_agree_are_followed() {
+        let incremental = router(vec![
+            StubProvider::new("a").incremental(),
+            StubProvider::new("b").incremental(),
+        ]);
+        assert_eq!(incremental.stream_usage("m"), StreamUsage::Incremental);
+        let cumulative = router(vec![StubProvider::new("a"), StubProvider::new("b")]);
+        assert_eq!(cumulative.stream_usage("m"), StreamUsage::Cumulative);
+    }
+
+    #[tokio::test]
+    async fn mixed_backends_stream_running_totals() {
+        let provider = router(vec![
+            StubProvider::new("a").incremental(),
+            StubProvider::new("b"),
+        ]);
+        assert_eq!(provider.stream_usage("m"), StreamUsage::Cumulative);
+        let mut served_incremental = 0;
+        for _ in 0..10 {
+            let body = collect(provider.chat_stream(request("m")).await.unwrap()).await;
+            assert!(body.ends_with("data: [DONE]\n\n"));
+            let usages = usages(&body);
+            if body.contains("\"content\":\"a\"") {
+                served_incremental += 1;
+                // Each incremental block now covers everything before it
+                assert_eq!(usages.last().unwrap()["completion_tokens"], 2);
+                assert_eq!(usages.last().unwrap()["total_tokens"], 5);
+            } else {
+                assert!(usages.is_empty());
+            }
+        }
+        // The seed picks both backends within ten requests
+        assert!((1..10).contains(&served_incremental), "{}", served_incremental);
+    }
+
+    #[tokio::test]
+    async fn agreeing_backends_stream_unchanged() {
+        let provider = router(vec![StubProvider::new("a").incremental()]);
+        let body = collect(provider.chat_stream(request("m")).await.unwrap()).await;
+        let completions: Vec<_> = usages(&body)
+            .iter()
+            .map(|usage| usage["completion_tokens"].clone())
+            .collect();
+        assert_eq!(completions, [1, 1]);
+    }
+}
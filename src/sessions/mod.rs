@@ -0,0 +1,244 @@
+use crate::models::Message;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Header naming the conversation a chat request continues
+pub const SESSION_HEADER: &str = "x-session-id";
+
+#[derive(Debug)]
+struct Session {
+    messages: Vec<Message>,
+    last_used: Instant,
+}
+
+/// Server-side conversation history, so chat clients can send only the new turn.
+/// Sessions belong to the API key that created them, expire after `ttl` without use,
+/// and the least recently used one is dropped once `max_sessions` are stored.
+/// Each session's oldest turns are dropped once it holds more than `max_messages`
+/// messages or `max_bytes` of JSON.
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<(String, String), Session>>,
+    ttl: Duration,
+    max_sessions: usize,
+    max_messages: usize,
+    max_bytes: usize,
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration, max_sessions: usize) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+            max_sessions: max_sessions.max(1),
+            max_messages: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+
+    /// Bound each session's history (`SESSION_MAX_MESSAGES`, `SESSION_MAX_BYTES`), since
+    /// all of it is sent upstream with every turn.
+    pub fn with_history_limits(mut self, max_messages: usize, max_bytes: usize) -> Self {
+        self.max_messages = max_messages;
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Drop whole turns from the front until `messages` fits the limits. A turn runs up
+    /// to the next user message, so a reply or tool result never leads the history.
+    fn trim(&self, messages: &mut Vec<Message>) {
+        let size = |message: &Message| serde_json::to_vec(message).map_or(0, |json| json.len());
+        let mut bytes: usize = messages.iter().map(size).sum();
+        while messages.len() > self.max_messages || bytes > self.max_bytes {
+            let end = messages
+                .iter()
+                .skip(1)
+                .position(|message| message.role == "user")
+                .map_or(messages.len(), |index| index + 1);
+            bytes -= messages
+                .drain(..end)
+                .map(|message| size(&message))
+                .sum::<usize>();
+        }
+    }
+
+    /// Stored messages for the session; empty when it's unknown or has expired.
+    pub fn history(&self, api_key: &str, id: &str) -> Vec<Message> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (api_key.to_string(), id.to_string());
+        match sessions.get(&key) {
+            Some(session) if session.last_used.elapsed() < self.ttl => session.messages.clone(),
+            Some(_) => {
+                sessions.remove(&key);
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Add a completed turn (the client's new messages and the reply) to the session,
+    /// creating it if needed.
+    pub fn append(&self, api_key: &str, id: &str, messages: Vec<Message>) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let key = (api_key.to_string(), id.to_string());
+
+        if !sessions.contains_key(&key) && sessions.len() >= self.max_sessions {
+            sessions.retain(|_, session| session.last_used.elapsed() < self.ttl);
+            if sessions.len() >= self.max_sessions {
+                let oldest = sessions
+                    .iter()
+                    .min_by_key(|(_, session)| session.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    sessions.remove(&oldest);
+                }
+            }
+        }
+
+        let session = sessions.entry(key).or_insert_with(|| Session {
+            messages: Vec::new(),
+            last_used: Instant::now(),
+        });
+        // An expired session that wasn't read since starts over
+        if session.last_used.elapsed() >= self.ttl {
+            session.messages.clear();
+        }
+        session.messages.extend(messages);
+        self.trim(&mut session.messages);
+        session.last_used = Instant::now();
+    }
+
+    /// Returns whether the session existed.
+    pub fn remove(&self, api_key: &str, id: &str) -> bool {
+        self.sessions
+            .lock()
+            .unwrap()
+            .remove(&(api_key.to_string(), id.to_string()))
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string().into(),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    fn contents(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|m| format!("{}: {}", m.role, m.content.text()))
+            .collect()
+    }
+
+    #[test]
+    fn two_turns_rebuild_the_history() {
+        let store = SessionStore::new(Duration::from_secs(60), 10);
+        store.append(
+            "key",
+            "s1",
+            vec![message("user", "Hi"), message("assistant", "Hello")],
+        );
+        store.append(
+            "key",
+            "s1",
+            vec![message("user", "And?"), message("assistant", "That's all")],
+        );
+        assert_eq!(
+            contents(&store.history("key", "s1")),
+            [
+                "user: Hi",
+                "assistant: Hello",
+                "user: And?",
+                "assistant: That's all"
+            ]
+        );
+    }
+
+    fn turn(question: &str) -> Vec<Message> {
+        vec![message("user", question), message("assistant", "ok")]
+    }
+
+    #[test]
+    fn oldest_turns_are_dropped_past_the_message_cap() {
+        let store =
+            SessionStore::new(Duration::from_secs(60), 10).with_history_limits(5, usize::MAX);
+        for question in ["1", "2", "3"] {
+            store.append("key", "s1", turn(question));
+        }
+        // Six messages: the first whole turn goes, not just its question
+        assert_eq!(
+            contents(&store.history("key", "s1")),
+            ["user: 2", "assistant: ok", "user: 3", "assistant: ok"]
+        );
+    }
+
+    #[test]
+    fn oldest_turns_are_dropped_past_the_byte_cap() {
+        let turn_bytes: usize = turn("1")
+            .iter()
+            .map(|m| serde_json::to_vec(m).unwrap().len())
+            .sum();
+        let store =
+            SessionStore::new(Duration::from_secs(60), 10).with_history_limits(100, turn_bytes * 2);
+        for question in ["1", "2", "3", "4"] {
+            store.append("key", "s1", turn(question));
+        }
+        assert_eq!(
+            contents(&store.history("key", "s1")),
+            ["user: 3", "assistant: ok", "user: 4", "assistant: ok"]
+        );
+
+        // A single turn over the cap leaves nothing to resend
+        store.append(
+            "key",
+            "s1",
+            vec![message("user", &"x".repeat(turn_bytes * 2))],
+        );
+        assert!(store.history("key", "s1").is_empty());
+    }
+
+    #[test]
+    fn sessions_belong_to_their_key() {
+        let store = SessionStore::new(Duration::from_secs(60), 10);
+        store.append("key-a", "s1", vec![message("user", "Hi")]);
+        assert!(store.history("key-b", "s1").is_empty());
+        assert!(!store.remove("key-b", "s1"));
+        assert!(store.remove("key-a", "s1"));
+        assert!(store.history("key-a", "s1").is_empty());
+    }
+
+    #[test]
+    fn expired_sessions_start_over() {
+        let store = SessionStore::new(Duration::from_millis(20), 10);
+        store.append("key", "s1", vec![message("user", "Hi")]);
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(store.history("key", "s1").is_empty());
+
+        store.append("key", "s2", vec![message("user", "Old")]);
+        std::thread::sleep(Duration::from_millis(40));
+        store.append("key", "s2", vec![message("user", "New")]);
+        assert_eq!(contents(&store.history("key", "s2")), ["user: New"]);
+    }
+
+    #[test]
+    fn least_recently_used_session_is_dropped_at_the_cap() {
+        let store = SessionStore::new(Duration::from_secs(60), 2);
+        store.append("key", "s1", vec![message("user", "1")]);
+        std::thread::sleep(Duration::from_millis(2));
+        store.append("key", "s2", vec![message("user", "2")]);
+        std::thread::sleep(Duration::from_millis(2));
+        store.append("key", "s3", vec![message("user", "3")]);
+        assert!(store.history("key", "s1").is_empty());
+        assert_eq!(store.history("key", "s2").len(), 1);
+        assert_eq!(store.history("key", "s3").len(), 1);
+    }
+}
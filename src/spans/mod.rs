@@ -0,0 +1,251 @@
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// One timed phase of a request, relative to when the request arrived
+#[derive(Debug, Serialize)]
+pub struct SpanPhase {
+    pub name: String,
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+/// Timing record for a single request, written as one JSON line
+#[derive(Debug, Default, Serialize)]
+pub struct SpanRecord {
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    pub started_at_ms: u64,
+    pub status: Option<u16>,
+    /// The request's end user, when the client sent one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub phases: Vec<SpanPhase>,
+    pub total_ms: u64,
+}
+
+/// Background writer that appends span records to a JSONL file.
+/// Requests only push onto a channel, so they never wait on disk I/O.
+#[derive(Clone)]
+pub struct SpanExporter {
+    tx: Sender<SpanRecord>,
+}
+
+impl SpanExporter {
+    pub fn spawn(path: &str) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (tx, rx) = mpsc::channel::<SpanRecord>();
+
+        std::thread::spawn(move || {
+            let mut writer = BufWriter::new(file);
+            for record in rx {
+                let result = serde_json::to_writer(&mut writer, &record)
+                    .map_err(std::io::Error::from)
+                    .and_then(|_| writer.write_all(b"\n"))
+                    .and_then(|_| writer.flush());
+                if let Err(e) = result {
+                    error!("Failed to write span record: {}", e);
+                }
+            }
+        });
+
+        Ok(Self { tx })
+    }
+}
+
+struct SpanInner {
+    start: Instant,
+    record: Mutex<SpanRecord>,
+    exporter: SpanExporter,
+}
+
+impl Drop for SpanInner {
+    // The span is complete once nothing holds it anymore: the request for
+    // regular responses, or the body stream for streaming ones.
+    fn drop(&mut self) {
+        let Ok(record) = self.record.get_mut() else {
+            return;
+        };
+        let mut record = std::mem::take(record);
+        record.total_ms = self.start.elapsed().as_millis() as u64;
+        let _ = self.exporter.tx.send(record);
+    }
+}
+
+/// Per-request span handle stored in request extensions. Cheap to clone; the
+/// record is exported when the last clone is dropped.
+#[derive(Clone)]
+pub struct SpanContext {
+    inner: Arc<SpanInner>,
+}
+
+impl SpanContext {
+    pub fn new(exporter: SpanExporter, request_id: String, method: &str, path: &str) -> Self {
+        let started_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        Self {
+            inner: Arc::new(SpanInner {
+                start: Instant::now(),
+                record: Mutex::new(SpanRecord {
+                    request_id,
+                    method: method.to_string(),
+                    path: path.to_string(),
+                    started_at_ms,
+                    ..Default::default()
+                }),
+                exporter,
+            }),
+        }
+    }
+
+    /// Record a phase that began at `started` and ends now
+    pub fn record(&self, name: &str, started: Instant) {
+        let start_ms = started
+            .saturating_duration_since(self.inner.start)
+            .as_millis() as u64;
+        let duration_ms = started.elapsed().as_millis() as u64;
+        if let Ok(mut record) = self.inner.record.lock() {
+            record.phases.push(SpanPhase {
+                name: name.to_string(),
+                start_ms,
+                duration_ms,
+            });
+        }
+    }
+
+    /// Record an instantaneous milestone (e.g. first streamed chunk)
+    pub fn mark(&self, name: &str) {
+        self.record(name, Instant::now());
+    }
+
+    pub fn set_user(&self, user: &str) {
+        if let Ok(mut record) = self.inner.record.lock() {
+            record.user = Some(user.to_string());
+        }
+    }
+
+    pub fn set_status(&self, status: u16) {
+        if let Ok(mut record) = self.inner.record.lock() {
+            record.status = Some(status);
+        }
+    }
+}
+
+/// Streaming milestones for a span: marks the first chunk and, when the body
+/// stream finishes or is dropped, the end of the stream.
+pub struct StreamSpan {
+    span: SpanContext,
+    seen_first_chunk: bool,
+}
+
+impl StreamSpan {
+    pub fn new(span: SpanContext) -> Self {
+        Self {
+            span,
+            seen_first_chunk: false,
+        }
+    }
+
+    pub fn on_chunk(&mut self) {
+        if !self.seen_first_chunk {
+            self.span.mark("first_chunk");
+            self.seen_first_chunk = true;
+        }
+    }
+}
+
+impl Drop for StreamSpan {
+    fn drop(&mut self) {
+        self.span.mark("stream_end");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// The lines written to `path`, once the writer thread has produced `count`.
+    fn read_lines(path: &std::path::Path, count: usize) -> Vec<serde_json::Value> {
+        for _ in 0..100 {
+            let raw = std::fs::read_to_string(path).unwrap_or_default();
+            let lines: Vec<_> = raw
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect();
+            if lines.len() >= count {
+                return lines;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("span file never got {} lines", count);
+    }
+
+    fn span_file() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("spans-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn finished_span_is_written_as_one_json_line() {
+        let path = span_file();
+        let exporter = SpanExporter::spawn(path.to_str().unwrap()).unwrap();
+        let span = SpanContext::new(
+            exporter,
+            String::from("req-1"),
+            "POST",
+            "/v1/chat/completions",
+        );
+        span.record("auth", Instant::now());
+        span.set_user("alice");
+        span.set_status(200);
+        drop(span);
+
+        let lines = read_lines(&path, 1);
+        let _ = std::fs::remove_file(&path);
+        let line = &lines[0];
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["method"], "POST");
+        assert_eq!(line["path"], "/v1/chat/completions");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["user"], "alice");
+        assert_eq!(line["phases"][0]["name"], "auth");
+        assert!(line["started_at_ms"].as_u64().unwrap() > 0);
+        assert!(line["total_ms"].is_u64());
+    }
+
+    #[test]
+    fn streamed_span_waits_for_the_stream_to_end() {
+        let path = span_file();
+        let exporter = SpanExporter::spawn(path.to_str().unwrap()).unwrap();
+        let span = SpanContext::new(
+            exporter,
+            String::from("req-2"),
+            "POST",
+            "/v1/chat/completions",
+        );
+        let mut stream_span = StreamSpan::new(span.clone());
+        drop(span);
+        stream_span.on_chunk();
+        stream_span.on_chunk();
+        drop(stream_span);
+
+        let lines = read_lines(&path, 1);
+        let _ = std::fs::remove_file(&path);
+        let phases: Vec<_> = lines[0]["phases"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(phases, ["first_chunk", "stream_end"]);
+        assert!(lines[0].get("user").is_none());
+    }
+}
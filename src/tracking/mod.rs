@@ -1,26 +1,102 @@
+mod monthly;
+pub mod sink;
+mod window;
+
+pub use monthly::{until_next_month, MonthlyUsage};
+pub use window::{RecentActivity, WindowStats};
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::time::SystemTime;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, Write};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Bumped whenever the saved layout changes in a way `#[serde(default)]` can't
+/// absorb; `migrate` upgrades older files step by step.
+pub const STATS_SCHEMA_VERSION: u32 = 1;
 
 /// Tracks request metrics across all API keys
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct RequestTracker {
+    /// Files from before versioning have none, which reads as 0
+    #[serde(default)]
+    version: u32,
     stats: HashMap<String, KeyStats>,
+    /// Serializes saves (callers only hold the tracker's read lock, so several can
+    /// race) and remembers a hash of the last contents written.
+    #[serde(skip)]
+    save_lock: Mutex<Option<u64>>,
 }
 
 /// Per-API-key statistics
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyStats {
+    #[serde(default)]
     pub request_count: u64,
+    #[serde(default)]
     pub error_count: u64,
+    #[serde(default)]
     pub total_latency_ms: u64,
+    #[serde(default)]
     pub total_prompt_tokens: u64,
+    #[serde(default)]
     pub total_completion_tokens: u64,
-    pub models_used: HashMap<String, u64>,
-    #[serde(with = "system_time_as_millis")]
+    /// Requests answered from the response cache (also included in `request_count`)
+    #[serde(default)]
+    pub cache_hits: u64,
+    /// Requests rejected by the rate limiter (not included in `request_count`)
+    #[serde(default)]
+    pub rate_limited_count: u64,
+    #[serde(default)]
+    pub models_used: HashMap<String, ModelStats>,
+    /// The same breakdown by the requests' `user`, for keys shared by many end users
+    #[serde(default)]
+    pub users: HashMap<String, ModelStats>,
+    #[serde(with = "system_time_as_millis", default = "unix_epoch")]
     pub last_request_timestamp: SystemTime,
+    /// Per-minute counts for the last 24h, for `stats_last`
+    #[serde(default)]
+    pub recent: RecentActivity,
+    /// Tokens used this calendar month, for monthly quotas
+    #[serde(default)]
+    pub monthly: MonthlyUsage,
+}
+
+/// Users tracked per key; requests from users past this many only count toward the key
+const MAX_USERS_PER_KEY: usize = 1000;
+
+fn unix_epoch() -> SystemTime {
+    SystemTime::UNIX_EPOCH
+}
+
+/// Per-model breakdown within a key's stats
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelStats {
+    pub request_count: u64,
+    pub error_count: u64,
+    pub total_latency_ms: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Totals across every key, see `RequestTracker::aggregate`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AggregateStats {
+    /// Keys with any recorded stats
+    pub active_keys: usize,
+    pub request_count: u64,
+    pub error_count: u64,
+    pub total_latency_ms: u64,
+    pub avg_latency_ms: f64,
+    pub total_prompt_tokens: u64,
+    pub total_completion_tokens: u64,
+    pub total_tokens: u64,
 }
 
 impl KeyStats {
@@ -31,34 +107,80 @@ impl KeyStats {
             total_latency_ms: 0,
             total_prompt_tokens: 0,
             total_completion_tokens: 0,
+            cache_hits: 0,
+            rate_limited_count: 0,
             models_used: HashMap::new(),
+            users: HashMap::new(),
             last_request_timestamp: SystemTime::now(),
+            recent: RecentActivity::default(),
+            monthly: MonthlyUsage::default(),
         }
     }
+
+    /// `None` once the key already tracks `MAX_USERS_PER_KEY` other users
+    fn user_stats(&mut self, user: &str) -> Option<&mut ModelStats> {
+        if !self.users.contains_key(user) && self.users.len() >= MAX_USERS_PER_KEY {
+            return None;
+        }
+        Some(self.users.entry(user.to_string()).or_default())
+    }
 }
 
 impl RequestTracker {
     pub fn new() -> Self {
         Self {
-            stats: HashMap::new(),
+            version: STATS_SCHEMA_VERSION,
+            ..Self::default()
         }
     }
 
+    /// Loads stats saved by any earlier version, upgrading them to the current schema.
     pub fn load_from_file(path: &str) -> std::io::Result<Self> {
         let file = File::open(path)?;
         let reader = BufReader::new(file);
-        let tracker = serde_json::from_reader(reader)?;
+        let value: Value = serde_json::from_reader(reader)?;
+        let mut tracker: Self = serde_json::from_value(migrate(value))?;
+        tracker.version = STATS_SCHEMA_VERSION;
         Ok(tracker)
     }
 
+    /// Write to `<path>.tmp` then rename over `path`, so a crash mid-write never
+    /// leaves a truncated stats file behind (rename is atomic on the same filesystem).
+    ///
+    /// Only one save runs at a time. A caller that waited behind another save
+    /// returns without writing if nothing changed in the meantime.
     pub fn save_to_file(&self, path: &str) -> std::io::Result<()> {
-        let file = File::create(path)?;
-        serde_json::to_writer_pretty(file, self)?;
+        let mut last_saved = self.save_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let contents = serde_json::to_vec_pretty(self)?;
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if *last_saved == Some(hash) {
+            return Ok(());
+        }
+
+        let tmp_path = format!("{}.tmp", path);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+
+        *last_saved = Some(hash);
         Ok(())
     }
 
-    /// Record a completed request (called by middleware after response)
-    pub fn record_request(&mut self, api_key: &str, latency_ms: u64, is_error: bool) {
+    /// Record a completed request (called by middleware after response).
+    /// `model` and `user` are set for requests a handler attributed to them.
+    pub fn record_request(
+        &mut self,
+        api_key: &str,
+        latency_ms: u64,
+        is_error: bool,
+        model: Option<&str>,
+        user: Option<&str>,
+    ) {
         let stats = self
             .stats
             .entry(api_key.to_string())
@@ -69,6 +191,24 @@ impl RequestTracker {
         if is_error {
             stats.error_count += 1;
         }
+        stats.recent.record_request(is_error);
+
+        if let Some(model) = model {
+            let model_stats = stats.models_used.entry(model.to_string()).or_default();
+            model_stats.request_count += 1;
+            model_stats.total_latency_ms += latency_ms;
+            if is_error {
+                model_stats.error_count += 1;
+            }
+        }
+
+        if let Some(user_stats) = user.and_then(|user| stats.user_stats(user)) {
+            user_stats.request_count += 1;
+            user_stats.total_latency_ms += latency_ms;
+            if is_error {
+                user_stats.error_count += 1;
+            }
+        }
     }
 
     /// Record token usage (called by handler after parsing LLM response)
@@ -78,6 +218,7 @@ impl RequestTracker {
         prompt_tokens: u64,
         completion_tokens: u64,
         model: &str,
+        user: Option<&str>,
     ) {
         let stats = self
             .stats
@@ -85,7 +226,39 @@ impl RequestTracker {
             .or_insert_with(KeyStats::new);
         stats.total_prompt_tokens += prompt_tokens;
         stats.total_completion_tokens += completion_tokens;
-        *stats.models_used.entry(model.to_string()).or_insert(0) += 1;
+        stats
+            .recent
+            .record_tokens(prompt_tokens + completion_tokens);
+        stats
+            .monthly
+            .record_tokens(prompt_tokens + completion_tokens);
+
+        let model_stats = stats.models_used.entry(model.to_string()).or_default();
+        model_stats.prompt_tokens += prompt_tokens;
+        model_stats.completion_tokens += completion_tokens;
+
+        if let Some(user_stats) = user.and_then(|user| stats.user_stats(user)) {
+            user_stats.prompt_tokens += prompt_tokens;
+            user_stats.completion_tokens += completion_tokens;
+        }
+    }
+
+    /// Record a request that was served from the response cache
+    pub fn record_cache_hit(&mut self, api_key: &str) {
+        let stats = self
+            .stats
+            .entry(api_key.to_string())
+            .or_insert_with(KeyStats::new);
+        stats.cache_hits += 1;
+    }
+
+    /// Record a request the rate limiter turned away
+    pub fn record_rate_limited(&mut self, api_key: &str) {
+        let stats = self
+            .stats
+            .entry(api_key.to_string())
+            .or_insert_with(KeyStats::new);
+        stats.rate_limited_count += 1;
     }
 
     /// Get stats for a specific API key
@@ -93,12 +266,104 @@ impl RequestTracker {
         self.stats.get(api_key)
     }
 
+    /// Requests, errors and tokens for `api_key` over the trailing `window` (up to 24h)
+    pub fn stats_last(&self, api_key: &str, window: Duration) -> WindowStats {
+        self.stats
+            .get(api_key)
+            .map(|stats| stats.recent.last(window))
+            .unwrap_or_default()
+    }
+
+    /// Tokens `api_key` has used this calendar month (UTC)
+    pub fn tokens_this_month(&self, api_key: &str) -> u64 {
+        self.stats
+            .get(api_key)
+            .map_or(0, |stats| stats.monthly.tokens_this_month())
+    }
+
+    /// Clear stats for every key
+    pub fn reset(&mut self) {
+        self.stats.clear();
+    }
+
+    /// Clear stats for a single key. Returns false if the key had no stats.
+    pub fn reset_key(&mut self, api_key: &str) -> bool {
+        self.stats.remove(api_key).is_some()
+    }
+
+    /// Sum every key's stats into gateway-wide totals
+    pub fn aggregate(&self) -> AggregateStats {
+        let mut totals = AggregateStats {
+            active_keys: self.stats.len(),
+            ..AggregateStats::default()
+        };
+        for stats in self.stats.values() {
+            totals.request_count += stats.request_count;
+            totals.error_count += stats.error_count;
+            totals.total_latency_ms += stats.total_latency_ms;
+            totals.total_prompt_tokens += stats.total_prompt_tokens;
+            totals.total_completion_tokens += stats.total_completion_tokens;
+        }
+        totals.total_tokens = totals.total_prompt_tokens + totals.total_completion_tokens;
+        if totals.request_count > 0 {
+            totals.avg_latency_ms = totals.total_latency_ms as f64 / totals.request_count as f64;
+        }
+        totals
+    }
+
     /// Get all stats (for /stats endpoint)
     pub fn get_all_stats(&self) -> &HashMap<String, KeyStats> {
         &self.stats
     }
 }
 
+/// Upgrade a saved tracker to `STATS_SCHEMA_VERSION`, one version at a time.
+/// Fields that were only added are filled in by `#[serde(default)]` instead.
+fn migrate(mut value: Value) -> Value {
+    let from = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+    let current = u64::from(STATS_SCHEMA_VERSION);
+    if from > current {
+        warn!(
+            "Stats file uses schema v{}, newer than v{}; loading the fields this version knows",
+            from, current
+        );
+        return value;
+    }
+
+    for version in from..current {
+        match version {
+            0 => migrate_v0_models_used(&mut value),
+            _ => unreachable!("no migration from stats schema v{}", version),
+        }
+        info!(
+            "Migrated stats from schema v{} to v{}",
+            version,
+            version + 1
+        );
+    }
+    value
+}
+
+/// v0 mapped each model in `models_used` to a plain request count.
+fn migrate_v0_models_used(value: &mut Value) {
+    let Some(stats) = value.get_mut("stats").and_then(Value::as_object_mut) else {
+        return;
+    };
+    for key_stats in stats.values_mut() {
+        let Some(models) = key_stats
+            .get_mut("models_used")
+            .and_then(Value::as_object_mut)
+        else {
+            continue;
+        };
+        for entry in models.values_mut() {
+            if let Some(request_count) = entry.as_u64() {
+                *entry = serde_json::json!({ "request_count": request_count });
+            }
+        }
+    }
+}
+
 /// Custom serializer/deserializer for SystemTime as milliseconds since UNIX epoch
 mod system_time_as_millis {
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -123,3 +388,84 @@ mod system_time_as_millis {
         Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_path() -> String {
+        let path = std::env::temp_dir().join(format!("stats-{}.json", uuid::Uuid::new_v4()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn concurrent_saves_leave_one_consistent_file() {
+        let path = stats_path();
+        let mut tracker = RequestTracker::new();
+        tracker.record_tokens("key", 5, 7, "llama3.2", None);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| tracker.save_to_file(&path).unwrap());
+            }
+        });
+
+        let saved = RequestTracker::load_from_file(&path).unwrap();
+        let stats = saved.get_stats("key").unwrap();
+        assert_eq!(
+            (stats.total_prompt_tokens, stats.total_completion_tokens),
+            (5, 7)
+        );
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unchanged_stats_are_not_written_again() {
+        let path = stats_path();
+        let mut tracker = RequestTracker::new();
+        tracker.record_tokens("key", 5, 7, "llama3.2", None);
+        tracker.save_to_file(&path).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        tracker.save_to_file(&path).unwrap();
+        assert!(!std::path::Path::new(&path).exists());
+
+        tracker.record_tokens("key", 1, 1, "llama3.2", None);
+        tracker.save_to_file(&path).unwrap();
+        assert!(std::path::Path::new(&path).exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn v0_file_keeps_its_history() {
+        let path = stats_path();
+        // No `version`, plain counts in `models_used`, none of the later fields
+        let v0 = serde_json::json!({
+            "stats": {
+                "key": {
+                    "request_count": 4,
+                    "error_count": 1,
+                    "total_latency_ms": 800,
+                    "models_used": { "llama3.2": 4 },
+                    "last_request_timestamp": 1_700_000_000_000u64
+                }
+            }
+        });
+        std::fs::write(&path, v0.to_string()).unwrap();
+
+        let tracker = RequestTracker::load_from_file(&path).unwrap();
+        assert_eq!(tracker.version, STATS_SCHEMA_VERSION);
+        let stats = tracker.get_stats("key").unwrap();
+        assert_eq!((stats.request_count, stats.error_count), (4, 1));
+        assert_eq!(stats.models_used["llama3.2"].request_count, 4);
+        assert_eq!((stats.cache_hits, stats.rate_limited_count), (0, 0));
+        assert!(stats.users.is_empty());
+
+        // Saved again, the file carries the current version
+        tracker.save_to_file(&path).unwrap();
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], STATS_SCHEMA_VERSION);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use time::{Date, Month, OffsetDateTime};
+
+/// Tokens a key has used in the current calendar month (UTC), checked against
+/// `KEY_MONTHLY_TOKEN_QUOTAS`. The count starts over the first time it's touched in
+/// a new month.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MonthlyUsage {
+    /// The `YYYY-MM` the count belongs to
+    month: String,
+    tokens: u64,
+}
+
+impl MonthlyUsage {
+    pub fn record_tokens(&mut self, tokens: u64) {
+        let month = current_month();
+        if self.month != month {
+            self.month = month;
+            self.tokens = 0;
+        }
+        self.tokens += tokens;
+    }
+
+    pub fn tokens_this_month(&self) -> u64 {
+        if self.month == current_month() {
+            self.tokens
+        } else {
+            0
+        }
+    }
+}
+
+fn current_month() -> String {
+    let today = OffsetDateTime::now_utc().date();
+    format!("{}-{:02}", today.year(), today.month() as u8)
+}
+
+/// Time left until monthly usage starts over, at midnight UTC on the 1st.
+pub fn until_next_month() -> Duration {
+    let now = OffsetDateTime::now_utc();
+    let (year, month) = match now.month() {
+        Month::December => (now.year() + 1, Month::January),
+        month => (now.year(), month.next()),
+    };
+    let next = Date::from_calendar_date(year, month, 1)
+        .expect("the 1st is a valid date")
+        .midnight()
+        .assume_utc();
+    (next - now).try_into().unwrap_or_default()
+}
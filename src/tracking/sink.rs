@@ -0,0 +1,200 @@
+use crate::middleware::auth::mask_key;
+use crate::tracking::RequestTracker;
+use serde_json::{Map, Value};
+use std::sync::RwLock;
+use std::time::Duration;
+use tracing::error;
+
+/// Pushes serialized stats to an external HTTP endpoint alongside the local file.
+pub struct StatsSink {
+    client: reqwest::Client,
+    url: String,
+    mask_keys: bool,
+}
+
+impl StatsSink {
+    pub fn new(url: String, mask_keys: bool, timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Self {
+            client,
+            url,
+            mask_keys,
+        }
+    }
+
+    /// Serialize the tracker for the sink, masking API keys if configured.
+    /// Masked keys that collide get a `#n` suffix so no entry is overwritten.
+    pub fn payload(&self, tracker: &RequestTracker) -> serde_json::Result<Value> {
+        let mut value = serde_json::to_value(tracker)?;
+        if !self.mask_keys {
+            return Ok(value);
+        }
+
+        if let Some(Value::Object(stats)) = value.get_mut("stats") {
+            let mut masked = Map::new();
+            for (key, entry) in std::mem::take(stats) {
+                let base = mask_key(&key);
+                let mut candidate = base.clone();
+                let mut n = 2;
+                while masked.contains_key(&candidate) {
+                    candidate = format!("{}#{}", base, n);
+                    n += 1;
+                }
+                masked.insert(candidate, entry);
+            }
+            *stats = masked;
+        }
+
+        Ok(value)
+    }
+
+    pub async fn push(&self, payload: &Value) -> Result<(), String> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(payload)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(format!("sink returned {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Saves the tracker to `path`, then pushes the same snapshot to `sink` if there is
+/// one. The local save comes first: a failing sink must never cost us the file copy.
+pub async fn flush_stats(tracker: &RwLock<RequestTracker>, path: &str, sink: Option<&StatsSink>) {
+    let payload = {
+        let tracker = tracker.read().unwrap();
+        if let Err(e) = tracker.save_to_file(path) {
+            error!("Periodic stats flush failed: {}", e);
+        }
+        sink.map(|sink| sink.payload(&tracker))
+    };
+
+    if let (Some(sink), Some(payload)) = (sink, payload) {
+        match payload {
+            Ok(payload) => {
+                if let Err(e) = sink.push(&payload).await {
+                    error!("Failed to push stats to sink: {}", e);
+                }
+            }
+            Err(e) => error!("Failed to serialize stats for sink: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// A one-request HTTP server answering `status`; the request body comes out of
+    /// the receiver.
+    fn mock_sink(status: u16) -> (String, mpsc::Receiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stats", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let response = format!("HTTP/1.1 {} OK\r\ncontent-length: 0\r\n\r\n", status);
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            tx.send(serde_json::from_slice(&body).unwrap()).unwrap();
+        });
+        (url, rx)
+    }
+
+    fn tracker() -> RwLock<RequestTracker> {
+        let mut tracker = RequestTracker::new();
+        tracker.record_tokens("sk-live-1234567890", 5, 7, "llama3.2", None);
+        RwLock::new(tracker)
+    }
+
+    fn stats_path() -> String {
+        let path = std::env::temp_dir().join(format!("stats-{}.json", uuid::Uuid::new_v4()));
+        path.to_str().unwrap().to_string()
+    }
+
+    fn saved_keys(path: &str) -> Vec<String> {
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let _ = std::fs::remove_file(path);
+        saved["stats"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn flush_writes_the_file_and_posts_masked_stats() {
+        let (url, received) = mock_sink(200);
+        let sink = StatsSink::new(url, true, Duration::from_secs(5));
+        let path = stats_path();
+        flush_stats(&tracker(), &path, Some(&sink)).await;
+
+        assert_eq!(saved_keys(&path), ["sk-live-1234567890"]);
+        let posted = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        let posted_keys: Vec<_> = posted["stats"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(posted_keys, ["sk-l***7890"]);
+    }
+
+    #[tokio::test]
+    async fn failing_sink_still_leaves_the_file() {
+        let (url, received) = mock_sink(500);
+        let sink = StatsSink::new(url, false, Duration::from_secs(5));
+        let path = stats_path();
+        flush_stats(&tracker(), &path, Some(&sink)).await;
+
+        let posted = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(posted["stats"].get("sk-live-1234567890").is_some());
+        assert_eq!(saved_keys(&path), ["sk-live-1234567890"]);
+    }
+
+    #[test]
+    fn colliding_masked_keys_are_kept_apart() {
+        let mut tracker = RequestTracker::new();
+        tracker.record_tokens("sk-a-same-1234", 1, 1, "m", None);
+        tracker.record_tokens("sk-a-diff-1234", 1, 1, "m", None);
+        let sink = StatsSink::new(String::from("http://unused"), true, Duration::from_secs(1));
+        let payload = sink.payload(&tracker).unwrap();
+        let mut keys: Vec<_> = payload["stats"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["sk-a***1234", "sk-a***1234#2"]);
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+/// How far back per-minute activity is kept
+const RETENTION_MINUTES: u64 = 24 * 60;
+
+/// Totals over a trailing window, see `RequestTracker::stats_last`
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct WindowStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub tokens: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct MinuteBucket {
+    /// Minutes since the UNIX epoch
+    minute: u64,
+    requests: u64,
+    errors: u64,
+    tokens: u64,
+}
+
+/// A key's activity over the last 24 hours in per-minute buckets, oldest first.
+/// Only minutes with activity get a bucket, and buckets older than a day are
+/// dropped as new ones are added, so idle keys cost nothing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RecentActivity {
+    buckets: VecDeque<MinuteBucket>,
+}
+
+impl RecentActivity {
+    pub fn record_request(&mut self, is_error: bool) {
+        let bucket = self.current_bucket();
+        bucket.requests += 1;
+        if is_error {
+            bucket.errors += 1;
+        }
+    }
+
+    pub fn record_tokens(&mut self, tokens: u64) {
+        self.current_bucket().tokens += tokens;
+    }
+
+    /// Totals for the trailing `window` (rounded up to whole minutes, capped at 24h),
+    /// including the current minute.
+    pub fn last(&self, window: Duration) -> WindowStats {
+        let minutes = window.as_secs().div_ceil(60).clamp(1, RETENTION_MINUTES);
+        let oldest = current_minute().saturating_sub(minutes - 1);
+        let mut totals = WindowStats::default();
+        for bucket in self.buckets.iter().rev() {
+            if bucket.minute < oldest {
+                break;
+            }
+            totals.requests += bucket.requests;
+            totals.errors += bucket.errors;
+            totals.tokens += bucket.tokens;
+        }
+        totals
+    }
+
+    fn current_bucket(&mut self) -> &mut MinuteBucket {
+        let minute = current_minute();
+        let expired_before = minute.saturating_sub(RETENTION_MINUTES - 1);
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.minute < expired_before)
+        {
+            self.buckets.pop_front();
+        }
+
+        // If the clock went backwards, keep adding to the newest bucket
+        let needs_bucket = match self.buckets.back() {
+            Some(newest) => newest.minute < minute,
+            None => true,
+        };
+        if needs_bucket {
+            self.buckets.push_back(MinuteBucket {
+                minute,
+                ..MinuteBucket::default()
+            });
+        }
+        self.buckets.back_mut().expect("bucket was just ensured")
+    }
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
@@ -0,0 +1,161 @@
+use crate::models::{ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, Message};
+use std::fmt::Debug;
+
+/// Pre-processing applied to chat requests before they reach the provider, so it
+/// covers streaming and non-streaming calls to every upstream alike.
+pub trait RequestTransformer: Debug + Send + Sync {
+    fn transform(&self, req: &mut ChatCompletionRequest);
+}
+
+/// Puts a house system prompt (`SYSTEM_PROMPT`) first in every conversation that
+/// doesn't already have a system message.
+#[derive(Debug)]
+pub struct InjectSystemPrompt {
+    prompt: String,
+}
+
+impl InjectSystemPrompt {
+    pub fn new(prompt: &str) -> Self {
+        Self {
+            prompt: prompt.to_string(),
+        }
+    }
+}
+
+impl RequestTransformer for InjectSystemPrompt {
+    fn transform(&self, req: &mut ChatCompletionRequest) {
+        if req.messages.iter().any(|m| m.role == "system") {
+            return;
+        }
+        req.messages.insert(
+            0,
+            Message {
+                role: String::from("system"),
+                content: self.prompt.clone().into(),
+                tool_calls: None,
+                tool_call_id: None,
+            },
+        );
+    }
+}
+
+/// Post-processing applied to completions before they are returned to the client.
+pub trait ResponseTransformer: Debug + Send + Sync {
+    fn transform(&self, resp: &mut ChatCompletionResponse);
+
+    /// Streaming counterpart, called once per parsed chunk. Defaults to a no-op.
+    fn transform_chunk(&self, _chunk: &mut ChatCompletionChunk) {}
+}
+
+/// Appends a fixed disclaimer to every choice (`APPEND_DISCLAIMER`).
+/// Streams get it as extra content on the chunk that carries the finish reason.
+#[derive(Debug)]
+pub struct AppendDisclaimer {
+    text: String,
+}
+
+impl AppendDisclaimer {
+    pub fn new(disclaimer: &str) -> Self {
+        Self {
+            text: format!("\n\n{}", disclaimer),
+        }
+    }
+}
+
+impl ResponseTransformer for AppendDisclaimer {
+    fn transform(&self, resp: &mut ChatCompletionResponse) {
+        for choice in &mut resp.choices {
+            choice.message.content.push_str(&self.text);
+        }
+    }
+
+    fn transform_chunk(&self, chunk: &mut ChatCompletionChunk) {
+        for choice in &mut chunk.choices {
+            if choice.finish_reason.is_some() {
+                choice.delta.content.push_str(&self.text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Choice, ChunkChoice, Delta};
+
+    fn response(content: &str) -> ChatCompletionResponse {
+        ChatCompletionResponse {
+            id: String::from("chatcmpl-test"),
+            object: String::from("chat.completion"),
+            created: 0,
+            model: String::from("llama3.2"),
+            choices: vec![Choice {
+                index: 0,
+                message: Message {
+                    role: String::from("assistant"),
+                    content: content.to_string().into(),
+                    tool_calls: None,
+                    tool_call_id: None,
+                },
+                finish_reason: String::from("stop"),
+            }],
+            usage: None,
+            cached: false,
+        }
+    }
+
+    fn chunk(content: &str, finish_reason: Option<&str>) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: String::from("chatcmpl-test"),
+            object: String::from("chat.completion.chunk"),
+            created: 0,
+            model: String::from("llama3.2"),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta: Delta {
+                    role: None,
+                    content: content.to_string(),
+                    tool_calls: None,
+                },
+                finish_reason: finish_reason.map(String::from),
+            }],
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn disclaimer_is_appended_to_every_choice() {
+        let mut resp = response("Hello");
+        resp.choices.push(resp.choices[0].clone());
+        AppendDisclaimer::new("AI generated").transform(&mut resp);
+        for choice in &resp.choices {
+            assert_eq!(choice.message.content.text(), "Hello\n\nAI generated");
+        }
+    }
+
+    #[test]
+    fn disclaimer_goes_on_the_final_stream_chunk_only() {
+        let disclaimer = AppendDisclaimer::new("AI generated");
+        let mut middle = chunk("Hello", None);
+        let mut last = chunk("", Some("stop"));
+        disclaimer.transform_chunk(&mut middle);
+        disclaimer.transform_chunk(&mut last);
+        assert_eq!(middle.choices[0].delta.content, "Hello");
+        assert_eq!(last.choices[0].delta.content, "\n\nAI generated");
+    }
+
+    #[test]
+    fn system_prompt_is_only_added_when_missing() {
+        let mut req: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "llama3.2",
+            "messages": [{"role": "user", "content": "hi"}]
+        }))
+        .unwrap();
+        let inject = InjectSystemPrompt::new("Be brief");
+        inject.transform(&mut req);
+        inject.transform(&mut req);
+        let roles: Vec<&str> = req.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user"]);
+        assert_eq!(req.messages[0].content.text(), "Be brief");
+    }
+}